@@ -6,18 +6,25 @@ fn main() {
     // set by cargo's artifact dependency feature, see
     // https://doc.rust-lang.org/nightly/cargo/reference/unstable.html#artifact-dependencies
     let kernel = PathBuf::from(std::env::var_os("CARGO_BIN_FILE_KERNEL_kernel").unwrap());
+    // a statically linked user program, which the kernel starts as the init process
+    println!("cargo:rerun-if-env-changed=ROST_INIT");
+    let init = std::env::var_os("ROST_INIT").map(PathBuf::from);
 
     // create an UEFI disk image (optional)
     let uefi_path = out_dir.join("uefi.img");
-    bootloader::UefiBoot::new(&kernel)
-        .create_disk_image(&uefi_path)
-        .unwrap();
+    let mut uefi = bootloader::UefiBoot::new(&kernel);
+    if let Some(init) = &init {
+        uefi.set_ramdisk(init);
+    }
+    uefi.create_disk_image(&uefi_path).unwrap();
 
     // create a BIOS disk image
     let bios_path = out_dir.join("bios.img");
-    bootloader::BiosBoot::new(&kernel)
-        .create_disk_image(&bios_path)
-        .unwrap();
+    let mut bios = bootloader::BiosBoot::new(&kernel);
+    if let Some(init) = &init {
+        bios.set_ramdisk(init);
+    }
+    bios.create_disk_image(&bios_path).unwrap();
 
     // pass the disk image paths as env variables to the
     println!("cargo:rustc-env=UEFI_PATH={}", uefi_path.display());
@@ -14,7 +14,7 @@ use internal_utils::{
     gpu_device::{
         BLACK, GPU_DEVICE, GPUDevice, GPUDeviceCapabilityMut, GPUDeviceCapabilityRequest,
     },
-    kernel_information::KernelInformation,
+    kernel_information::{KernelInformation, allocator::register_low_memory_hook},
     logln,
 };
 use tinytga::RawTga;
@@ -34,6 +34,9 @@ pub fn init_vga(kernel_info: KernelInformation) {
     }
     let mut vga_device = VGADeviceFactory::from_kernel_info(kernel_info);
     logln!("VGA device created");
+    if !register_low_memory_hook(pixel_buffer::on_low_memory) {
+        logln!("Couldn't register the VGA low memory hook");
+    }
     show_logo(&mut vga_device);
 
     GPU_DEVICE.call_once(|| Box::new(vga_device));
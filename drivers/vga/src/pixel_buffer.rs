@@ -1,13 +1,27 @@
-use core::alloc::Layout;
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use alloc::alloc::alloc;
+use alloc::alloc::{alloc, dealloc};
 
 use alloc::slice;
 use internal_utils::{
     gpu_device::VGAColor,
     kernel_information::kernel_frame_buffer::{KernelFrameBuffer, PixelFormat},
+    logln,
 };
 
+/// Set when the kernel heap runs low, so the pixel buffers give up their back buffers.
+static LOW_MEMORY: AtomicBool = AtomicBool::new(false);
+
+/// The low memory hook of the driver.
+///
+/// Freeing the memory has to wait for the next flush, as the hook can run in the middle of an allocation.
+pub(crate) fn on_low_memory() {
+    LOW_MEMORY.store(true, Ordering::Relaxed);
+}
+
 pub trait PixelBuffer: Send {
     /// Places a color on the following buffer index
     fn put_pixel(&mut self, index: usize, color: VGAColor<u8>);
@@ -17,7 +31,9 @@ pub trait PixelBuffer: Send {
 
 pub(crate) struct BasePixelBuffer<const P: PixelFormat, const N: usize> {
     frame_pointer: &'static mut [u8],
-    back_buffer: &'static mut [u8],
+    /// What's currently in the frame buffer, so only changed pixels are written to it.
+    /// It's freed when memory runs low, and every pixel gets written from then on.
+    back_buffer: Option<&'static mut [u8]>,
     change_buffer: &'static mut [u8],
 }
 
@@ -43,16 +59,23 @@ impl<const P: PixelFormat, const N: usize> BasePixelBuffer<P, N> {
             );
             Self {
                 frame_pointer: slice::from_raw_parts_mut(buffer.buffer.get(), len),
-                back_buffer,
+                back_buffer: Some(back_buffer),
                 change_buffer,
             }
         }
     }
 
     fn inner_flush(&mut self) {
+        if LOW_MEMORY.load(Ordering::Relaxed) {
+            self.free_back_buffer();
+        }
+        let Some(back_buffer) = self.back_buffer.as_mut() else {
+            self.frame_pointer.copy_from_slice(self.change_buffer);
+            return;
+        };
         let len = self.change_buffer.len() >> 3;
         let frame_ptr = self.frame_pointer.as_mut_ptr() as *mut u64;
-        let buffer_ptr = self.back_buffer.as_mut_ptr() as *mut u64;
+        let buffer_ptr = back_buffer.as_mut_ptr() as *mut u64;
         let change_ptr = self.change_buffer.as_mut_ptr() as *mut u64;
         for i in 0..len {
             unsafe {
@@ -62,7 +85,15 @@ impl<const P: PixelFormat, const N: usize> BasePixelBuffer<P, N> {
                 }
             }
         }
-        self.back_buffer.copy_from_slice(self.change_buffer);
+        back_buffer.copy_from_slice(self.change_buffer);
+    }
+
+    fn free_back_buffer(&mut self) {
+        if let Some(back_buffer) = self.back_buffer.take() {
+            let layout = Layout::from_size_align(back_buffer.len(), 8).unwrap();
+            unsafe { dealloc(back_buffer.as_mut_ptr(), layout) };
+            logln!("Memory is low, the VGA back buffer has been freed");
+        }
     }
 }
 
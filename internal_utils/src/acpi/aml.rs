@@ -0,0 +1,52 @@
+use super::sdt::{Sdt, SdtHeader};
+
+// The AML opcodes needed to read the sleep state packages
+const NAME_OP: u8 = 0x08;
+const PACKAGE_OP: u8 = 0x12;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const BYTE_PREFIX: u8 = 0x0A;
+const ROOT_CHAR: u8 = b'\\';
+
+/// Finds the SLP_TYPa and SLP_TYPb values of the sleep state in the AML code of the table.
+///
+/// This isn't an AML interpreter - it only recognizes the sleep state declared as
+/// `Name (_Sx_, Package () { a, b, ... })`, which is how the firmwares declare them.
+pub(super) fn find_sleep_types(table: Sdt, name: &[u8; 4]) -> Option<(u8, u8)> {
+    let length = table.header().length as u64;
+    let code_start = size_of::<SdtHeader>() as u64;
+    let read = |offset: u64| (offset < length).then(|| table.read::<u8>(offset));
+
+    let name_offset = (code_start + 2..length.saturating_sub(3)).find(|&offset| {
+        let declared = read(offset - 1) == Some(NAME_OP)
+            || (read(offset - 1) == Some(ROOT_CHAR) && read(offset - 2) == Some(NAME_OP));
+        declared && table.read::<[u8; 4]>(offset) == *name
+    })?;
+
+    let mut offset = name_offset + 4;
+    if read(offset)? != PACKAGE_OP {
+        return None;
+    }
+    // The top two bits of the package length tell how many more bytes it takes
+    let package_length = read(offset + 1)?;
+    offset += 2 + (package_length >> 6) as u64;
+    // Skipping the number of elements
+    offset += 1;
+
+    let mut read_integer = || {
+        let value = match read(offset)? {
+            ZERO_OP => 0,
+            ONE_OP => 1,
+            BYTE_PREFIX => {
+                offset += 1;
+                read(offset)?
+            }
+            _ => return None,
+        };
+        offset += 1;
+        Some(value)
+    };
+    let sleep_type_a = read_integer()?;
+    let sleep_type_b = read_integer()?;
+    Some((sleep_type_a, sleep_type_b))
+}
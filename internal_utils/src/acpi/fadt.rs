@@ -0,0 +1,146 @@
+use x86_64::PhysAddr;
+
+use super::{generic_address::GenericAddress, sdt::Sdt};
+
+/// The Fixed ACPI Description Table, describing the power management hardware.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    table: Sdt,
+}
+
+/// The fields of the FADT the kernel uses, up to the extended PM1 control blocks.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct FadtFields {
+    firmware_control: u32,
+    dsdt: u32,
+    reserved: u8,
+    preferred_power_profile: u8,
+    sci_interrupt: u16,
+    smi_command_port: u32,
+    acpi_enable: u8,
+    acpi_disable: u8,
+    s4bios_request: u8,
+    pstate_control: u8,
+    pm1a_event_block: u32,
+    pm1b_event_block: u32,
+    pm1a_control_block: u32,
+    pm1b_control_block: u32,
+    pm2_control_block: u32,
+    pm_timer_block: u32,
+    gpe0_block: u32,
+    gpe1_block: u32,
+    pm1_event_length: u8,
+    pm1_control_length: u8,
+    pm2_control_length: u8,
+    pm_timer_length: u8,
+    gpe0_length: u8,
+    gpe1_length: u8,
+    gpe1_base: u8,
+    c_state_control: u8,
+    worst_c2_latency: u16,
+    worst_c3_latency: u16,
+    flush_size: u16,
+    flush_stride: u16,
+    duty_offset: u8,
+    duty_width: u8,
+    day_alarm: u8,
+    month_alarm: u8,
+    century: u8,
+    boot_architecture_flags: u16,
+    reserved2: u8,
+    flags: u32,
+    reset_register: GenericAddress,
+    reset_value: u8,
+    arm_boot_architecture_flags: u16,
+    minor_version: u8,
+    x_firmware_control: u64,
+    x_dsdt: u64,
+    x_pm1a_event_block: GenericAddress,
+    x_pm1b_event_block: GenericAddress,
+    x_pm1a_control_block: GenericAddress,
+    x_pm1b_control_block: GenericAddress,
+}
+
+/// Set in the FADT flags when the reset register can be used.
+const RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+/// Set in the boot architecture flags when the system has a PS/2 keyboard controller.
+const HAS_8042: u16 = 1 << 1;
+
+impl Fadt {
+    pub(super) fn new(table: Sdt) -> Fadt {
+        Fadt { table }
+    }
+
+    fn fields(&self) -> FadtFields {
+        self.table.read_fields()
+    }
+
+    /// Returns the Differentiated System Description Table, holding the AML code of the system.
+    pub fn dsdt(&self) -> PhysAddr {
+        let fields = self.fields();
+        match fields.x_dsdt {
+            0 => PhysAddr::new(fields.dsdt as u64),
+            x_dsdt => PhysAddr::new(x_dsdt),
+        }
+    }
+
+    /// Returns the interrupt the ACPI events are signalled with.
+    pub fn sci_interrupt(&self) -> u16 {
+        self.fields().sci_interrupt
+    }
+
+    /// Returns the port and the value that switch the system from legacy to ACPI mode,
+    /// or `None` if it's always in ACPI mode.
+    pub fn acpi_enable(&self) -> Option<(u16, u8)> {
+        let fields = self.fields();
+        (fields.smi_command_port != 0 && fields.acpi_enable != 0)
+            .then_some((fields.smi_command_port as u16, fields.acpi_enable))
+    }
+
+    /// Returns the PM1a control block, which every system should have.
+    pub fn pm1a_control_block(&self) -> Option<GenericAddress> {
+        let fields = self.fields();
+        Self::control_block(fields.x_pm1a_control_block, fields.pm1a_control_block)
+    }
+
+    /// Returns the PM1b control block, if the system has one.
+    pub fn pm1b_control_block(&self) -> Option<GenericAddress> {
+        let fields = self.fields();
+        Self::control_block(fields.x_pm1b_control_block, fields.pm1b_control_block)
+    }
+
+    /// Prefers the extended block, falling back to the legacy IO port.
+    fn control_block(extended: GenericAddress, legacy_port: u32) -> Option<GenericAddress> {
+        if extended.is_present() {
+            return Some(extended);
+        }
+        (legacy_port != 0).then_some(GenericAddress {
+            address_space: 1,
+            bit_width: 16,
+            bit_offset: 0,
+            access_size: 2,
+            address: legacy_port as u64,
+        })
+    }
+
+    /// Returns the register resetting the system and the value to write to it, if the system supports it.
+    pub fn reset_register(&self) -> Option<(GenericAddress, u8)> {
+        let fields = self.fields();
+        (fields.flags & RESET_REGISTER_SUPPORTED != 0 && fields.reset_register.is_present())
+            .then_some((fields.reset_register, fields.reset_value))
+    }
+
+    /// Returns the index of the century in the RTC, if it has one.
+    pub fn century_register(&self) -> Option<u8> {
+        let century = self.fields().century;
+        (century != 0).then_some(century)
+    }
+
+    /// Whether the system has a PS/2 keyboard controller.
+    ///
+    /// Only meaningful from revision 2 on - the older tables always say it doesn't.
+    pub fn has_keyboard_controller(&self) -> bool {
+        self.fields().boot_architecture_flags & HAS_8042 != 0
+    }
+}
@@ -0,0 +1,36 @@
+/// Where a register lives, as described by the ACPI Generic Address Structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+/// The address spaces a `GenericAddress` can point into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfiguration,
+    /// An address space the kernel doesn't access.
+    Other(u8),
+}
+
+impl GenericAddress {
+    pub fn address_space(&self) -> AddressSpace {
+        match self.address_space {
+            0 => AddressSpace::SystemMemory,
+            1 => AddressSpace::SystemIo,
+            2 => AddressSpace::PciConfiguration,
+            other => AddressSpace::Other(other),
+        }
+    }
+
+    /// Whether the register exists - the firmware leaves the ones it doesn't have zeroed.
+    pub fn is_present(&self) -> bool {
+        self.address != 0
+    }
+}
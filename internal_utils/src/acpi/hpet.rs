@@ -0,0 +1,63 @@
+use x86_64::PhysAddr;
+
+use super::{generic_address::GenericAddress, sdt::Sdt};
+
+/// The HPET Description Table, describing a High Precision Event Timer.
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    table: Sdt,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct HpetFields {
+    event_timer_block_id: u32,
+    base_address: GenericAddress,
+    hpet_number: u8,
+    minimum_tick: u16,
+    page_protection: u8,
+}
+
+impl Hpet {
+    pub(super) fn new(table: Sdt) -> Hpet {
+        Hpet { table }
+    }
+
+    fn fields(&self) -> HpetFields {
+        self.table.read_fields()
+    }
+
+    /// Returns the physical address of the timer's registers.
+    pub fn base_address(&self) -> PhysAddr {
+        PhysAddr::new(self.fields().base_address.address)
+    }
+
+    /// Returns the number of the timer, when the system has more than one.
+    pub fn hpet_number(&self) -> u8 {
+        self.fields().hpet_number
+    }
+
+    /// Returns the smallest number of ticks the periodic mode can be set to without losing interrupts.
+    pub fn minimum_tick(&self) -> u16 {
+        self.fields().minimum_tick
+    }
+
+    /// Returns the number of comparators of the first timer block.
+    pub fn comparators(&self) -> u8 {
+        ((self.fields().event_timer_block_id >> 8) & 0x1F) as u8 + 1
+    }
+
+    /// Whether the counter is 64 bit wide.
+    pub fn is_64_bit(&self) -> bool {
+        self.fields().event_timer_block_id & (1 << 13) != 0
+    }
+
+    /// Whether the timer can replace the PIT and the RTC interrupts.
+    pub fn supports_legacy_replacement(&self) -> bool {
+        self.fields().event_timer_block_id & (1 << 15) != 0
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        (self.fields().event_timer_block_id >> 16) as u16
+    }
+}
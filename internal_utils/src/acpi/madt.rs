@@ -0,0 +1,135 @@
+use x86_64::PhysAddr;
+
+use super::sdt::{Sdt, SdtHeader};
+
+/// The Multiple APIC Description Table, listing the processors' local APICs and the IO-APICs.
+#[derive(Debug, Clone, Copy)]
+pub struct Madt {
+    table: Sdt,
+}
+
+/// The fields following the header of the MADT, before its entries.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct MadtFields {
+    local_apic_address: u32,
+    flags: u32,
+}
+
+/// Set in the MADT flags when the system also has the legacy 8259 PICs.
+const PCAT_COMPAT: u32 = 1;
+
+/// How an interrupt input is signalled, from the MPS INTI flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptFlags(u16);
+
+impl InterruptFlags {
+    /// Whether the input is active low - ISA interrupts conforming to the bus are active high.
+    pub fn active_low(self) -> bool {
+        self.0 & 0b11 == 0b11
+    }
+
+    /// Whether the input is level triggered - ISA interrupts conforming to the bus are edge triggered.
+    pub fn level_triggered(self) -> bool {
+        (self.0 >> 2) & 0b11 == 0b11
+    }
+}
+
+/// An entry of the MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MadtEntry {
+    /// A processor and its local APIC.
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        /// Whether the processor can be used - it might only be able to be brought online otherwise.
+        enabled: bool,
+    },
+    IoApic {
+        id: u8,
+        address: PhysAddr,
+        /// The first global system interrupt the IO-APIC handles.
+        interrupt_base: u32,
+    },
+    /// An ISA interrupt that isn't identity mapped to a global system interrupt.
+    InterruptSourceOverride {
+        irq: u8,
+        global_interrupt: u32,
+        flags: InterruptFlags,
+    },
+    /// The 64 bit address of the local APICs, replacing the one in the table.
+    LocalApicAddressOverride { address: PhysAddr },
+    /// An entry not used by the kernel.
+    Other { entry_type: u8 },
+}
+
+impl Madt {
+    pub(super) fn new(table: Sdt) -> Madt {
+        Madt { table }
+    }
+
+    fn read<T: Copy>(&self, offset: u64) -> T {
+        self.table.read(offset)
+    }
+
+    fn fields(&self) -> MadtFields {
+        self.table.read_fields()
+    }
+
+    /// Returns the physical address of the local APICs.
+    pub fn local_apic_address(&self) -> PhysAddr {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address),
+                _ => None,
+            })
+            .unwrap_or(PhysAddr::new(self.fields().local_apic_address as u64))
+    }
+
+    /// Whether the system also has the legacy 8259 PICs, which have to be masked to use the APICs.
+    pub fn has_legacy_pics(&self) -> bool {
+        self.fields().flags & PCAT_COMPAT != 0
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> + '_ {
+        let length = self.table.header().length as u64;
+        let mut offset = (size_of::<SdtHeader>() + size_of::<MadtFields>()) as u64;
+        core::iter::from_fn(move || {
+            if offset + 2 > length {
+                return None;
+            }
+            let entry_type = self.read::<u8>(offset);
+            let entry_length = self.read::<u8>(offset + 1) as u64;
+            if entry_length < 2 || offset + entry_length > length {
+                return None;
+            }
+            let entry = self.read_entry(entry_type, offset);
+            offset += entry_length;
+            Some(entry)
+        })
+    }
+
+    fn read_entry(&self, entry_type: u8, offset: u64) -> MadtEntry {
+        match entry_type {
+            0 => MadtEntry::LocalApic {
+                processor_id: self.read(offset + 2),
+                apic_id: self.read(offset + 3),
+                enabled: self.read::<u32>(offset + 4) & 1 != 0,
+            },
+            1 => MadtEntry::IoApic {
+                id: self.read(offset + 2),
+                address: PhysAddr::new(self.read::<u32>(offset + 4) as u64),
+                interrupt_base: self.read(offset + 8),
+            },
+            2 => MadtEntry::InterruptSourceOverride {
+                irq: self.read(offset + 3),
+                global_interrupt: self.read(offset + 4),
+                flags: InterruptFlags(self.read(offset + 8)),
+            },
+            5 => MadtEntry::LocalApicAddressOverride {
+                address: PhysAddr::new(self.read(offset + 4)),
+            },
+            entry_type => MadtEntry::Other { entry_type },
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use x86_64::PhysAddr;
+
+use super::sdt::{Sdt, SdtHeader};
+
+/// The PCI Express memory mapped configuration table.
+#[derive(Debug, Clone, Copy)]
+pub struct Mcfg {
+    table: Sdt,
+}
+
+/// The reserved bytes between the header of the MCFG and its entries.
+const ENTRIES_OFFSET: u64 = size_of::<SdtHeader>() as u64 + 8;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct McfgEntry {
+    base_address: u64,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+    reserved: u32,
+}
+
+/// The configuration space of the buses of a PCI segment group, mapped in physical memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciConfigRegion {
+    /// The address of the configuration space of the bus 0 - even if the region starts at another bus.
+    pub base_address: PhysAddr,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl PciConfigRegion {
+    /// Returns the address of the configuration space of the function,
+    /// or `None` if its bus isn't in the region.
+    pub fn function_address(&self, bus: u8, device: u8, function: u8) -> Option<PhysAddr> {
+        if !(self.start_bus..=self.end_bus).contains(&bus) || device >= 32 || function >= 8 {
+            return None;
+        }
+        let offset = (bus as u64) << 20 | (device as u64) << 15 | (function as u64) << 12;
+        Some(self.base_address + offset)
+    }
+}
+
+impl Mcfg {
+    pub(super) fn new(table: Sdt) -> Mcfg {
+        Mcfg { table }
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = PciConfigRegion> + '_ {
+        let length = self.table.header().length as u64;
+        let entries = length.saturating_sub(ENTRIES_OFFSET) / size_of::<McfgEntry>() as u64;
+        (0..entries).map(move |index| {
+            let entry = self
+                .table
+                .read::<McfgEntry>(ENTRIES_OFFSET + index * size_of::<McfgEntry>() as u64);
+            PciConfigRegion {
+                base_address: PhysAddr::new(entry.base_address),
+                segment: entry.segment,
+                start_bus: entry.start_bus,
+                end_bus: entry.end_bus,
+            }
+        })
+    }
+}
@@ -0,0 +1,199 @@
+use core::fmt::Display;
+
+use x86_64::PhysAddr;
+
+mod aml;
+mod fadt;
+pub use fadt::Fadt;
+mod generic_address;
+pub use generic_address::{AddressSpace, GenericAddress};
+mod hpet;
+pub use hpet::Hpet;
+mod madt;
+pub use madt::{InterruptFlags, Madt, MadtEntry};
+mod mcfg;
+pub use mcfg::{Mcfg, PciConfigRegion};
+mod sdt;
+pub use sdt::{Sdt, SdtHeader};
+
+/// The Root System Description Pointer the firmware leaves in memory, leading to the other tables.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // The fields below only exist from ACPI 2.0 on
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// The length of the RSDP of ACPI 1.0, covered by its first checksum.
+const RSDP_V1_LENGTH: u64 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The RSDP doesn't start with "RSD PTR "
+    InvalidRsdpSignature,
+    /// The bytes of the RSDP don't add up to zero
+    InvalidRsdpChecksum,
+    /// The RSDT or XSDT doesn't have the right signature, or its bytes don't add up to zero
+    InvalidRootTable,
+}
+
+impl Display for AcpiError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AcpiError::InvalidRsdpSignature => write!(f, "The RSDP has an invalid signature"),
+            AcpiError::InvalidRsdpChecksum => write!(f, "The RSDP has an invalid checksum"),
+            AcpiError::InvalidRootTable => write!(f, "The RSDT/XSDT is invalid"),
+        }
+    }
+}
+
+/// The ACPI tables, read through the physical memory mapping.
+#[derive(Debug, Clone, Copy)]
+pub struct AcpiTables {
+    physical_memory_offset: u64,
+    /// The RSDT, or the XSDT if the firmware has one.
+    root: Sdt,
+    /// Whether the root table is the XSDT, which holds 64 bit addresses.
+    extended: bool,
+    revision: u8,
+}
+
+impl AcpiTables {
+    /// Reads the root table the RSDP points to, checking both of them.
+    ///
+    /// # Safety
+    /// The RSDP has to be the one given by the bootloader, and the physical memory has to be mapped at the offset.
+    pub unsafe fn new(
+        rsdp_address: PhysAddr,
+        physical_memory_offset: u64,
+    ) -> Result<AcpiTables, AcpiError> {
+        let rsdp = unsafe { read_physical::<Rsdp>(rsdp_address, physical_memory_offset) };
+        if &rsdp.signature != b"RSD PTR " {
+            return Err(AcpiError::InvalidRsdpSignature);
+        }
+        if !unsafe { checksum_valid(rsdp_address, RSDP_V1_LENGTH, physical_memory_offset) } {
+            return Err(AcpiError::InvalidRsdpChecksum);
+        }
+        let extended = rsdp.revision >= 2 && rsdp.xsdt_address != 0;
+        if extended
+            && !unsafe { checksum_valid(rsdp_address, rsdp.length as u64, physical_memory_offset) }
+        {
+            return Err(AcpiError::InvalidRsdpChecksum);
+        }
+
+        let (root_address, signature) = if extended {
+            (rsdp.xsdt_address, b"XSDT")
+        } else {
+            (rsdp.rsdt_address as u64, b"RSDT")
+        };
+        let root = unsafe { Sdt::new(PhysAddr::new(root_address), physical_memory_offset) };
+        if &root.signature() != signature || !root.is_valid() {
+            return Err(AcpiError::InvalidRootTable);
+        }
+        Ok(AcpiTables {
+            physical_memory_offset,
+            root,
+            extended,
+            revision: rsdp.revision,
+        })
+    }
+
+    /// Returns the revision of the RSDP - 0 for ACPI 1.0, 2 for the later versions.
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    /// Returns the root table, the XSDT if the firmware has one, or the RSDT.
+    pub fn root(&self) -> Sdt {
+        self.root
+    }
+
+    /// Returns every table listed in the root table, even the ones with invalid checksums.
+    pub fn tables(&self) -> impl Iterator<Item = Sdt> + '_ {
+        let entry_size = if self.extended { 8 } else { 4 };
+        let entries = (self.root.header().length as u64)
+            .saturating_sub(size_of::<SdtHeader>() as u64)
+            / entry_size;
+        (0..entries).map(move |index| {
+            let offset = size_of::<SdtHeader>() as u64 + index * entry_size;
+            let address = if self.extended {
+                self.root.read::<u64>(offset)
+            } else {
+                self.root.read::<u32>(offset) as u64
+            };
+            unsafe { Sdt::new(PhysAddr::new(address), self.physical_memory_offset) }
+        })
+    }
+
+    /// Returns the first valid table with the signature.
+    pub fn find_table(&self, signature: &[u8; 4]) -> Option<Sdt> {
+        self.tables()
+            .find(|table| &table.signature() == signature && table.is_valid())
+    }
+
+    /// Returns the Multiple APIC Description Table, describing the interrupt controllers.
+    pub fn madt(&self) -> Option<Madt> {
+        self.find_table(b"APIC").map(Madt::new)
+    }
+
+    /// Returns the Fixed ACPI Description Table, describing the power management hardware.
+    pub fn fadt(&self) -> Option<Fadt> {
+        self.find_table(b"FACP").map(Fadt::new)
+    }
+
+    /// Returns the table describing the High Precision Event Timer.
+    pub fn hpet(&self) -> Option<Hpet> {
+        self.find_table(b"HPET").map(Hpet::new)
+    }
+
+    /// Returns the table describing the PCI Express configuration space.
+    pub fn mcfg(&self) -> Option<Mcfg> {
+        self.find_table(b"MCFG").map(Mcfg::new)
+    }
+
+    /// Returns the Differentiated System Description Table, holding the AML code of the system.
+    pub fn dsdt(&self) -> Option<Sdt> {
+        let address = self.fadt()?.dsdt();
+        let dsdt = unsafe { Sdt::new(address, self.physical_memory_offset) };
+        (&dsdt.signature() == b"DSDT" && dsdt.is_valid()).then_some(dsdt)
+    }
+
+    /// Returns the SLP_TYPa and SLP_TYPb values entering the sleep state (from 0 to 5) of the DSDT.
+    pub fn sleep_types(&self, state: u8) -> Option<(u8, u8)> {
+        if state > 5 {
+            return None;
+        }
+        aml::find_sleep_types(self.dsdt()?, &[b'_', b'S', b'0' + state, b'_'])
+    }
+}
+
+/// Reads a value from physical memory, which doesn't need to be aligned.
+///
+/// # Safety
+/// The value has to be in physical memory mapped at the offset.
+unsafe fn read_physical<T: Copy>(address: PhysAddr, physical_memory_offset: u64) -> T {
+    let pointer = (physical_memory_offset + address.as_u64()) as *const T;
+    unsafe { pointer.read_unaligned() }
+}
+
+/// Whether the bytes add up to zero, as the ACPI structures' checksums make them.
+///
+/// # Safety
+/// The bytes have to be in physical memory mapped at the offset.
+unsafe fn checksum_valid(address: PhysAddr, length: u64, physical_memory_offset: u64) -> bool {
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            (physical_memory_offset + address.as_u64()) as *const u8,
+            length as usize,
+        )
+    };
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
@@ -0,0 +1,83 @@
+use core::mem::MaybeUninit;
+
+use x86_64::PhysAddr;
+
+use super::{checksum_valid, read_physical};
+
+/// The header every ACPI System Description Table starts with.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    /// The length of the whole table, including the header.
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// A System Description Table, read through the physical memory mapping.
+#[derive(Debug, Clone, Copy)]
+pub struct Sdt {
+    address: PhysAddr,
+    physical_memory_offset: u64,
+}
+
+impl Sdt {
+    /// # Safety
+    /// The address has to be the one of a table, in physical memory mapped at the offset.
+    pub(super) unsafe fn new(address: PhysAddr, physical_memory_offset: u64) -> Sdt {
+        Sdt {
+            address,
+            physical_memory_offset,
+        }
+    }
+
+    pub fn address(&self) -> PhysAddr {
+        self.address
+    }
+
+    pub fn header(&self) -> SdtHeader {
+        self.read(0)
+    }
+
+    pub fn signature(&self) -> [u8; 4] {
+        self.header().signature
+    }
+
+    /// Whether the bytes of the table add up to zero, as they should.
+    pub fn is_valid(&self) -> bool {
+        let length = self.header().length as u64;
+        length >= size_of::<SdtHeader>() as u64
+            && unsafe { checksum_valid(self.address, length, self.physical_memory_offset) }
+    }
+
+    /// Reads a value at the offset from the start of the table.
+    pub(super) fn read<T: Copy>(&self, offset: u64) -> T {
+        unsafe { read_physical(self.address + offset, self.physical_memory_offset) }
+    }
+
+    /// Reads the fields following the header.
+    ///
+    /// The older revisions of the tables are shorter, so the fields past the end of the table are zeroed.
+    /// `T` has to be made of integers only.
+    pub(super) fn read_fields<T: Copy>(&self) -> T {
+        let length = (self.header().length as usize).saturating_sub(size_of::<SdtHeader>());
+        let mut fields = MaybeUninit::<T>::zeroed();
+        let source = (self.physical_memory_offset
+            + self.address.as_u64()
+            + size_of::<SdtHeader>() as u64) as *const u8;
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                source,
+                fields.as_mut_ptr().cast::<u8>(),
+                length.min(size_of::<T>()),
+            );
+            fields.assume_init()
+        }
+    }
+}
@@ -1,5 +1,8 @@
+mod pit;
+pub use pit::busy_wait_microseconds;
+
 mod rtc;
 pub use rtc::{get_current_time, init_rtc};
 
 mod tick;
-pub use tick::get_current_tick;
+pub use tick::{count_timer_tick, get_current_tick, get_timer_ticks};
@@ -0,0 +1,46 @@
+use core::hint::spin_loop;
+
+use x86_64::instructions::port::Port;
+
+/// The frequency of the PIT's oscillator, in Hz.
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_2_DATA_PORT: u16 = 0x42;
+const COMMAND_PORT: u16 = 0x43;
+/// Gates the channel 2 (bit 0) and the speaker (bit 1), and reads the output of the channel 2 (bit 5).
+const SPEAKER_CONTROL_PORT: u16 = 0x61;
+
+const CHANNEL_2_GATE: u8 = 1;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+/// Channel 2, low then high byte of the count, mode 0 (raising the output at the end of the count).
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+/// Waits for the microseconds to pass, counted by the channel 2 of the PIT.
+///
+/// Doesn't need the timer interrupts, so it can be used while the system is starting.
+/// The channel is shared, so only one processor may wait at once.
+pub fn busy_wait_microseconds(microseconds: u64) {
+    let mut control = Port::<u8>::new(SPEAKER_CONTROL_PORT);
+    let mut command = Port::<u8>::new(COMMAND_PORT);
+    let mut data = Port::<u8>::new(CHANNEL_2_DATA_PORT);
+
+    // The counter is 16 bits wide, so the long waits take multiple counts
+    let mut remaining_ticks = (microseconds * PIT_FREQUENCY).div_ceil(1_000_000);
+    while remaining_ticks > 0 {
+        let ticks = remaining_ticks.min(u16::MAX as u64) as u16;
+        remaining_ticks -= ticks as u64;
+        unsafe {
+            // Stopping the channel while it's loaded, and keeping the speaker quiet
+            let gate = control.read() & !(CHANNEL_2_GATE | SPEAKER_ENABLE);
+            control.write(gate);
+            command.write(CHANNEL_2_ONE_SHOT);
+            data.write(ticks as u8);
+            data.write((ticks >> 8) as u8);
+            control.write(gate | CHANNEL_2_GATE);
+            while control.read() & CHANNEL_2_OUTPUT == 0 {
+                spin_loop();
+            }
+        }
+    }
+}
@@ -1,4 +1,10 @@
-use core::arch::x86_64::{_mm_lfence, _rdtsc};
+use core::{
+    arch::x86_64::{_mm_lfence, _rdtsc},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The number of timer interrupts since the timer has been started.
+static TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
 
 #[inline(always)]
 /// Returns the current CPU tick.
@@ -7,3 +13,15 @@ pub fn get_current_tick() -> u64 {
     unsafe { _mm_lfence() };
     value
 }
+
+/// Counts a timer interrupt. Should only be called by the timer interrupt handler.
+pub fn count_timer_tick() {
+    TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of timer interrupts since the timer has been started.
+///
+/// Unlike `get_current_tick`, this advances at a fixed rate, so it can be used to measure time.
+pub fn get_timer_ticks() -> u64 {
+    TIMER_TICKS.load(Ordering::Relaxed)
+}
@@ -1,4 +1,7 @@
-use core::sync::atomic::{AtomicU8, Ordering};
+use core::{
+    arch::x86_64::__cpuid,
+    sync::atomic::{AtomicU16, Ordering},
+};
 
 use spin::Mutex;
 use x86_64::instructions::interrupts::{are_enabled, disable, enable};
@@ -11,12 +14,12 @@ static SPIN_LOCK: Mutex<()> = Mutex::new(());
 /// Marker value to indicate no-one has the lock.
 ///
 /// Initialising `LOCK_OWNER` to 0 means cheaper static initialisation so it's the best choice
-const LOCK_UNOWNED: u8 = 0;
+const LOCK_UNOWNED: u16 = 0;
 
 /// Indicates which core owns the lock so that we can call critical_section recursively.
 ///
-/// 0 = no one has the lock, 1 = core0 has the lock, 2 = core1 has the lock
-static LOCK_OWNER: AtomicU8 = AtomicU8::new(LOCK_UNOWNED);
+/// 0 = no one has the lock, otherwise the APIC ID of the core holding it plus 1
+static LOCK_OWNER: AtomicU16 = AtomicU16::new(LOCK_UNOWNED);
 
 /// Marker value to indicate that we already owned the lock when we started the `critical_section`.
 ///
@@ -30,9 +33,8 @@ unsafe impl critical_section::Impl for RpSpinlockCs {
     unsafe fn acquire() -> u8 {
         // Store the initial interrupt state and current core id in stack variables
         let interrupts_active = are_enabled();
-        // We reserved 0 as our `LOCK_UNOWNED` value, so add 1 to core_id so we get 1 for core0, 2 for core1.
-        // TODO: figure out the actual CPU id
-        let core = 0 as u8 + 1;
+        // We reserved 0 as our `LOCK_UNOWNED` value, so add 1 to the APIC ID of the core
+        let core = current_apic_id() as u16 + 1;
         // Do we already own the spinlock?
         if LOCK_OWNER.load(Ordering::Acquire) == core {
             // We already own the lock, so we must have called acquire within a critical_section.
@@ -85,3 +87,8 @@ unsafe impl critical_section::Impl for RpSpinlockCs {
         }
     }
 }
+
+/// Returns the initial APIC ID of the running core, which CPUID reports even before the APICs are set up.
+fn current_apic_id() -> u8 {
+    (__cpuid(1).ebx >> 24) as u8
+}
@@ -1,5 +1,226 @@
-use linked_list_allocator::LockedHeap;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::{NonNull, null_mut},
+};
+
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+use super::{
+    heap_tracking::{
+        HEAP_TRACKING_ENABLED, current_allocation_tag, record_allocation, record_deallocation,
+    },
+    slab_allocator::{SIZE_CLASSES, SLAB_LAYOUT, SlabAllocator, SlabStatistics},
+};
 
 /// The global memory allocator
 #[global_allocator]
-pub static ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static ALLOCATOR: GrowableHeap = GrowableHeap::empty();
+
+/// The most low memory hooks that can be registered.
+const MAX_LOW_MEMORY_HOOKS: usize = 8;
+
+/// Called when the heap is running low on memory.
+///
+/// The hooks run in the middle of the allocation that ran low - possibly in an interrupt handler,
+/// or with locks held - so they should only take note of it, and free their memory later.
+pub type LowMemoryHook = fn();
+
+static LOW_MEMORY_HOOKS: Mutex<[Option<LowMemoryHook>; MAX_LOW_MEMORY_HOOKS]> =
+    Mutex::new([None; MAX_LOW_MEMORY_HOOKS]);
+
+/// How the heap gets more memory once it's full.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapGrowth {
+    /// Makes the given range at the end of the heap usable, returning whether it succeeded.
+    ///
+    /// It's called with the heap locked, so it can't allocate.
+    pub grow: fn(heap_top: usize, size: usize) -> bool,
+    /// The size the heap can grow by at once - the ranges given to `grow` are multiples of it.
+    pub step: usize,
+    /// The size the heap can never grow past.
+    pub max_size: usize,
+}
+
+struct HeapState {
+    heap: Heap,
+    growth: Option<HeapGrowth>,
+    /// Whether the hooks have been told the heap is nearing its limit.
+    warned: bool,
+}
+
+impl HeapState {
+    /// Allocates the memory, growing the heap if needed.
+    ///
+    /// Also returns whether the heap is running low on memory.
+    fn allocate(&mut self, layout: Layout) -> (Option<NonNull<u8>>, bool) {
+        if let Ok(pointer) = self.heap.allocate_first_fit(layout) {
+            return (Some(pointer), false);
+        }
+        let Some(growth) = self.growth else {
+            return (None, false);
+        };
+
+        // The free space at the end of the heap might not be aligned right, so the alignment is added
+        let Some(size) = layout
+            .size()
+            .checked_add(layout.align())
+            .and_then(|size| size.checked_next_multiple_of(growth.step))
+        else {
+            return (None, true);
+        };
+        let grown = self.heap.size() + size <= growth.max_size
+            && (growth.grow)(self.heap.top() as usize, size);
+        if !grown {
+            return (None, true);
+        }
+        unsafe { self.heap.extend(size) };
+
+        // Warning once the heap uses three quarters of what it can get
+        let low_memory = !self.warned && self.heap.size() >= growth.max_size / 4 * 3;
+        self.warned |= low_memory;
+        (self.heap.allocate_first_fit(layout).ok(), low_memory)
+    }
+}
+
+/// A heap that grows on demand, up to a hard limit.
+///
+/// The small allocations are served by slabs taken from the heap.
+pub struct GrowableHeap {
+    state: Mutex<HeapState>,
+    slabs: SlabAllocator,
+}
+
+impl GrowableHeap {
+    pub const fn empty() -> Self {
+        GrowableHeap {
+            state: Mutex::new(HeapState {
+                heap: Heap::empty(),
+                growth: None,
+                warned: false,
+            }),
+            slabs: SlabAllocator::new(),
+        }
+    }
+
+    /// Initializes the heap with the given memory. Without `growth`, the heap never grows.
+    ///
+    /// # Safety
+    /// The memory has to be mapped and unused, and this can only be called once.
+    pub unsafe fn init(&self, start: *mut u8, size: usize, growth: Option<HeapGrowth>) {
+        let mut state = self.state.lock();
+        unsafe { state.heap.init(start, size) };
+        state.growth = growth;
+    }
+
+    /// Returns the memory used by allocations.
+    pub fn used(&self) -> usize {
+        self.state.lock().heap.used()
+    }
+
+    /// Returns the memory the heap currently has.
+    pub fn size(&self) -> usize {
+        self.state.lock().heap.size()
+    }
+
+    /// Returns the statistics of the slabs' size classes, smallest objects first.
+    pub fn slab_statistics(&self) -> [SlabStatistics; SIZE_CLASSES] {
+        self.slabs.statistics()
+    }
+
+    /// Allocates the memory straight from the heap.
+    fn allocate_from_heap(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let (pointer, low_memory) = self.state.lock().allocate(layout);
+        if low_memory {
+            run_low_memory_hooks();
+        }
+        pointer
+    }
+
+    /// Returns the size the heap can never grow past.
+    pub fn max_size(&self) -> usize {
+        let state = self.state.lock();
+        state
+            .growth
+            .map_or(state.heap.size(), |growth| growth.max_size)
+    }
+}
+
+impl GrowableHeap {
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        match SlabAllocator::size_class(layout) {
+            Some(class) => self
+                .slabs
+                .allocate(class, || self.allocate_from_heap(SLAB_LAYOUT)),
+            None => self.allocate_from_heap(layout),
+        }
+    }
+
+    /// # Safety
+    /// The memory has to be allocated with the same layout, and can't be used after.
+    unsafe fn deallocate(&self, pointer: NonNull<u8>, layout: Layout) {
+        if let Some(class) = SlabAllocator::size_class(layout) {
+            unsafe { self.slabs.deallocate(class, pointer) };
+            return;
+        }
+        unsafe { self.state.lock().heap.deallocate(pointer, layout) };
+    }
+}
+
+/// Returns the layout of a tracked allocation, which starts with the tag of the allocation,
+/// and the offset of the memory given out in it.
+fn tracked_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let (tracked_layout, offset) = Layout::new::<u8>().extend(layout).ok()?;
+    Some((tracked_layout.pad_to_align(), offset))
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !HEAP_TRACKING_ENABLED {
+            return self.allocate(layout).map_or(null_mut(), NonNull::as_ptr);
+        }
+        let Some((tracked_layout, offset)) = tracked_layout(layout) else {
+            return null_mut();
+        };
+        let Some(pointer) = self.allocate(tracked_layout) else {
+            return null_mut();
+        };
+        let tag = current_allocation_tag();
+        unsafe { pointer.write(tag) };
+        record_allocation(tag, layout.size());
+        unsafe { pointer.add(offset).as_ptr() }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let pointer = unsafe { NonNull::new_unchecked(ptr) };
+        if !HEAP_TRACKING_ENABLED {
+            unsafe { self.deallocate(pointer, layout) };
+            return;
+        }
+        let (tracked_layout, offset) =
+            tracked_layout(layout).expect("The layout has been tracked when allocating");
+        let pointer = unsafe { pointer.sub(offset) };
+        record_deallocation(unsafe { pointer.read() }, layout.size());
+        unsafe { self.deallocate(pointer, tracked_layout) };
+    }
+}
+
+/// Registers a hook called when the heap is nearing its limit, or can't grow anymore.
+///
+/// Returns `false` if there is no space left for the hook.
+pub fn register_low_memory_hook(hook: LowMemoryHook) -> bool {
+    let mut hooks = LOW_MEMORY_HOOKS.lock();
+    match hooks.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some(hook);
+            true
+        }
+        None => false,
+    }
+}
+
+fn run_low_memory_hooks() {
+    // Copied out, so the hooks can allocate (and so run out of memory again) without deadlocking
+    let hooks = *LOW_MEMORY_HOOKS.lock();
+    hooks.into_iter().flatten().for_each(|hook| hook());
+}
@@ -1,6 +1,11 @@
 use alloc::sync::Arc;
 use spin::Mutex;
-use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Size2MiB, Size4KiB};
+use x86_64::{
+    PhysAddr,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PhysFrame, Size2MiB, Size4KiB, frame::PhysFrameRange,
+    },
+};
 
 use crate::{display::format_size, kernel_information::allocator::ALLOCATOR, logln};
 
@@ -24,6 +29,37 @@ pub trait FullFrameAllocator:
 
     /// Returns the number of free 2M frames.
     fn get_free_2m_frames(&self) -> u64;
+
+    /// Adds a reference to an allocated 4K frame, which is then only freed after every reference is released.
+    fn share_frame(&mut self, frame: PhysFrame<Size4KiB>);
+
+    /// Returns the number of references to an allocated 4K frame.
+    fn get_frame_references(&self, frame: PhysFrame<Size4KiB>) -> u64;
+
+    /// Releases a reference to a 4K frame, freeing the frame if it was the last one.
+    ///
+    /// Returns whether the frame has been freed.
+    ///
+    /// # Safety
+    /// The reference must not be used after it has been released.
+    unsafe fn release_frame(&mut self, frame: PhysFrame<Size4KiB>) -> bool;
+
+    /// Allocates `count` physically contiguous 4K frames, all below `max_address`,
+    /// with the first one aligned to `alignment` bytes (a power of two).
+    ///
+    /// The memory below `LOW_MEMORY_LIMIT` is only given out when the range can't be found above it.
+    fn allocate_contiguous(
+        &mut self,
+        count: u64,
+        max_address: PhysAddr,
+        alignment: u64,
+    ) -> Option<PhysFrameRange<Size4KiB>>;
+
+    /// Frees the frames allocated by `allocate_contiguous`.
+    ///
+    /// # Safety
+    /// The frames must not be used after they have been freed.
+    unsafe fn deallocate_contiguous(&mut self, frames: PhysFrameRange<Size4KiB>);
 }
 
 pub fn print_memory(allocator: Arc<Mutex<dyn FullFrameAllocator + Send + Sync>>) {
@@ -32,18 +68,38 @@ pub fn print_memory(allocator: Arc<Mutex<dyn FullFrameAllocator + Send + Sync>>)
 }
 
 pub fn print_heap_memory() {
-    let (used, size) = {
-        let heap_allocator = ALLOCATOR.lock();
-        (heap_allocator.used(), heap_allocator.size())
-    };
+    let (used, size, max_size) = (ALLOCATOR.used(), ALLOCATOR.size(), ALLOCATOR.max_size());
     logln!(
-        "{:<14}{:>7}/{:>7}",
+        "{:<14}{:>7}/{:>7} (limit {})",
         "Heap memory:",
         format_size(used as u64),
-        format_size(size as u64)
+        format_size(size as u64),
+        format_size(max_size as u64)
     );
 }
 
+/// Prints how much of every slab size class is used.
+pub fn print_slab_memory() {
+    logln!(
+        "{:>8} | {:>6} | {:>8} | {:>8} | {:>12}",
+        "Object",
+        "Slabs",
+        "Used",
+        "Free",
+        "Allocations"
+    );
+    for statistics in ALLOCATOR.slab_statistics() {
+        logln!(
+            "{:>8} | {:>6} | {:>8} | {:>8} | {:>12}",
+            format_size(statistics.object_size as u64),
+            statistics.slabs,
+            statistics.used_objects,
+            statistics.free_objects,
+            statistics.allocations
+        );
+    }
+}
+
 pub fn print_frame_memory(allocator: Arc<Mutex<dyn FullFrameAllocator + Send + Sync>>) {
     #[cfg(debug_assertions)]
     {
@@ -0,0 +1,200 @@
+use core::{
+    cmp::Reverse,
+    fmt::Display,
+    sync::atomic::{AtomicU8, AtomicU64, AtomicUsize, Ordering},
+};
+
+use spin::Once;
+
+/// Whether the allocator tracks what the heap is used for - enabled by the `heap_tracking` feature,
+/// as it costs a header in front of every allocation.
+pub const HEAP_TRACKING_ENABLED: bool = cfg!(feature = "heap_tracking");
+
+/// The subsystems the heap allocations are accounted to.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationTag {
+    /// Allocations made outside of any tagged scope
+    Untagged,
+    Memory,
+    Interrupts,
+    Processes,
+    Syscalls,
+    Drivers,
+    Ikd,
+}
+
+const TAG_COUNT: usize = 7;
+
+impl AllocationTag {
+    pub const ALL: [AllocationTag; TAG_COUNT] = [
+        AllocationTag::Untagged,
+        AllocationTag::Memory,
+        AllocationTag::Interrupts,
+        AllocationTag::Processes,
+        AllocationTag::Syscalls,
+        AllocationTag::Drivers,
+        AllocationTag::Ikd,
+    ];
+
+    fn from_u8(value: u8) -> Self {
+        Self::ALL
+            .get(value as usize)
+            .copied()
+            .unwrap_or(AllocationTag::Untagged)
+    }
+}
+
+impl Display for AllocationTag {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AllocationTag::Untagged => write!(f, "untagged"),
+            AllocationTag::Memory => write!(f, "memory"),
+            AllocationTag::Interrupts => write!(f, "interrupts"),
+            AllocationTag::Processes => write!(f, "processes"),
+            AllocationTag::Syscalls => write!(f, "syscalls"),
+            AllocationTag::Drivers => write!(f, "drivers"),
+            AllocationTag::Ikd => write!(f, "ikd"),
+        }
+    }
+}
+
+struct TagCounters {
+    live_bytes: AtomicUsize,
+    live_allocations: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    total_allocations: AtomicU64,
+}
+
+static COUNTERS: [TagCounters; TAG_COUNT] = [const {
+    TagCounters {
+        live_bytes: AtomicUsize::new(0),
+        live_allocations: AtomicUsize::new(0),
+        peak_bytes: AtomicUsize::new(0),
+        total_allocations: AtomicU64::new(0),
+    }
+}; TAG_COUNT];
+
+/// The tag the allocations are accounted to, until the kernel gives every processor its own.
+static BOOT_TAG: AtomicU8 = AtomicU8::new(AllocationTag::Untagged as u8);
+
+/// Returns the tag of the running processor.
+static PROCESSOR_TAG: Once<fn() -> &'static AtomicU8> = Once::new();
+
+/// Gives every processor its own tag, returned by the function for the running one.
+///
+/// The running processor keeps the tag it had so far.
+pub fn set_processor_tags(processor_tag: fn() -> &'static AtomicU8) {
+    PROCESSOR_TAG.call_once(|| {
+        processor_tag().store(BOOT_TAG.load(Ordering::Relaxed), Ordering::Relaxed);
+        processor_tag
+    });
+}
+
+fn current_tag() -> &'static AtomicU8 {
+    PROCESSOR_TAG
+        .get()
+        .map_or(&BOOT_TAG, |processor_tag| processor_tag())
+}
+
+/// Runs the function with its allocations accounted to the tag.
+///
+/// The tag belongs to the running processor, so allocations of interrupt handlers running on it
+/// meanwhile are accounted to it too.
+/// If the function never returns, like a system call switching to another thread, the previous tag isn't
+/// restored: the code it switches to has to reset the tag with `set_allocation_tag`.
+pub fn with_allocation_tag<T>(tag: AllocationTag, function: impl FnOnce() -> T) -> T {
+    let previous = current_tag().swap(tag as u8, Ordering::Relaxed);
+    let result = function();
+    // The thread could have moved to another processor, which reset the tag when switching to it
+    current_tag().store(previous, Ordering::Relaxed);
+    result
+}
+
+/// Accounts the next allocations of the running processor to the tag, until it's changed again.
+///
+/// Switching to another thread leaves the tagged scopes of the previous one, so it resets the tag.
+pub fn set_allocation_tag(tag: AllocationTag) {
+    current_tag().store(tag as u8, Ordering::Relaxed);
+}
+
+pub(super) fn current_allocation_tag() -> u8 {
+    current_tag().load(Ordering::Relaxed)
+}
+
+pub(super) fn record_allocation(tag: u8, size: usize) {
+    let counters = &COUNTERS[AllocationTag::from_u8(tag) as usize];
+    let live_bytes = counters.live_bytes.fetch_add(size, Ordering::Relaxed) + size;
+    counters.live_allocations.fetch_add(1, Ordering::Relaxed);
+    counters.peak_bytes.fetch_max(live_bytes, Ordering::Relaxed);
+    counters.total_allocations.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(super) fn record_deallocation(tag: u8, size: usize) {
+    let counters = &COUNTERS[AllocationTag::from_u8(tag) as usize];
+    counters.live_bytes.fetch_sub(size, Ordering::Relaxed);
+    counters.live_allocations.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// What the heap holds for a tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagStatistics {
+    pub tag: AllocationTag,
+    pub live_bytes: usize,
+    pub live_allocations: usize,
+    /// The most bytes the tag has had at once.
+    pub peak_bytes: usize,
+    pub total_allocations: u64,
+}
+
+/// How a tag changed between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagDifference {
+    pub tag: AllocationTag,
+    pub bytes: isize,
+    pub allocations: isize,
+}
+
+/// The statistics of every tag at one moment, so they can be compared to find leaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapSnapshot {
+    tags: [TagStatistics; TAG_COUNT],
+}
+
+impl HeapSnapshot {
+    pub fn take() -> Self {
+        HeapSnapshot {
+            tags: AllocationTag::ALL.map(|tag| {
+                let counters = &COUNTERS[tag as usize];
+                TagStatistics {
+                    tag,
+                    live_bytes: counters.live_bytes.load(Ordering::Relaxed),
+                    live_allocations: counters.live_allocations.load(Ordering::Relaxed),
+                    peak_bytes: counters.peak_bytes.load(Ordering::Relaxed),
+                    total_allocations: counters.total_allocations.load(Ordering::Relaxed),
+                }
+            }),
+        }
+    }
+
+    /// Returns the tags, the ones holding the most bytes first.
+    pub fn top_consumers(&self) -> [TagStatistics; TAG_COUNT] {
+        let mut tags = self.tags;
+        tags.sort_unstable_by_key(|tag| Reverse(tag.live_bytes));
+        tags
+    }
+
+    /// Returns how every tag changed since the earlier snapshot, the ones that grew the most first.
+    pub fn difference_since(&self, earlier: &HeapSnapshot) -> [TagDifference; TAG_COUNT] {
+        let mut differences = core::array::from_fn(|index| {
+            let (now, before) = (self.tags[index], earlier.tags[index]);
+            TagDifference {
+                tag: now.tag,
+                bytes: now.live_bytes as isize - before.live_bytes as isize,
+                allocations: now.live_allocations as isize - before.live_allocations as isize,
+            }
+        });
+        differences.sort_unstable_by_key(|difference: &TagDifference| Reverse(difference.bytes));
+        differences
+    }
+}
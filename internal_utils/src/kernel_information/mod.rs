@@ -1,4 +1,4 @@
-use alloc::sync::Arc;
+use alloc::{format, sync::Arc};
 use bootloader_api::{
     BootInfo,
     info::{MemoryRegions, Optional},
@@ -7,6 +7,7 @@ use spin::Mutex;
 use x86_64::PhysAddr;
 
 use crate::{
+    acpi::AcpiTables,
     display::HexNumber,
     kernel_information::{
         frame_allocator::FullFrameAllocator, kernel_frame_buffer::KernelFrameBuffer,
@@ -17,7 +18,9 @@ use crate::{
 
 pub mod allocator;
 pub mod frame_allocator;
+pub mod heap_tracking;
 pub mod kernel_frame_buffer;
+pub mod slab_allocator;
 
 #[derive(Clone)]
 #[repr(C)]
@@ -29,6 +32,10 @@ pub struct KernelInformation {
     pub allocator: Arc<Mutex<dyn FullFrameAllocator + Send + Sync>>,
     pub kernel_start: PhysAddr,
     pub rsdp: Option<PhysAddr>,
+    /// The ACPI tables the RSDP leads to, if they're valid.
+    pub acpi: Option<AcpiTables>,
+    /// The ELF image the bootloader loaded as the ramdisk, started as the first user process.
+    pub init_image: Option<&'static [u8]>,
 }
 
 impl KernelInformation {
@@ -40,17 +47,29 @@ impl KernelInformation {
             Some(framebuffer) => Optional::Some(KernelFrameBuffer::new(framebuffer)),
             None => Optional::None,
         };
+        let physical_memory_offset = *boot_info
+            .physical_memory_offset
+            .as_ref()
+            .expect("No physical memory mapping");
+        let rsdp = boot_info.rsdp_addr.as_ref().copied().map(PhysAddr::new);
+        let acpi = rsdp.and_then(|rsdp| {
+            unsafe { AcpiTables::new(rsdp, physical_memory_offset) }
+                .inspect_err(|error| logln!("[WARN] Ignoring the ACPI tables: {}", error))
+                .ok()
+        });
+        let init_image = boot_info.ramdisk_addr.as_ref().map(|&address| unsafe {
+            core::slice::from_raw_parts(address as *const u8, boot_info.ramdisk_len as usize)
+        });
         let v = boot_info.api_version;
         let kernel_info = KernelInformation {
             bootloader_version: (v.version_major(), v.version_minor(), v.version_patch()),
-            physical_memory_offset: *boot_info
-                .physical_memory_offset
-                .as_ref()
-                .expect("No physical memory mapping"),
+            physical_memory_offset,
             framebuffer,
             memory_regions: &boot_info.memory_regions,
             allocator,
-            rsdp: boot_info.rsdp_addr.as_ref().copied().map(PhysAddr::new),
+            rsdp,
+            acpi,
+            init_image,
             kernel_start: PhysAddr::new(boot_info.kernel_addr),
         };
         KERNEL_INFORMATION.call_once(|| kernel_info.clone());
@@ -77,6 +96,24 @@ impl KernelInformation {
         } else {
             logln!("{:<20} {:>32}", "RSDP:", "No RSDP found")
         }
+        if let Some(acpi) = self.acpi.as_ref() {
+            logln!(
+                "{:<20} {:>32}",
+                "ACPI tables:",
+                format!("{} (revision {})", acpi.tables().count(), acpi.revision())
+            );
+        } else {
+            logln!("{:<20} {:>32}", "ACPI tables:", "No valid tables");
+        }
+        if let Some(image) = self.init_image {
+            logln!(
+                "{:<20} {:>32}",
+                "Init image:",
+                format!("{} bytes", image.len())
+            );
+        } else {
+            logln!("{:<20} {:>32}", "Init image:", "No image");
+        }
         logln!(
             "{:<20} {:>32}",
             "Physical memory map:",
@@ -0,0 +1,152 @@
+use core::{alloc::Layout, ptr::NonNull};
+
+use spin::Mutex;
+
+/// The size of the chunks the slabs take from the heap.
+pub const SLAB_SIZE: usize = 4096;
+/// The smallest size class - a free object has to fit a pointer.
+const MIN_OBJECT_SIZE: usize = 16;
+/// The size classes, doubling from `MIN_OBJECT_SIZE` up to half a slab.
+pub const SIZE_CLASSES: usize = 8;
+
+/// The layout of the chunks the slabs take from the heap - aligned to their size,
+/// so every object is aligned to its own size.
+pub const SLAB_LAYOUT: Layout = match Layout::from_size_align(SLAB_SIZE, SLAB_SIZE) {
+    Ok(layout) => layout,
+    Err(_) => panic!("Invalid slab layout"),
+};
+
+/// An object waiting in a size class for reuse.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+struct SlabClass {
+    free_list: Option<NonNull<FreeObject>>,
+    slabs: usize,
+    used_objects: usize,
+    allocations: u64,
+}
+
+// The free objects are only ever accessed through the class owning them.
+unsafe impl Send for SlabClass {}
+
+/// What a size class of the slab allocator holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabStatistics {
+    pub object_size: usize,
+    /// The chunks taken from the heap.
+    pub slabs: usize,
+    pub used_objects: usize,
+    pub free_objects: usize,
+    /// Every allocation the class has served.
+    pub allocations: u64,
+}
+
+/// Serves the small allocations from slabs of equally sized objects,
+/// which get reused without going through the heap again.
+///
+/// Slabs are never returned to the heap, their objects wait for the next allocation of their class.
+pub struct SlabAllocator {
+    classes: [Mutex<SlabClass>; SIZE_CLASSES],
+}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        SlabAllocator {
+            classes: [const {
+                Mutex::new(SlabClass {
+                    free_list: None,
+                    slabs: 0,
+                    used_objects: 0,
+                    allocations: 0,
+                })
+            }; SIZE_CLASSES],
+        }
+    }
+
+    /// Returns the size class of the allocation, if it's small enough for the slabs.
+    pub fn size_class(layout: Layout) -> Option<usize> {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(MIN_OBJECT_SIZE)
+            .next_power_of_two();
+        let class = (size.trailing_zeros() - MIN_OBJECT_SIZE.trailing_zeros()) as usize;
+        (class < SIZE_CLASSES).then_some(class)
+    }
+
+    fn object_size(class: usize) -> usize {
+        MIN_OBJECT_SIZE << class
+    }
+
+    /// Allocates an object of the class, taking a new slab from `allocate_slab` if the class is full.
+    ///
+    /// `allocate_slab` has to return memory with `SLAB_LAYOUT`.
+    pub fn allocate(
+        &self,
+        class: usize,
+        allocate_slab: impl FnOnce() -> Option<NonNull<u8>>,
+    ) -> Option<NonNull<u8>> {
+        let mut slab_class = self.classes[class].lock();
+        if slab_class.free_list.is_none() {
+            let slab = allocate_slab()?;
+            let object_size = Self::object_size(class);
+            // Threading the objects onto the free list backwards, so they're handed out in order
+            for offset in (0..SLAB_SIZE).step_by(object_size).rev() {
+                let object = unsafe { slab.byte_add(offset) }.cast::<FreeObject>();
+                unsafe {
+                    object.write(FreeObject {
+                        next: slab_class.free_list,
+                    })
+                };
+                slab_class.free_list = Some(object);
+            }
+            slab_class.slabs += 1;
+        }
+
+        let object = slab_class.free_list?;
+        slab_class.free_list = unsafe { object.as_ref() }.next;
+        slab_class.used_objects += 1;
+        slab_class.allocations += 1;
+        Some(object.cast())
+    }
+
+    /// Returns the object to its class.
+    ///
+    /// # Safety
+    /// The object has to be allocated from the class, and can't be used after.
+    pub unsafe fn deallocate(&self, class: usize, object: NonNull<u8>) {
+        let mut slab_class = self.classes[class].lock();
+        let object = object.cast::<FreeObject>();
+        unsafe {
+            object.write(FreeObject {
+                next: slab_class.free_list,
+            })
+        };
+        slab_class.free_list = Some(object);
+        slab_class.used_objects -= 1;
+    }
+
+    /// Returns the statistics of every size class, smallest objects first.
+    pub fn statistics(&self) -> [SlabStatistics; SIZE_CLASSES] {
+        core::array::from_fn(|class| {
+            let slab_class = self.classes[class].lock();
+            let object_size = Self::object_size(class);
+            let objects = slab_class.slabs * (SLAB_SIZE / object_size);
+            SlabStatistics {
+                object_size,
+                slabs: slab_class.slabs,
+                used_objects: slab_class.used_objects,
+                free_objects: objects - slab_class.used_objects,
+                allocations: slab_class.allocations,
+            }
+        })
+    }
+}
+
+impl Default for SlabAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -12,6 +12,7 @@
 )]
 
 extern crate alloc;
+pub mod acpi;
 pub mod block_device;
 pub mod capabilities;
 pub mod clocks;
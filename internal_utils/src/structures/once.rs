@@ -35,6 +35,11 @@ impl<T> OnceMutex<T> {
         self.0.get().map(|lock| lock.lock())
     }
 
+    /// Locks the mutex if it's initialized and not locked already.
+    pub fn try_lock(&'_ self) -> Option<MutexGuard<'_, T>> {
+        self.0.get().and_then(|lock| lock.try_lock())
+    }
+
     pub fn call_once<F: FnOnce() -> T>(&self, f: F) {
         self.0.call_once(|| Mutex::new(f()));
     }
@@ -13,7 +13,23 @@ pub const LOW_MEMORY_LIMIT: u64 = 0x0100_0000; // 16MiB
 
 const KERNEL_STACK_SIZE: u64 = 16 * 1024 * 1024; // 16MiB
 pub const HEAP_START: u64 = 0x0000_7FA0_0000_0000;
-pub const HEAP_SIZE: u64 = 16 * 1024 * 1024; // 16MiB
+pub const HEAP_SIZE: u64 = 16 * 1024 * 1024; // 16MiB mapped at boot, the heap grows past it on demand
+pub const HEAP_MAX_SIZE: u64 = 256 * 1024 * 1024; // 256MiB
+
+// User processes get the lower half of the address space, up to the level 4 entry holding the heap
+pub const USER_SPACE_START: u64 = 0x0000_0000_0000_1000; // We keep the null page unmapped
+pub const USER_SPACE_END: u64 = 0x0000_7F80_0000_0000;
+pub const USER_STACK_TOP: u64 = 0x0000_7F00_0000_0000;
+pub const USER_STACK_SIZE: u64 = 64 * 1024; // 64KiB
+pub const USER_MAPPINGS_START: u64 = 0x0000_1000_0000_0000; // Where the memory mapped without an address goes
+
+// Kernel thread stacks live in equally sized slots, each starting with an unmapped guard page
+pub const GUARDED_STACKS_START: u64 = 0xFFFF_8050_0000_0000;
+pub const GUARDED_STACK_SIZE: u64 = 64 * 1024; // 64KiB
+pub const GUARDED_STACK_SLOTS: u64 = 0x10_0000; // 68GiB of address space, with the guard pages
+
+// The image of the init process, which the bootloader loads with the kernel if it's given one
+const INIT_IMAGE_START: u64 = 0xFFFF_8070_0000_0000;
 
 pub static BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
@@ -23,5 +39,6 @@ pub static BOOTLOADER_CONFIG: BootloaderConfig = {
     config.mappings.framebuffer = Mapping::FixedAddress(ADDRESSES[2]);
     config.mappings.kernel_base = Mapping::FixedAddress(ADDRESSES[3]);
     config.mappings.physical_memory = Some(Mapping::FixedAddress(ADDRESSES[4]));
+    config.mappings.ramdisk_memory = Mapping::FixedAddress(INIT_IMAGE_START);
     config
 };
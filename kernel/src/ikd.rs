@@ -7,6 +7,10 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use crosstrait::Cast;
 use internal_utils::HexNumber;
+use internal_utils::format_size;
+use internal_utils::kernel_information::heap_tracking::{
+    AllocationTag, HEAP_TRACKING_ENABLED, HeapSnapshot, with_allocation_tag,
+};
 
 use internal_utils::tag_store::{
     BoolQueryExpression, BoolQueryExpressionType, BooleanTag, IntegerTag, Query, QueryOptions,
@@ -14,16 +18,26 @@ use internal_utils::tag_store::{
 };
 use internal_utils::{
     clocks::{get_current_tick, get_current_time},
-    kernel_information::{KERNEL_INFORMATION, frame_allocator::print_memory},
+    kernel_information::{
+        KERNEL_INFORMATION,
+        frame_allocator::{print_memory, print_slab_memory},
+    },
     log, logln,
 };
+use spin::Mutex;
 use x86_64::registers::read_rip;
 
 use crate::addressing;
-use crate::processes::{SCHEDULER, run_processes};
+use crate::interrupts::crash_records::get_crash_records;
+use crate::power::{power_off, reboot};
+use crate::processes::{elf::start_init_process, run_processes, scheduler_table};
 
 /// Parses a command. Returns whether we should exit the IKD
 pub fn parse_command(command: &str) -> bool {
+    with_allocation_tag(AllocationTag::Ikd, || run_command(command))
+}
+
+fn run_command(command: &str) -> bool {
     let command = command.trim();
     let result = COMMANDS
         .iter()
@@ -52,9 +66,12 @@ static COMMANDS: &[(&str, StaticFunction)] = &[
     ("help", &help),
     ("memory", &memory),
     ("exit", &exit),
+    ("power", &power),
     ("kernel", &kernel),
     ("scheduler", &scheduler),
     ("clocks", &clocks),
+    ("crashes", &crashes),
+    ("acpi", &acpi),
     ("ip", &ip),
     ("tbes", &tbes),
     ("panic", &panic),
@@ -78,8 +95,10 @@ fn memory(args: Arguments) -> Result<bool, Cow<'static, str>> {
         match subcommand {
             "info" => {
                 print_memory(kernel_info.allocator);
+                print_slab_memory();
                 Ok(false)
             }
+            "heap" => heap(args),
             "view" | "viewp" | "viewk" => {
                 if let Some((from, to)) = get_from_to(args) {
                     if subcommand == "view" {
@@ -98,7 +117,22 @@ fn memory(args: Arguments) -> Result<bool, Cow<'static, str>> {
         }
     } else {
         logln!("memory subcommands:");
-        logln!("- {:<20} | Shows memory information", "info");
+        logln!(
+            "- {:<20} | Shows memory information and slab statistics",
+            "info"
+        );
+        logln!(
+            "- {:<20} | Shows the subsystems using the most heap memory",
+            "heap"
+        );
+        logln!(
+            "- {:<20} | Remembers the heap usage to compare it later",
+            "heap snapshot"
+        );
+        logln!(
+            "- {:<20} | Shows how the heap usage changed since the snapshot",
+            "heap diff"
+        );
         logln!(
             "- {:<20} | Shows a slice of virtual memory in a hex view",
             "view from:to"
@@ -115,6 +149,65 @@ fn memory(args: Arguments) -> Result<bool, Cow<'static, str>> {
     }
 }
 
+/// The heap usage remembered by `memory heap snapshot`.
+static HEAP_SNAPSHOT: Mutex<Option<HeapSnapshot>> = Mutex::new(None);
+
+fn heap(args: Arguments) -> Result<bool, Cow<'static, str>> {
+    if !HEAP_TRACKING_ENABLED {
+        return Err(
+            "Heap tracking is disabled, build the kernel with the heap_tracking feature".into(),
+        );
+    }
+    let snapshot = HeapSnapshot::take();
+    match args.next() {
+        None => {
+            logln!(
+                "{:<12} | {:>9} | {:>11} | {:>9} | {:>11}",
+                "Subsystem",
+                "Live",
+                "Allocations",
+                "Peak",
+                "Total"
+            );
+            for tag in snapshot.top_consumers() {
+                logln!(
+                    "{:<12} | {:>9} | {:>11} | {:>9} | {:>11}",
+                    tag.tag,
+                    format_size(tag.live_bytes as u64),
+                    tag.live_allocations,
+                    format_size(tag.peak_bytes as u64),
+                    tag.total_allocations
+                );
+            }
+        }
+        Some("snapshot") => {
+            *HEAP_SNAPSHOT.lock() = Some(snapshot);
+            logln!("Heap snapshot taken");
+        }
+        Some("diff") => {
+            let Some(earlier) = *HEAP_SNAPSHOT.lock() else {
+                return Err("Take a snapshot with \"memory heap snapshot\" first".into());
+            };
+            logln!(
+                "{:<12} | {:>12} | {:>11}",
+                "Subsystem",
+                "Bytes",
+                "Allocations"
+            );
+            for difference in snapshot.difference_since(&earlier) {
+                logln!(
+                    "{:<12} | {:>+12} | {:>+11}",
+                    difference.tag,
+                    difference.bytes,
+                    difference.allocations
+                );
+            }
+        }
+        Some(_) => return Err("Invalid subcommand".into()),
+    }
+    Ok(false)
+}
+
 fn exit(args: Arguments) -> Result<bool, Cow<'static, str>> {
     let subcommand = args.next();
     if let Some(subcommand) = subcommand {
@@ -131,6 +224,25 @@ fn exit(args: Arguments) -> Result<bool, Cow<'static, str>> {
     }
 }
 
+fn power(args: Arguments) -> Result<bool, Cow<'static, str>> {
+    let subcommand = args.next();
+    if let Some(subcommand) = subcommand {
+        match subcommand {
+            "off" => Err(format!("{}", power_off()).into()),
+            "reboot" => Err(format!("{}", reboot()).into()),
+            _ => Err("Invalid subcommand".into()),
+        }
+    } else {
+        logln!("power subcommands:");
+        logln!("- {:<20} | Powers the system off through ACPI", "off");
+        logln!(
+            "- {:<20} | Reboots through ACPI or the keyboard controller",
+            "reboot"
+        );
+        Ok(false)
+    }
+}
+
 fn exit_qemu() -> ! {
     unsafe {
         asm!(
@@ -208,6 +320,52 @@ fn clocks(args: Arguments) -> Result<bool, Cow<'static, str>> {
     }
 }
 
+fn crashes(args: Arguments) -> Result<bool, Cow<'static, str>> {
+    if args.next().is_some() {
+        Err("crashes does not accept arguments".into())
+    } else {
+        let records = get_crash_records();
+        if records.is_empty() {
+            logln!("No process has crashed");
+        }
+        for record in records {
+            logln!("{}", record);
+        }
+        Ok(false)
+    }
+}
+
+fn acpi(args: Arguments) -> Result<bool, Cow<'static, str>> {
+    if args.next().is_some() {
+        return Err("acpi does not accept arguments".into());
+    }
+    let Some(tables) = KERNEL_INFORMATION.get().unwrap().acpi else {
+        return Err("No valid ACPI tables found".into());
+    };
+    logln!(
+        "{:<9} | {:>21} | {:>8} | {:>8} | {:<6} | {}",
+        "Signature",
+        "Address",
+        "Length",
+        "Revision",
+        "OEM",
+        "Checksum"
+    );
+    for table in core::iter::once(tables.root()).chain(tables.tables()) {
+        let header = table.header();
+        logln!(
+            "{:<9} | {:>21} | {:>8} | {:>8} | {:<6} | {}",
+            String::from_utf8_lossy(&header.signature),
+            table.address().to_separated_hex(),
+            format_size(header.length as u64),
+            header.revision,
+            String::from_utf8_lossy(&header.oem_id),
+            if table.is_valid() { "valid" } else { "invalid" }
+        );
+    }
+    Ok(false)
+}
+
 fn ip(args: Arguments) -> Result<bool, Cow<'static, str>> {
     if args.next().is_some() {
         Err("ip does not accept arguments".into())
@@ -228,16 +386,25 @@ fn scheduler(args: Arguments) -> Result<bool, Cow<'static, str>> {
     if let Some(subcommand) = subcommand {
         match subcommand {
             "processes" => {
-                SCHEDULER.lock().unwrap().get_processes_and_threads().log();
+                scheduler_table().log();
                 Ok(false)
             }
             "run" => run_processes(),
+            "start" => match start_init_process() {
+                Some(Ok(_)) => Ok(false),
+                Some(Err(error)) => Err(format!("Couldn't start the init image: {}", error).into()),
+                None => Err("The kernel was booted without an init image".into()),
+            },
             _ => Err("Invalid subcommand".into()),
         }
     } else {
         logln!("scheduler subcommands:");
         logln!("- {:<20} | Shows processes", "processes");
         logln!("- {:<20} | Runs the scheduler", "run");
+        logln!(
+            "- {:<20} | Starts another process from the init image",
+            "start"
+        );
         Ok(false)
     }
 }
@@ -0,0 +1,113 @@
+use core::ptr::{read_volatile, write_volatile};
+
+use x86_64::PhysAddr;
+
+// The registers are accessed by writing their index to the selector, then using the window
+const REGISTER_SELECT: u64 = 0x00;
+const REGISTER_WINDOW: u64 = 0x10;
+
+const VERSION: u32 = 0x01;
+const REDIRECTION_TABLE: u32 = 0x10;
+
+const ACTIVE_LOW: u64 = 1 << 13;
+const LEVEL_TRIGGERED: u64 = 1 << 15;
+const MASKED: u64 = 1 << 16;
+
+/// Where an IO-APIC sends one of its interrupts.
+#[derive(Debug, Clone, Copy)]
+pub struct Redirection {
+    pub vector: u8,
+    /// The APIC ID of the processor receiving the interrupt.
+    pub destination: u8,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+/// An IO-APIC, routing the global system interrupts from `interrupt_base` on to the local APICs.
+#[derive(Debug)]
+pub struct IoApic {
+    /// The virtual address of the registers.
+    base: u64,
+    interrupt_base: u32,
+    interrupts: u32,
+}
+
+impl IoApic {
+    /// # Safety
+    /// The address has to be the one of an IO-APIC, in physical memory mapped at the offset.
+    pub unsafe fn new(
+        address: PhysAddr,
+        interrupt_base: u32,
+        physical_memory_offset: u64,
+    ) -> IoApic {
+        let mut io_apic = IoApic {
+            base: physical_memory_offset + address.as_u64(),
+            interrupt_base,
+            interrupts: 0,
+        };
+        // The version register holds the index of the last redirection entry
+        io_apic.interrupts = ((io_apic.read(VERSION) >> 16) & 0xFF) + 1;
+        io_apic
+    }
+
+    fn read(&mut self, register: u32) -> u32 {
+        unsafe {
+            write_volatile((self.base + REGISTER_SELECT) as *mut u32, register);
+            read_volatile((self.base + REGISTER_WINDOW) as *const u32)
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        unsafe {
+            write_volatile((self.base + REGISTER_SELECT) as *mut u32, register);
+            write_volatile((self.base + REGISTER_WINDOW) as *mut u32, value);
+        }
+    }
+
+    fn read_entry(&mut self, index: u32) -> u64 {
+        let register = REDIRECTION_TABLE + index * 2;
+        self.read(register) as u64 | (self.read(register + 1) as u64) << 32
+    }
+
+    fn write_entry(&mut self, index: u32, entry: u64) {
+        let register = REDIRECTION_TABLE + index * 2;
+        // The high half goes first, so the entry is never unmasked with the wrong destination
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+
+    /// Whether the global system interrupt is routed by this IO-APIC.
+    pub fn handles(&self, interrupt: u32) -> bool {
+        (self.interrupt_base..self.interrupt_base + self.interrupts).contains(&interrupt)
+    }
+
+    /// Masks every interrupt of the IO-APIC.
+    pub fn mask_all(&mut self) {
+        for index in 0..self.interrupts {
+            self.write_entry(index, MASKED);
+        }
+    }
+
+    /// Routes the global system interrupt, leaving it masked.
+    pub fn redirect(&mut self, interrupt: u32, redirection: Redirection) {
+        let mut entry = redirection.vector as u64 | MASKED | (redirection.destination as u64) << 56;
+        if redirection.active_low {
+            entry |= ACTIVE_LOW;
+        }
+        if redirection.level_triggered {
+            entry |= LEVEL_TRIGGERED;
+        }
+        self.write_entry(interrupt - self.interrupt_base, entry);
+    }
+
+    pub fn set_masked(&mut self, interrupt: u32, masked: bool) {
+        let index = interrupt - self.interrupt_base;
+        let entry = self.read_entry(index);
+        let entry = if masked {
+            entry | MASKED
+        } else {
+            entry & !MASKED
+        };
+        self.write_entry(index, entry);
+    }
+}
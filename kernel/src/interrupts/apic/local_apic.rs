@@ -0,0 +1,131 @@
+use core::{
+    hint::spin_loop,
+    ptr::{read_volatile, write_volatile},
+};
+
+use x86_64::{PhysAddr, registers::model_specific::Msr};
+
+/// The MSR holding the address of the local APIC and whether it's enabled.
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+
+// The registers, as offsets from the base address
+const ID: u64 = 0x20;
+const TASK_PRIORITY: u64 = 0x80;
+const END_OF_INTERRUPT: u64 = 0xB0;
+const SPURIOUS_INTERRUPT_VECTOR: u64 = 0xF0;
+const INTERRUPT_COMMAND_LOW: u64 = 0x300;
+const INTERRUPT_COMMAND_HIGH: u64 = 0x310;
+const LVT_TIMER: u64 = 0x320;
+const TIMER_INITIAL_COUNT: u64 = 0x380;
+const TIMER_CURRENT_COUNT: u64 = 0x390;
+const TIMER_DIVIDE_CONFIGURATION: u64 = 0x3E0;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+
+// The bits of the interrupt command register
+const DELIVERY_MODE_FIXED: u32 = 0b000 << 8;
+const DELIVERY_MODE_INIT: u32 = 0b101 << 8;
+const DELIVERY_MODE_STARTUP: u32 = 0b110 << 8;
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+
+// The bits of the timer's local vector table entry
+const TIMER_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+/// The timer counts down at the bus frequency divided by 16.
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+/// The local APIC of the processor running the code.
+///
+/// Every processor sees its own local APIC at the same address, so this is shared between them.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApic {
+    /// The virtual address of the registers.
+    base: u64,
+}
+
+impl LocalApic {
+    /// # Safety
+    /// The address has to be the one of the local APICs, in physical memory mapped at the offset.
+    pub unsafe fn new(address: PhysAddr, physical_memory_offset: u64) -> LocalApic {
+        LocalApic {
+            base: physical_memory_offset + address.as_u64(),
+        }
+    }
+
+    fn read(&self, register: u64) -> u32 {
+        unsafe { read_volatile((self.base + register) as *const u32) }
+    }
+
+    fn write(&self, register: u64, value: u32) {
+        unsafe { write_volatile((self.base + register) as *mut u32, value) };
+    }
+
+    /// Enables the local APIC of the running processor, accepting every interrupt priority.
+    ///
+    /// # Safety
+    /// The spurious interrupt vector needs a handler, which doesn't signal the end of the interrupt.
+    pub unsafe fn enable(&self, spurious_vector: u8) {
+        let mut apic_base = Msr::new(IA32_APIC_BASE);
+        unsafe { apic_base.write(apic_base.read() | APIC_GLOBAL_ENABLE) };
+        self.write(TASK_PRIORITY, 0);
+        self.write(
+            SPURIOUS_INTERRUPT_VECTOR,
+            SOFTWARE_ENABLE | spurious_vector as u32,
+        );
+    }
+
+    /// Returns the APIC ID of the running processor.
+    pub fn id(&self) -> u8 {
+        (self.read(ID) >> 24) as u8
+    }
+
+    /// Signals the end of the interrupt being handled on the running processor.
+    pub fn end_of_interrupt(&self) {
+        self.write(END_OF_INTERRUPT, 0);
+    }
+
+    /// Sends an inter-processor interrupt, waiting for the local APIC to deliver it.
+    fn send_ipi(&self, apic_id: u8, command: u32) {
+        self.write(INTERRUPT_COMMAND_HIGH, (apic_id as u32) << 24);
+        self.write(INTERRUPT_COMMAND_LOW, command);
+        while self.read(INTERRUPT_COMMAND_LOW) & DELIVERY_PENDING != 0 {
+            spin_loop();
+        }
+    }
+
+    /// Sends an IPI raising the interrupt vector on the processor.
+    pub fn send_fixed(&self, apic_id: u8, vector: u8) {
+        self.send_ipi(apic_id, DELIVERY_MODE_FIXED | LEVEL_ASSERT | vector as u32);
+    }
+
+    /// Sends an INIT IPI, resetting the processor to wait for a startup IPI.
+    pub fn send_init(&self, apic_id: u8) {
+        self.send_ipi(apic_id, DELIVERY_MODE_INIT | LEVEL_ASSERT);
+    }
+
+    /// Sends a startup IPI, starting the processor in real mode at the beginning of the page (below 1MiB).
+    pub fn send_startup(&self, apic_id: u8, page: u8) {
+        self.send_ipi(apic_id, DELIVERY_MODE_STARTUP | LEVEL_ASSERT | page as u32);
+    }
+
+    /// Returns how far the timer counts while the function runs, without raising interrupts.
+    pub fn measure_timer(&self, function: impl FnOnce()) -> u32 {
+        self.write(TIMER_DIVIDE_CONFIGURATION, TIMER_DIVIDE_BY_16);
+        self.write(LVT_TIMER, TIMER_MASKED);
+        self.write(TIMER_INITIAL_COUNT, u32::MAX);
+        function();
+        let elapsed = u32::MAX - self.read(TIMER_CURRENT_COUNT);
+        // Stopping the timer
+        self.write(TIMER_INITIAL_COUNT, 0);
+        elapsed
+    }
+
+    /// Starts the timer of the running processor, raising the interrupt every time it counted down from the initial count.
+    pub fn start_periodic_timer(&self, vector: u8, initial_count: u32) {
+        self.write(TIMER_DIVIDE_CONFIGURATION, TIMER_DIVIDE_BY_16);
+        self.write(LVT_TIMER, TIMER_PERIODIC | vector as u32);
+        self.write(TIMER_INITIAL_COUNT, initial_count);
+    }
+}
@@ -0,0 +1,222 @@
+use alloc::vec::Vec;
+use core::{
+    arch::x86_64::__cpuid,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use internal_utils::{
+    acpi::{InterruptFlags, Madt, MadtEntry},
+    clocks::busy_wait_microseconds,
+    kernel_information::KERNEL_INFORMATION,
+    logln,
+};
+use spin::{Mutex, Once};
+
+use crate::interrupts::pic::InterruptIndex;
+
+mod io_apic;
+use io_apic::{IoApic, Redirection};
+mod local_apic;
+use local_apic::LocalApic;
+
+/// The vector of the interrupts the local APIC raises when an interrupt goes away before being accepted.
+pub const SPURIOUS_INTERRUPT_VECTOR: u8 = 0xFF;
+
+/// The period of the PIT's timer interrupt with its default divisor (65536), in microseconds.
+const TIMER_PERIOD: u64 = 54_925;
+/// How long the local APIC timer is measured against the PIT, in microseconds.
+const TIMER_CALIBRATION_TIME: u64 = 10_000;
+
+/// The count the local APIC timers start from to tick as often as the PIT, 0 until it's calibrated.
+static TIMER_INITIAL_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// The interrupt controllers replacing the PICs.
+struct Apic {
+    local_apic: LocalApic,
+    io_apics: Mutex<Vec<IoApic>>,
+    /// The global system interrupts of the kernel's interrupts, as the ISA IRQs might be remapped.
+    global_interrupts: Vec<(u8, u32)>,
+}
+
+static APIC: Once<Apic> = Once::new();
+
+/// Whether the interrupts go through the APICs instead of the PICs.
+pub fn is_active() -> bool {
+    APIC.is_completed()
+}
+
+/// Routes the kernel's interrupts through the local APIC and the IO-APICs described in the MADT.
+///
+/// Returns `false` if the system has no APIC, leaving the PICs in charge.
+/// The routed interrupts stay masked until they're enabled.
+pub fn init() -> bool {
+    let supports_apic = __cpuid(1).edx & (1 << 9) != 0;
+    let Some(kernel_info) = KERNEL_INFORMATION.get() else {
+        return false;
+    };
+    let pmo = kernel_info.physical_memory_offset;
+    let Some(madt) = kernel_info
+        .acpi
+        .filter(|_| supports_apic)
+        .and_then(|tables| tables.madt())
+    else {
+        return false;
+    };
+
+    let mut io_apics = madt
+        .entries()
+        .filter_map(|entry| match entry {
+            MadtEntry::IoApic {
+                address,
+                interrupt_base,
+                ..
+            } => Some(unsafe { IoApic::new(address, interrupt_base, pmo) }),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if io_apics.is_empty() {
+        return false;
+    }
+    // The bootstrap processor's timer counts the time and wakes up the sleeping threads,
+    // so the APICs only take over if they can route it
+    let (timer_interrupt, _) = find_global_interrupt(&madt, InterruptIndex::Timer.irq_line());
+    if !io_apics
+        .iter()
+        .any(|io_apic| io_apic.handles(timer_interrupt))
+    {
+        logln!("[WARN] No IO-APIC handles the timer IRQ");
+        return false;
+    }
+    io_apics.iter_mut().for_each(IoApic::mask_all);
+
+    let local_apic = unsafe { LocalApic::new(madt.local_apic_address(), pmo) };
+    unsafe { local_apic.enable(SPURIOUS_INTERRUPT_VECTOR) };
+
+    let mut global_interrupts = Vec::new();
+    for interrupt in InterruptIndex::ALL {
+        let irq = interrupt.irq_line();
+        let (global_interrupt, flags) = find_global_interrupt(&madt, irq);
+        let Some(io_apic) = io_apics
+            .iter_mut()
+            .find(|io_apic| io_apic.handles(global_interrupt))
+        else {
+            logln!("[WARN] No IO-APIC handles IRQ {}", irq);
+            continue;
+        };
+        io_apic.redirect(
+            global_interrupt,
+            Redirection {
+                vector: interrupt.as_u8(),
+                destination: local_apic.id(),
+                active_low: flags.is_some_and(|flags| flags.active_low()),
+                level_triggered: flags.is_some_and(|flags| flags.level_triggered()),
+            },
+        );
+        global_interrupts.push((interrupt.as_u8(), global_interrupt));
+    }
+
+    APIC.call_once(|| Apic {
+        local_apic,
+        io_apics: Mutex::new(io_apics),
+        global_interrupts,
+    });
+    true
+}
+
+/// Returns the global system interrupt of the ISA IRQ, and its flags if the MADT overrides it.
+fn find_global_interrupt(madt: &Madt, irq: u8) -> (u32, Option<InterruptFlags>) {
+    // The ISA IRQs are identity mapped, unless the MADT overrides them
+    madt.entries()
+        .find_map(|entry| match entry {
+            MadtEntry::InterruptSourceOverride {
+                irq: source,
+                global_interrupt,
+                flags,
+            } if source == irq => Some((global_interrupt, Some(flags))),
+            _ => None,
+        })
+        .unwrap_or((irq as u32, None))
+}
+
+/// Unmasks the interrupt in the IO-APIC routing it.
+pub fn enable_irq(interrupt: InterruptIndex) {
+    let apic = APIC.get().expect("The APICs have to be initialized");
+    let Some(&(_, global_interrupt)) = apic
+        .global_interrupts
+        .iter()
+        .find(|(vector, _)| *vector == interrupt.as_u8())
+    else {
+        return;
+    };
+    if let Some(io_apic) = apic
+        .io_apics
+        .lock()
+        .iter_mut()
+        .find(|io_apic| io_apic.handles(global_interrupt))
+    {
+        io_apic.set_masked(global_interrupt, false);
+    }
+}
+
+/// Signals the end of the interrupt to the local APIC.
+pub fn end_of_interrupt() {
+    if let Some(apic) = APIC.get() {
+        apic.local_apic.end_of_interrupt();
+    }
+}
+
+/// Returns the APIC ID of the running processor, or `None` if the interrupts don't go through the APICs.
+pub fn local_apic_id() -> Option<u8> {
+    APIC.get().map(|apic| apic.local_apic.id())
+}
+
+/// Enables the local APIC of an application processor, as they start with it disabled.
+pub fn enable_local_apic() {
+    if let Some(apic) = APIC.get() {
+        unsafe { apic.local_apic.enable(SPURIOUS_INTERRUPT_VECTOR) };
+    }
+}
+
+/// Sends an IPI raising the interrupt vector on the processor.
+pub fn send_ipi(apic_id: u8, vector: u8) {
+    let apic = APIC.get().expect("The APICs have to be initialized");
+    apic.local_apic.send_fixed(apic_id, vector);
+}
+
+/// Sends an INIT IPI to the processor, which then waits for a startup IPI.
+pub fn send_init_ipi(apic_id: u8) {
+    let apic = APIC.get().expect("The APICs have to be initialized");
+    apic.local_apic.send_init(apic_id);
+}
+
+/// Sends a startup IPI to the processor, starting it in real mode at the beginning of the page.
+pub fn send_startup_ipi(apic_id: u8, page: u8) {
+    let apic = APIC.get().expect("The APICs have to be initialized");
+    apic.local_apic.send_startup(apic_id, page);
+}
+
+/// Measures the local APIC timer against the PIT, so the application processors' timers can tick
+/// as often as the PIT does on the bootstrap processor.
+pub fn calibrate_timer() {
+    let apic = APIC.get().expect("The APICs have to be initialized");
+    let elapsed = apic
+        .local_apic
+        .measure_timer(|| busy_wait_microseconds(TIMER_CALIBRATION_TIME));
+    let initial_count = elapsed as u64 * TIMER_PERIOD / TIMER_CALIBRATION_TIME;
+    TIMER_INITIAL_COUNT.store(
+        initial_count.clamp(1, u32::MAX as u64) as u32,
+        Ordering::Relaxed,
+    );
+}
+
+/// Starts the local APIC timer of an application processor, which raises the timer interrupt
+/// like the PIT does on the bootstrap processor.
+pub fn start_local_timer() {
+    let initial_count = TIMER_INITIAL_COUNT.load(Ordering::Relaxed);
+    if let Some(apic) = APIC.get()
+        && initial_count != 0
+    {
+        apic.local_apic
+            .start_periodic_timer(InterruptIndex::Timer.as_u8(), initial_count);
+    }
+}
@@ -1,9 +1,7 @@
-use internal_utils::logln;
 use x86_64::structures::idt::InterruptStackFrame;
 
-use crate::hlt_loop_hard;
+use super::{CpuException, handle_exception};
 
-pub extern "x86-interrupt" fn debug_handler(_: InterruptStackFrame) {
-    logln!("DEBUG");
-    hlt_loop_hard();
+pub extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    handle_exception(CpuException::Debug, &stack_frame, None);
 }
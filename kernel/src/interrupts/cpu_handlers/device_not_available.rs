@@ -1,9 +1,12 @@
-use internal_utils::logln;
 use x86_64::structures::idt::InterruptStackFrame;
 
-use crate::hlt_loop_hard;
+use super::{CpuException, handle_exception};
+use crate::processes::handle_fpu_trap;
 
-pub extern "x86-interrupt" fn device_not_available_handler(_stack_frame: InterruptStackFrame) {
-    logln!("DEVICE NOT AVAILABLE\n{:#?}", _stack_frame);
-    hlt_loop_hard();
+pub extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
+    // The FPU is disabled after a context switch, until the thread uses it and gets its state loaded
+    if handle_fpu_trap() {
+        return;
+    }
+    handle_exception(CpuException::DeviceNotAvailable, &stack_frame, None);
 }
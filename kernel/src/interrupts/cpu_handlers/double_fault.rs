@@ -1,12 +1,21 @@
+use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::InterruptStackFrame;
 
+use crate::memory::guarded_stack::find_guard_page_owner;
+
+/// Runs on its own interrupt stack, so it works even when the faulting stack has overflowed -
+/// touching a guard page faults, and pushing the page fault's frame onto the same stack faults again.
+///
 /// # Safety
 ///
-/// This should never do stack heavy operations because this handler has a separate stack that has no stack guard page and thus could corrupt the data after it.
+/// This should never do stack heavy operations, as its stack is only as big as the other guarded stacks.
 pub extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    if let Some(owner) = Cr2::read().ok().and_then(find_guard_page_owner) {
+        panic!("stack overflow in {}\n{:#?}", owner, stack_frame);
+    }
     panic!(
         "DOUBLE FAULT (error {:#?})\n{:#?}",
         _error_code, stack_frame
@@ -0,0 +1,136 @@
+use core::fmt::Display;
+
+use internal_utils::{clocks::get_current_tick, logln};
+use rost_user::abi::EXCEPTION_EXIT_CODE_BASE;
+use x86_64::{PrivilegeLevel, structures::idt::InterruptStackFrame};
+
+use crate::{
+    interrupts::crash_records::{CrashRecord, record_crash},
+    processes::{memory_areas::MemoryAccess, run_processes, running_thread, terminate_process},
+};
+
+/// The CPU exceptions a thread can cause by the code it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuException {
+    DivideError,
+    Debug,
+    Breakpoint,
+    InvalidOpcode,
+    DeviceNotAvailable,
+    InvalidTss,
+    SegmentNotPresent,
+    StackSegmentFault,
+    GeneralProtectionFault,
+    PageFault {
+        /// The address the instruction tried to access (CR2).
+        address: u64,
+        access: MemoryAccess,
+        /// Whether the page was present, so the access broke its protection.
+        protection_violation: bool,
+    },
+    X87FloatingPoint,
+    AlignmentCheck,
+    SimdFloatingPoint,
+}
+
+impl CpuException {
+    /// Returns the interrupt vector of the exception.
+    pub fn vector(&self) -> u64 {
+        match self {
+            CpuException::DivideError => 0,
+            CpuException::Debug => 1,
+            CpuException::Breakpoint => 3,
+            CpuException::InvalidOpcode => 6,
+            CpuException::DeviceNotAvailable => 7,
+            CpuException::InvalidTss => 10,
+            CpuException::SegmentNotPresent => 11,
+            CpuException::StackSegmentFault => 12,
+            CpuException::GeneralProtectionFault => 13,
+            CpuException::PageFault { .. } => 14,
+            CpuException::X87FloatingPoint => 16,
+            CpuException::AlignmentCheck => 17,
+            CpuException::SimdFloatingPoint => 19,
+        }
+    }
+}
+
+impl Display for CpuException {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CpuException::DivideError => write!(f, "DIVIDE ERROR"),
+            CpuException::Debug => write!(f, "DEBUG"),
+            CpuException::Breakpoint => write!(f, "BREAKPOINT"),
+            CpuException::InvalidOpcode => write!(f, "INVALID OPCODE"),
+            CpuException::DeviceNotAvailable => write!(f, "DEVICE NOT AVAILABLE"),
+            CpuException::InvalidTss => write!(f, "INVALID TSS"),
+            CpuException::SegmentNotPresent => write!(f, "SEGMENT NOT PRESENT"),
+            CpuException::StackSegmentFault => write!(f, "STACK SEGMENT FAULT"),
+            CpuException::GeneralProtectionFault => write!(f, "GP FAULT"),
+            CpuException::PageFault {
+                address,
+                access,
+                protection_violation,
+            } => write!(
+                f,
+                "PAGE FAULT ({:?} access at {:#X}, {})",
+                access,
+                address,
+                if *protection_violation {
+                    "protection violation"
+                } else {
+                    "page not present"
+                }
+            ),
+            CpuException::X87FloatingPoint => write!(f, "X87 FLOATING POINT"),
+            CpuException::AlignmentCheck => write!(f, "ALIGNMENT CHECK"),
+            CpuException::SimdFloatingPoint => write!(f, "SIMD FLOATING POINT"),
+        }
+    }
+}
+
+/// Handles an exception the handlers couldn't resolve themselves.
+///
+/// An exception caused by user mode code terminates the process of the running thread,
+/// with the vector added to `EXCEPTION_EXIT_CODE_BASE` as its exit code, and leaves a crash record.
+/// The other processes keep being scheduled.
+///
+/// An exception in kernel mode is a kernel bug, so it panics.
+pub fn handle_exception(
+    exception: CpuException,
+    stack_frame: &InterruptStackFrame,
+    error_code: Option<u64>,
+) -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    if stack_frame.code_segment.rpl() != PrivilegeLevel::Ring3 {
+        match error_code {
+            Some(error_code) => panic!(
+                "{} in kernel mode (error {:#X})\n{:#?}",
+                exception, error_code, stack_frame
+            ),
+            None => panic!("{} in kernel mode\n{:#?}", exception, stack_frame),
+        }
+    }
+
+    let thread = running_thread().expect("A user mode exception needs a running thread");
+    let (thread_id, process) = {
+        let thread = thread.lock();
+        (thread.id, thread.process.clone())
+    };
+    drop(thread);
+
+    let record = CrashRecord {
+        tick: get_current_tick(),
+        process_id: process.lock().id,
+        thread_id,
+        exception,
+        instruction: stack_frame.instruction_pointer,
+        error_code,
+    };
+    logln!("{}, terminating the process", record);
+    record_crash(record);
+
+    terminate_process(&process, EXCEPTION_EXIT_CODE_BASE + exception.vector());
+    drop(process);
+    run_processes();
+}
@@ -1,9 +1,7 @@
-use internal_utils::logln;
 use x86_64::structures::idt::InterruptStackFrame;
 
-use crate::hlt_loop_hard;
+use super::{CpuException, handle_exception};
 
-pub extern "x86-interrupt" fn invalid_opcode_handler(_stack_frame: InterruptStackFrame) {
-    logln!("INVALID OPCODE\n{:#?}", _stack_frame);
-    hlt_loop_hard();
+pub extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    handle_exception(CpuException::InvalidOpcode, &stack_frame, None);
 }
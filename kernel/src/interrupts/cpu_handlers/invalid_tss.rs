@@ -1,16 +1,10 @@
-use internal_utils::logln;
 use x86_64::structures::idt::InterruptStackFrame;
 
-use crate::hlt_loop_hard;
+use super::{CpuException, handle_exception};
 
 pub extern "x86-interrupt" fn invalid_tss_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: u64,
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
 ) {
-    logln!(
-        "INVALID TSS (error {:#?})\n{:#?}",
-        _error_code,
-        _stack_frame
-    );
-    hlt_loop_hard();
+    handle_exception(CpuException::InvalidTss, &stack_frame, Some(error_code));
 }
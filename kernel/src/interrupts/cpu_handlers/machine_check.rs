@@ -0,0 +1,10 @@
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// The hardware reported an error it couldn't correct, so the state of the system can't be trusted.
+///
+/// # Safety
+///
+/// This should never do stack heavy operations, as it shares its stack with the NMI handler.
+pub extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    panic!("MACHINE CHECK\n{:#?}", stack_frame);
+}
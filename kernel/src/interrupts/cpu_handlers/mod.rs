@@ -1,3 +1,6 @@
+mod exception;
+pub use exception::{CpuException, handle_exception};
+
 mod breakpoint;
 pub use breakpoint::breakpoint_handler;
 
@@ -36,3 +39,12 @@ pub use stack_segment_fault::stack_segment_fault_handler;
 
 mod debug;
 pub use debug::debug_handler;
+
+mod x87_floating_point;
+pub use x87_floating_point::x87_floating_point_handler;
+
+mod simd_floating_point;
+pub use simd_floating_point::simd_floating_point_handler;
+
+mod machine_check;
+pub use machine_check::machine_check_handler;
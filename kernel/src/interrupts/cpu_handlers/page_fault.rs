@@ -1,17 +1,74 @@
-use internal_utils::logln;
+use x86_64::VirtAddr;
+use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::InterruptStackFrame;
 use x86_64::structures::idt::PageFaultErrorCode;
 
-use crate::hlt_loop_hard;
+use super::{CpuException, handle_exception};
+use crate::memory::guarded_stack::find_guard_page_owner;
+use crate::processes::memory_areas::MemoryAccess;
+use crate::processes::running_thread;
+use crate::smp::tlb::{AddressSpace, TlbFlush};
 
 pub extern "x86-interrupt" fn page_fault_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: PageFaultErrorCode,
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
 ) {
-    use x86_64::registers::control::Cr2;
     x86_64::instructions::interrupts::disable();
 
-    logln!("PAGE FAULT (error {:#?})\n{:#?}", _error_code, _stack_frame);
-    logln!("Page: {:X?}", Cr2::read_raw());
-    hlt_loop_hard();
+    let address = Cr2::read_raw();
+    let access = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        MemoryAccess::Execute
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        MemoryAccess::Write
+    } else {
+        MemoryAccess::Read
+    };
+
+    if error_code.contains(PageFaultErrorCode::USER_MODE)
+        && resolve_user_page_fault(address, access)
+    {
+        return;
+    }
+    // Kernel code touching a guard page has overflowed its stack
+    if !error_code.contains(PageFaultErrorCode::USER_MODE)
+        && let Some(owner) = VirtAddr::try_new(address)
+            .ok()
+            .and_then(find_guard_page_owner)
+    {
+        panic!("stack overflow in {}\n{:#?}", owner, stack_frame);
+    }
+    handle_exception(
+        CpuException::PageFault {
+            address,
+            access,
+            protection_violation: error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION),
+        },
+        &stack_frame,
+        Some(error_code.bits()),
+    );
+}
+
+/// Maps the page the running user process touched for the first time (or copies a copy-on-write page).
+///
+/// Returns `false` if the process isn't allowed the access to the address.
+fn resolve_user_page_fault(address: u64, access: MemoryAccess) -> bool {
+    let Ok(address) = VirtAddr::try_new(address) else {
+        return false;
+    };
+    let process = running_thread()
+        .expect("A user mode page fault needs a running thread")
+        .lock()
+        .process
+        .clone();
+    let (resolved, flush) = {
+        let process = process.lock();
+        let mut flush = TlbFlush::new(AddressSpace::User(process.cr3.0));
+        (
+            process.resolve_page_fault(address, access, &mut flush),
+            flush,
+        )
+    };
+    // The other processors flush in an interrupt, so the process can't be locked meanwhile
+    flush.shoot_down();
+    resolved
 }
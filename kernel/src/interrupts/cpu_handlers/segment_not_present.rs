@@ -1,16 +1,14 @@
-use internal_utils::logln;
 use x86_64::structures::idt::InterruptStackFrame;
 
-use crate::hlt_loop_hard;
+use super::{CpuException, handle_exception};
 
 pub extern "x86-interrupt" fn segment_not_present_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: u64,
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
 ) {
-    logln!(
-        "SEGMENT NOT PRESENT (error {:#?})\n{:#?}",
-        _error_code,
-        _stack_frame
+    handle_exception(
+        CpuException::SegmentNotPresent,
+        &stack_frame,
+        Some(error_code),
     );
-    hlt_loop_hard();
 }
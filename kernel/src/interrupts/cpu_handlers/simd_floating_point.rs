@@ -0,0 +1,7 @@
+use x86_64::structures::idt::InterruptStackFrame;
+
+use super::{CpuException, handle_exception};
+
+pub extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    handle_exception(CpuException::SimdFloatingPoint, &stack_frame, None);
+}
@@ -1,16 +1,14 @@
-use internal_utils::logln;
 use x86_64::structures::idt::InterruptStackFrame;
 
-use crate::hlt_loop_hard;
+use super::{CpuException, handle_exception};
 
 pub extern "x86-interrupt" fn stack_segment_fault_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: u64,
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
 ) {
-    logln!(
-        "STACK SEGMENT FAULT (error {:#?})\n{:#?}",
-        _error_code,
-        _stack_frame
+    handle_exception(
+        CpuException::StackSegmentFault,
+        &stack_frame,
+        Some(error_code),
     );
-    hlt_loop_hard();
 }
@@ -0,0 +1,61 @@
+use core::fmt::Display;
+
+use alloc::{collections::VecDeque, vec::Vec};
+use spin::Mutex;
+use x86_64::{VirtAddr, instructions::interrupts::without_interrupts};
+
+use super::cpu_handlers::CpuException;
+
+/// How many crash records are kept - the oldest ones are dropped first.
+const MAX_CRASH_RECORDS: usize = 32;
+
+/// A user process the kernel terminated because of a CPU exception.
+#[derive(Debug, Clone, Copy)]
+pub struct CrashRecord {
+    /// The tick the process crashed at.
+    pub tick: u64,
+    pub process_id: u64,
+    pub thread_id: u64,
+    pub exception: CpuException,
+    /// The address of the faulting instruction.
+    pub instruction: VirtAddr,
+    /// The error code the CPU pushed, if the exception has one.
+    pub error_code: Option<u64>,
+}
+
+impl Display for CrashRecord {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "[{}] process {}, thread {}: {} at RIP {:#X}",
+            self.tick,
+            self.process_id,
+            self.thread_id,
+            self.exception,
+            self.instruction.as_u64()
+        )?;
+        if let Some(error_code) = self.error_code {
+            write!(f, " (error {:#X})", error_code)?;
+        }
+        Ok(())
+    }
+}
+
+/// The latest crashes of user processes, oldest first.
+static CRASH_RECORDS: Mutex<VecDeque<CrashRecord>> = Mutex::new(VecDeque::new());
+
+/// Stores the record, dropping the oldest one if there are too many.
+pub(super) fn record_crash(record: CrashRecord) {
+    without_interrupts(|| {
+        let mut records = CRASH_RECORDS.lock();
+        if records.len() == MAX_CRASH_RECORDS {
+            records.pop_front();
+        }
+        records.push_back(record);
+    });
+}
+
+/// Returns the latest crashes of user processes, oldest first.
+pub fn get_crash_records() -> Vec<CrashRecord> {
+    without_interrupts(|| CRASH_RECORDS.lock().iter().copied().collect())
+}
@@ -1,3 +1,10 @@
+use core::{
+    mem, ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use alloc::boxed::Box;
+
 use internal_utils::logln;
 use lazy_static::lazy_static;
 use x86_64::instructions::tables::load_tss;
@@ -6,96 +13,82 @@ use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::{PrivilegeLevel, VirtAddr};
 
+use crate::{
+    memory::guarded_stack::{GuardedStack, StackOwner},
+    smp::{MAX_CPUS, current_cpu_index},
+};
+
 /// the interrupt stack table index of the stack used for double faults
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 pub const NMI_IST_INDEX: u16 = 1;
 pub const GPF_IST_INDEX: u16 = 2;
 pub const DEBUG_IST_INDEX: u16 = 3;
 
-const STACK_SIZE: usize = 4096;
-#[repr(C, align(16))]
-struct Stack([u8; STACK_SIZE]);
-
-lazy_static! {
-    /// The TSS of the OS.
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-
-        // Stack used when an exception happens in user mode
-        tss.privilege_stack_table[0] = {
-
-            static mut STACK: Stack = Stack([0; STACK_SIZE]);
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-
-            // returns the highest address of the stack because the stack grows downwards
-            stack_start + STACK_SIZE as u64 - 8
-        };
-        tss.privilege_stack_table[1] = tss.privilege_stack_table[0];
-        tss.privilege_stack_table[2] = tss.privilege_stack_table[0];
-
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-
-            static mut STACK: Stack = Stack([0; STACK_SIZE]);
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-
-            stack_start + STACK_SIZE as u64
-        };
-
-        tss.interrupt_stack_table[NMI_IST_INDEX as usize] = {
-
-            static mut STACK: Stack = Stack([0; STACK_SIZE]);
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-
-            stack_start + STACK_SIZE as u64
-        };
-
-        tss.interrupt_stack_table[GPF_IST_INDEX as usize] = {
-
-            static mut STACK: Stack = Stack([0; STACK_SIZE]);
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-
-            stack_start + STACK_SIZE as u64
-        };
-
-        tss.interrupt_stack_table[DEBUG_IST_INDEX as usize] = {
-
-            static mut STACK: Stack = Stack([0; STACK_SIZE]);
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-
-            stack_start + STACK_SIZE as u64
-        };
-
-        tss
-    };
-
-    pub static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-
-        let kernel_code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let kernel_data_selector = gdt.append(Descriptor::kernel_data_segment());
+/// The TSS of the bootstrap processor.
+///
+/// It's mutable, as the dispatcher points the ring 0 stack to the kernel stack of the thread it runs.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// The TSS of every processor, by CPU index - the application processors allocate theirs when they start.
+static CPU_TSS: [AtomicPtr<TaskStateSegment>; MAX_CPUS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_CPUS];
+
+/// Allocates a guarded stack for the TSS, returning its highest address.
+///
+/// The stack is used for as long as the kernel runs, so it's never freed.
+fn allocate_tss_stack(name: &'static str) -> VirtAddr {
+    let stack = GuardedStack::new().expect("Not enough memory for the interrupt stacks");
+    stack.set_owner(StackOwner::Interrupt(name));
+    let stack_top = stack.top();
+    mem::forget(stack);
+    stack_top
+}
 
-        let user_data_selector = gdt.append(Descriptor::user_data_segment());
-        let user_code_selector = gdt.append(Descriptor::user_code_segment());
+/// Sets up the stacks of the TSS.
+///
+/// Every stack has an unmapped guard page below it, so overflowing one causes a double fault.
+fn init_tss(tss: &mut TaskStateSegment) {
+    // Stack used when an exception happens in user mode, before any thread has been dispatched
+    tss.privilege_stack_table[0] = allocate_tss_stack("privilege") - 8u64;
+    tss.privilege_stack_table[1] = tss.privilege_stack_table[0];
+    tss.privilege_stack_table[2] = tss.privilege_stack_table[0];
+
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = allocate_tss_stack("double fault");
+    tss.interrupt_stack_table[NMI_IST_INDEX as usize] = allocate_tss_stack("NMI");
+    tss.interrupt_stack_table[GPF_IST_INDEX as usize] = allocate_tss_stack("GP fault");
+    tss.interrupt_stack_table[DEBUG_IST_INDEX as usize] = allocate_tss_stack("debug");
+}
 
-        let mut tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
-        tss_selector.set_rpl(PrivilegeLevel::Ring0);
+/// Creates a GDT with the TSS.
+///
+/// The segments are always appended in the same order, so every processor's GDT has the same selectors.
+fn create_gdt(tss: *const TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+
+    let kernel_code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let kernel_data_selector = gdt.append(Descriptor::kernel_data_segment());
+
+    let user_data_selector = gdt.append(Descriptor::user_data_segment());
+    let user_code_selector = gdt.append(Descriptor::user_code_segment());
+
+    let mut tss_selector = gdt.append(unsafe { Descriptor::tss_segment_unchecked(tss) });
+    tss_selector.set_rpl(PrivilegeLevel::Ring0);
+
+    (
+        gdt,
+        Selectors {
+            kernel_code_selector,
+            kernel_data_selector,
+            user_code_selector,
+            user_data_selector,
+            tss_selector,
+        },
+    )
+}
 
-        (
-            gdt,
-            Selectors {
-                kernel_code_selector,
-                kernel_data_selector,
-                user_code_selector,
-                user_data_selector,
-                tss_selector
-            },
-        )
-    };
+lazy_static! {
+    /// The GDT of the bootstrap processor, whose selectors are the ones of every processor.
+    pub static ref GDT: (GlobalDescriptorTable, Selectors) = create_gdt(&raw const TSS);
 }
 
 pub struct Selectors {
@@ -106,19 +99,46 @@ pub struct Selectors {
     tss_selector: SegmentSelector,
 }
 
+/// Loads the kernel segments and the TSS of the GDT that was just loaded.
+fn load_segments(selectors: &Selectors) {
+    unsafe {
+        CS::set_reg(selectors.kernel_code_selector);
+        SS::set_reg(selectors.kernel_data_selector);
+        DS::set_reg(selectors.kernel_data_selector);
+        ES::set_reg(selectors.kernel_data_selector);
+        load_tss(selectors.tss_selector);
+    }
+}
+
 /// Initialises the GDT and TSS.
 pub fn reload_gdt() {
     logln!("[   ---{:^15}---   ]", "INTERRUPTS");
     logln!("Loading GDT and segment registers");
+    #[allow(static_mut_refs)]
+    init_tss(unsafe { &mut TSS });
+    CPU_TSS[0].store(&raw mut TSS, Ordering::Release);
     GDT.0.load();
     logln!("GDT loaded");
-    let selector = &GDT.1;
-    unsafe {
-        CS::set_reg(selector.kernel_code_selector);
-        SS::set_reg(selector.kernel_data_selector);
-        DS::set_reg(selector.kernel_data_selector);
-        ES::set_reg(selector.kernel_data_selector);
-        load_tss(selector.tss_selector);
-    }
+    load_segments(&GDT.1);
     logln!("Segment registers loaded");
 }
+
+/// Loads a GDT and TSS of its own on an application processor, so it has its own interrupt stacks.
+pub fn load_cpu_gdt(cpu_index: usize) {
+    let tss = Box::leak(Box::new(TaskStateSegment::new()));
+    init_tss(tss);
+    let tss: *mut TaskStateSegment = tss;
+    CPU_TSS[cpu_index].store(tss, Ordering::Release);
+
+    let (gdt, selectors) = create_gdt(tss);
+    Box::leak(Box::new(gdt)).load();
+    load_segments(&selectors);
+}
+
+/// Sets the stack the running processor switches to when an interrupt arrives while running in ring 3.
+pub fn set_privilege_stack(stack_top: VirtAddr) {
+    let tss = CPU_TSS[current_cpu_index()].load(Ordering::Acquire);
+    if let Some(tss) = unsafe { tss.as_mut() } {
+        tss.privilege_stack_table[0] = stack_top;
+    }
+}
@@ -3,18 +3,21 @@ use lazy_static::lazy_static;
 use x86_64::{VirtAddr, structures::idt::InterruptDescriptorTable};
 
 use crate::interrupts::{
+    apic::SPURIOUS_INTERRUPT_VECTOR,
     cpu_handlers::{
         alignment_check_handler, breakpoint_handler, debug_handler, device_not_available_handler,
         divide_error_handler, double_fault_handler, general_protection_fault_handler,
-        invalid_opcode_handler, invalid_tss_handler, nmi_handler, page_fault_handler,
-        segment_not_present_handler, stack_segment_fault_handler,
+        invalid_opcode_handler, invalid_tss_handler, machine_check_handler, nmi_handler,
+        page_fault_handler, segment_not_present_handler, simd_floating_point_handler,
+        stack_segment_fault_handler, x87_floating_point_handler,
     },
     pic::InterruptIndex,
     pic_handlers::{
         ata_primary_interrupt_handler, ata_secondary_interrupt_handler, keyboard_interrupt_handler,
-        timer_interrupt_handler,
+        spurious_interrupt_handler, timer_interrupt_handler, tlb_shootdown_interrupt_handler,
     },
 };
+use crate::smp::tlb::TLB_SHOOTDOWN_VECTOR;
 
 lazy_static! {
     /// The IDT used by the OS.
@@ -36,6 +39,11 @@ lazy_static! {
                 .set_handler_fn(nmi_handler)
                 .set_stack_index(crate::interrupts::gdt::NMI_IST_INDEX);
 
+            // Like NMIs, machine checks can interrupt anything
+            idt.machine_check
+                .set_handler_fn(machine_check_handler)
+                .set_stack_index(crate::interrupts::gdt::NMI_IST_INDEX);
+
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(crate::interrupts::gdt::DOUBLE_FAULT_IST_INDEX);
@@ -59,11 +67,15 @@ lazy_static! {
             idt.segment_not_present.set_handler_fn(segment_not_present_handler);
 
             idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+
+            idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+
+            idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
         }
 
-        // ##################
-        // # PIC interrupts #
-        // ##################
+        // ###########################
+        // # PIC and APIC interrupts #
+        // ###########################
         unsafe {
             idt[InterruptIndex::Timer.as_u8()]
                 .set_handler_addr(VirtAddr::from_ptr(timer_interrupt_handler as *const u8));
@@ -78,6 +90,9 @@ lazy_static! {
         idt[InterruptIndex::AtaSecondary.as_u8()]
             .set_handler_fn(ata_secondary_interrupt_handler);
 
+        idt[SPURIOUS_INTERRUPT_VECTOR].set_handler_fn(spurious_interrupt_handler);
+
+        idt[TLB_SHOOTDOWN_VECTOR].set_handler_fn(tlb_shootdown_interrupt_handler);
 
         idt
     };
@@ -85,6 +100,11 @@ lazy_static! {
 
 /// Loads the IDT.
 pub fn init_idt() {
-    IDT.load();
+    load_idt();
     logln!("IDT loaded");
 }
+
+/// Loads the IDT on the running processor - every processor shares it.
+pub fn load_idt() {
+    IDT.load();
+}
@@ -1,10 +1,14 @@
 // This might be reimplemented from scratch in the future.
 
-// TODO: Implement all remaining interrupt handlers for CPU interrupts
-// We need to implement all interrupt handlers and add basic handling to them so we don't double fault.
-// Better handling for each of them will be added later.
+// The IRQs go through the local APIC and the IO-APICs found in the MADT,
+// or through the legacy PICs on systems without them.
 
+// The CPU exception handlers share `handle_exception`, which terminates the faulting process
+// for exceptions from user mode, and panics for the ones from kernel mode.
+
+pub(crate) mod apic;
 mod cpu_handlers;
+pub mod crash_records;
 mod interrupt_register;
 use internal_utils::logln;
 pub(crate) mod gdt;
@@ -13,18 +17,34 @@ pub use gdt::GDT;
 mod pic;
 
 use crate::interrupts::{
-    gdt::reload_gdt,
-    interrupt_register::init_idt,
+    gdt::{load_cpu_gdt, reload_gdt},
+    interrupt_register::{init_idt, load_idt},
     pic::{InterruptIndex, PICS, Pics, enable_irq},
     pic_handlers::enable_keyboard_irq,
 };
 
-/// Initializes the GDT, IDT and PIC controllers
+/// Initializes the GDT, IDT and interrupt controllers
 pub fn setup() {
     reload_gdt();
     init_idt();
+    // The PICs are remapped even when they're not used, so their spurious IRQs don't look like exceptions
     PICS.initialize();
+    if apic::init() {
+        unsafe { PICS.lock().unwrap().disable() };
+        logln!("Interrupts routed through the APICs");
+    } else {
+        logln!("[WARN] No APIC found, interrupts routed through the PICs");
+    }
     enable_irq(InterruptIndex::Timer);
     enable_keyboard_irq();
     logln!("Interrupts set up");
 }
+
+/// Loads the GDT, TSS and IDT of an application processor, and enables its local APIC.
+///
+/// The IRQs keep going to the bootstrap processor.
+pub(crate) fn setup_application_processor(cpu_index: usize) {
+    load_cpu_gdt(cpu_index);
+    load_idt();
+    apic::enable_local_apic();
+}
@@ -1,6 +1,8 @@
 use internal_utils::structures::OnceMutex;
 use pic8259::ChainedPics;
 
+use super::apic;
+
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
@@ -17,6 +19,13 @@ pub enum InterruptIndex {
 }
 
 impl InterruptIndex {
+    pub const ALL: [InterruptIndex; 4] = [
+        InterruptIndex::Timer,
+        InterruptIndex::Keyboard,
+        InterruptIndex::AtaPrimary,
+        InterruptIndex::AtaSecondary,
+    ];
+
     /// Returns the corresponding interrupt number for this interrupt type as a u8
     pub fn as_u8(self) -> u8 {
         self as u8
@@ -52,8 +61,23 @@ impl Pics for OnceMutex<ChainedPics> {
     }
 }
 
-/// Unmask one PIC IRQ line without disturbing the rest of the mask state.
+/// Signals the end of the interrupt to the controller that raised it.
+pub fn end_of_interrupt(interrupt: InterruptIndex) {
+    if apic::is_active() {
+        apic::end_of_interrupt();
+    } else {
+        unsafe { PICS.notify_end_of_interrupt(interrupt.as_u8()) };
+    }
+}
+
+/// Unmask one IRQ line without disturbing the rest of the mask state.
+///
+/// The IRQ is unmasked in the IO-APIC if the APICs are used, and in the PICs otherwise.
 pub fn enable_irq(interrupt: InterruptIndex) {
+    if apic::is_active() {
+        apic::enable_irq(interrupt);
+        return;
+    }
     let irq_line = interrupt.irq_line();
     let mut pics = PICS.lock().unwrap();
 
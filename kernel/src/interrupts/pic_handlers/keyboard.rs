@@ -4,9 +4,8 @@ use pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1, layouts};
 use spin::Mutex;
 use x86_64::structures::idt::InterruptStackFrame;
 
-use crate::interrupts::pic::{PICS, Pics};
 use crate::interrupts::{
-    pic::{InterruptIndex, enable_irq},
+    pic::{InterruptIndex, enable_irq, end_of_interrupt},
     pic_handlers::addresses::PS2_INTERRUPT_CONTROLLER_SCAN_CODE_PORT,
 };
 
@@ -41,7 +40,5 @@ pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: Interrupt
         }
     }
 
-    unsafe {
-        PICS.notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Keyboard);
 }
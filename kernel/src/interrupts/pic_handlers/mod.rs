@@ -5,3 +5,7 @@ pub use keyboard::{enable_keyboard_irq, keyboard_interrupt_handler};
 mod ata;
 pub use ata::{ata_primary_interrupt_handler, ata_secondary_interrupt_handler};
 mod addresses;
+mod spurious;
+pub use spurious::spurious_interrupt_handler;
+mod tlb_shootdown;
+pub use tlb_shootdown::tlb_shootdown_interrupt_handler;
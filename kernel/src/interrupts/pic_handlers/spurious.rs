@@ -0,0 +1,4 @@
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// Handles a spurious interrupt of the local APIC, which must not be acknowledged.
+pub extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
@@ -1,9 +1,10 @@
-use internal_utils::clocks::get_current_tick;
+use internal_utils::clocks::count_timer_tick;
 
 use crate::{
-    interrupts::pic::{InterruptIndex, PICS, Pics},
-    processes::{RegistersState, SCHEDULER, dispatcher::dispatch_thread},
+    interrupts::pic::{InterruptIndex, end_of_interrupt},
+    processes::{RegistersState, on_timer_tick, run_processes},
     push_registers_state,
+    smp::current_cpu_index,
 };
 use core::arch::naked_asm;
 
@@ -29,14 +30,12 @@ pub unsafe extern "sysv64" fn timer_interrupt_handler() -> ! {
 
 #[unsafe(no_mangle)]
 pub extern "sysv64" fn timer_handler(registers: *const u8) {
-    let state = unsafe { (*(registers as *const RegistersState)).clone() };
-    let thread = {
-        let mut scheduler = SCHEDULER.lock().unwrap();
-        scheduler.on_tick(state, get_current_tick());
-        scheduler.schedule()
-    };
-    unsafe {
-        PICS.notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    // The application processors' timers tick at the same rate, so only the bootstrap processor counts the time
+    if current_cpu_index() == 0 {
+        count_timer_tick();
     }
-    dispatch_thread(thread);
+    let state = unsafe { (*(registers as *const RegistersState)).clone() };
+    on_timer_tick(state);
+    end_of_interrupt(InterruptIndex::Timer);
+    run_processes();
 }
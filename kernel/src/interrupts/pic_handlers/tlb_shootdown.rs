@@ -0,0 +1,9 @@
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::{interrupts::apic, smp::tlb::handle_tlb_shootdown};
+
+/// Handles another processor's request to flush the TLB, which it waits for.
+pub extern "x86-interrupt" fn tlb_shootdown_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    handle_tlb_shootdown();
+    apic::end_of_interrupt();
+}
@@ -21,7 +21,9 @@ pub mod addressing;
 mod ikd;
 pub mod interrupts;
 pub mod memory;
+pub mod power;
 pub mod processes;
+pub mod smp;
 pub mod syscalls;
 
 #[inline(always)]
@@ -16,27 +16,42 @@ use bootloader_api::{BootInfo, entry_point};
 use core::panic::PanicInfo;
 use internal_utils::clocks::{self};
 use internal_utils::kernel_information::KernelInformation;
+use internal_utils::kernel_information::heap_tracking::{AllocationTag, with_allocation_tag};
 use internal_utils::{logln, serial};
 use kernel::addressing::BOOTLOADER_CONFIG;
 use kernel::interrupts::{self};
+use kernel::processes::elf::start_init_process;
+use kernel::processes::{RoundRobinConfig, SchedulerKind};
 use kernel::{hlt_loop_hard, processes};
-use kernel::{memory, syscalls};
+use kernel::{memory, smp, syscalls};
 
 use core::alloc::Layout;
 
 entry_point!(kernel, config = &BOOTLOADER_CONFIG);
 pub fn kernel(boot_info: &'static mut BootInfo) -> ! {
     serial::init_logger();
+    smp::init_allocation_tags();
     clocks::init_rtc();
-    let allocator = memory::init_kernel_memory(boot_info);
+    let allocator = with_allocation_tag(AllocationTag::Memory, || {
+        memory::init_kernel_memory(boot_info)
+    });
     let kernel_info = KernelInformation::new(boot_info, allocator);
-    interrupts::setup();
-    syscalls::setup_syscalls();
-    tbes::init_tag_store();
-    ata::init_disks();
-    vga::init_vga(kernel_info);
+    with_allocation_tag(AllocationTag::Interrupts, interrupts::setup);
+    with_allocation_tag(AllocationTag::Syscalls, syscalls::setup_syscalls);
+    with_allocation_tag(AllocationTag::Drivers, || {
+        tbes::init_tag_store();
+        ata::init_disks();
+        vga::init_vga(kernel_info);
+    });
 
-    processes::init_scheduler();
+    processes::init_fpu();
+    with_allocation_tag(AllocationTag::Interrupts, smp::start_application_processors);
+    with_allocation_tag(AllocationTag::Processes, || {
+        processes::init_scheduler(SchedulerKind::RoundRobin(RoundRobinConfig::default()));
+        if let Some(Err(error)) = start_init_process() {
+            logln!("[WARN] The init process couldn't be started: {}", error);
+        }
+    });
     processes::run_processes();
 }
 
@@ -6,24 +6,35 @@ use x86_64::{
     PhysAddr, VirtAddr,
     structures::paging::{
         FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+        frame::PhysFrameRange,
     },
 };
 
 use crate::addressing::LOW_MEMORY_LIMIT;
 
+/// The number of 4K frames below `LOW_MEMORY_LIMIT`, which are kept for DMA.
+const DMA_FRAMES: u64 = LOW_MEMORY_LIMIT / Size4KiB::SIZE;
+
 /// A Frame Allocator that allocates according to the usage bitmap of the memory.
 /// The maximum size of usable memory is 64GiB with this bitflag.
 /// Have to pre-allocate one 4K frame and one 2M frame.
 #[repr(C)]
 pub struct BitmapFrameAllocator {
     total_usable_memory: u64,
-    low_memory_usable_size: u64,
+    free_dma_frames: u64,
     next_free_2m_frame_guess: usize,
     next_free_4k_frame_guess: usize,
     free_2m_frames: u64,
     free_4k_frames: u64,
     two_megabyte_frames_bitflag: &'static mut [u64; 512],
     four_kilobytes_frames_bitflag: &'static mut [u64; 262_144],
+    /// The frames below `LOW_MEMORY_LIMIT`, which are only given out by `allocate_contiguous`.
+    dma_frames_bitflag: [u64; (DMA_FRAMES / 64) as usize],
+    /// The number of additional references to every 4K frame, by frame number.
+    ///
+    /// It takes frames of its own when the allocator is created, so sharing a frame never needs the
+    /// heap - which might have to grow, and lock the allocator, to give the memory.
+    shared_frames: &'static mut [u32],
 }
 
 impl BitmapFrameAllocator {
@@ -37,16 +48,6 @@ impl BitmapFrameAllocator {
             .filter(|region| region.kind == MemoryRegionKind::Usable)
             .map(|region| region.end - region.start)
             .sum::<u64>();
-        let low_memory_usable_size = memory_map
-            .iter()
-            .filter(|region| region.kind == MemoryRegionKind::Usable)
-            .map(|region| {
-                // We skip regions below the lower limit
-                let end = region.end.min(LOW_MEMORY_LIMIT);
-                let start = region.start.min(end);
-                end - start
-            })
-            .sum::<u64>();
 
         // We first need to take a 2M frame and a 4K frame from the memory map for the bitflags.
         // We should probably spread it so we allocate the frames anywhere where they fit
@@ -93,13 +94,15 @@ impl BitmapFrameAllocator {
 
         let mut allocator = BitmapFrameAllocator {
             total_usable_memory,
-            low_memory_usable_size,
+            free_dma_frames: 0,
             next_free_2m_frame_guess: two_mega_frame.len(),
             next_free_4k_frame_guess: four_kilo_frame.len(),
             free_2m_frames: 0,
             free_4k_frames: 0,
             four_kilobytes_frames_bitflag: four_kilo_frame,
             two_megabyte_frames_bitflag: two_mega_frame,
+            dma_frames_bitflag: [u64::MAX; (DMA_FRAMES / 64) as usize],
+            shared_frames: &mut [],
         };
 
         // Now we need to set the usable memory regions as unused so they're not allocated.
@@ -114,13 +117,17 @@ impl BitmapFrameAllocator {
             );
             let frame_range = PhysFrame::<Size4KiB>::range_inclusive(start, end);
 
-            frame_range
-                .filter(|f| f.start_address().as_u64() >= LOW_MEMORY_LIMIT)
-                .for_each(|f| {
+            frame_range.for_each(|f| {
+                let address = f.start_address().as_u64();
+                if address >= LOW_MEMORY_LIMIT {
                     allocator
-                        .set_unused_lock(f.start_address().as_u64(), Size4KiB::SIZE)
+                        .set_unused_lock(address, Size4KiB::SIZE)
                         .expect("Failed setting memory regions as unused");
-                });
+                } else if address != 0 {
+                    // The null frame is skipped, as a buffer at address 0 would look like a missing one
+                    allocator.free_dma_frame(address);
+                }
+            });
         }
 
         // We set the regions where we placed the frame allocator as taken
@@ -133,6 +140,31 @@ impl BitmapFrameAllocator {
             Size4KiB::SIZE,
         );
 
+        // Only the usable memory can be shared, so the counters end with it
+        let usable_memory_end = memory_map
+            .iter()
+            .filter(|region| region.kind == MemoryRegionKind::Usable)
+            .map(|region| region.end)
+            .max()
+            .unwrap_or(0);
+        let counted_frames = (usable_memory_end / Size4KiB::SIZE)
+            .min(allocator.four_kilobytes_frames_bitflag.len() as u64 * 64);
+        let counters_size = counted_frames * size_of::<u32>() as u64;
+        let counters = allocator
+            .allocate_contiguous(
+                counters_size.div_ceil(Size4KiB::SIZE),
+                PhysAddr::new(usable_memory_end),
+                Size4KiB::SIZE,
+            )
+            .expect("Not enough memory for the frame reference counts");
+        allocator.shared_frames = unsafe {
+            core::slice::from_raw_parts_mut(
+                (counters.start.start_address().as_u64() + pmo) as *mut u32,
+                counted_frames as usize,
+            )
+        };
+        allocator.shared_frames.fill(0);
+
         logln!(
             "Next frame hints: {} (2M) and {} (4K)",
             allocator.next_free_2m_frame_guess,
@@ -142,6 +174,18 @@ impl BitmapFrameAllocator {
         allocator
     }
 
+    fn free_dma_frame(&mut self, address: u64) {
+        let frame = address / Size4KiB::SIZE;
+        let flags = &mut self.dma_frames_bitflag[(frame >> 6) as usize];
+        debug_assert_ne!(
+            *flags & (1 << (frame & 63)),
+            0,
+            "DMA frame already set as unused"
+        );
+        *flags &= !(1 << (frame & 63));
+        self.free_dma_frames += 1;
+    }
+
     fn set_used_lock(&mut self, start_address: u64, size: u64) -> Option<()> {
         match size {
             Size2MiB::SIZE => {
@@ -293,8 +337,45 @@ where
     S: PageSize,
 {
     unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
-        self.set_unused_lock(frame.start_address().as_u64(), frame.size());
+        let address = frame.start_address().as_u64();
+        if address < LOW_MEMORY_LIMIT {
+            // The DMA zone has its own bitmap, and is only split into 4K frames
+            let frames = PhysFrame::<Size4KiB>::range(
+                PhysFrame::containing_address(frame.start_address()),
+                PhysFrame::containing_address(frame.start_address() + frame.size()),
+            );
+            for frame in frames {
+                self.free_dma_frame(frame.start_address().as_u64());
+            }
+            return;
+        }
+        self.set_unused_lock(address, frame.size());
+    }
+}
+
+/// Finds `count` free frames in a row in the bitmap, between the frame numbers `start` and `end`,
+/// with the first one's number a multiple of `alignment`.
+fn find_free_run(bitmap: &[u64], start: u64, end: u64, count: u64, alignment: u64) -> Option<u64> {
+    let is_free = |frame: u64| bitmap[(frame >> 6) as usize] & (1 << (frame & 63)) == 0;
+    let mut candidate = start.checked_next_multiple_of(alignment)?;
+    while candidate.checked_add(count)? <= end {
+        match (candidate..candidate + count).rfind(|&frame| !is_free(frame)) {
+            None => return Some(candidate),
+            Some(used) => candidate = (used + 1).checked_next_multiple_of(alignment)?,
+        }
     }
+    None
+}
+
+fn frame_number(frame: PhysFrame<Size4KiB>) -> usize {
+    (frame.start_address().as_u64() / Size4KiB::SIZE) as usize
+}
+
+fn frame_range(first: u64, count: u64) -> PhysFrameRange<Size4KiB> {
+    PhysFrame::range(
+        PhysFrame::containing_address(PhysAddr::new(first * Size4KiB::SIZE)),
+        PhysFrame::containing_address(PhysAddr::new((first + count) * Size4KiB::SIZE)),
+    )
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
@@ -397,7 +478,7 @@ impl FullFrameAllocator for BitmapFrameAllocator {
     }
 
     fn get_free_dma_memory(&self) -> u64 {
-        self.low_memory_usable_size
+        self.free_dma_frames * Size4KiB::SIZE
     }
 
     fn get_free_4k_frames(&self) -> u64 {
@@ -427,4 +508,69 @@ impl FullFrameAllocator for BitmapFrameAllocator {
         }
         self.free_2m_frames
     }
+
+    fn share_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        self.shared_frames[frame_number(frame)] += 1;
+    }
+
+    fn get_frame_references(&self, frame: PhysFrame<Size4KiB>) -> u64 {
+        self.shared_frames[frame_number(frame)] as u64 + 1
+    }
+
+    unsafe fn release_frame(&mut self, frame: PhysFrame<Size4KiB>) -> bool {
+        let shared = &mut self.shared_frames[frame_number(frame)];
+        if *shared > 0 {
+            *shared -= 1;
+            return false;
+        }
+        unsafe { self.deallocate_frame(frame) };
+        true
+    }
+
+    fn allocate_contiguous(
+        &mut self,
+        count: u64,
+        max_address: PhysAddr,
+        alignment: u64,
+    ) -> Option<PhysFrameRange<Size4KiB>> {
+        if count == 0 || !alignment.is_power_of_two() {
+            return None;
+        }
+        let alignment = (alignment / Size4KiB::SIZE).max(1);
+        let max_frame = max_address.as_u64() / Size4KiB::SIZE;
+
+        let frames_end = max_frame.min(self.four_kilobytes_frames_bitflag.len() as u64 * 64);
+        if let Some(first) = find_free_run(
+            self.four_kilobytes_frames_bitflag.as_slice(),
+            DMA_FRAMES,
+            frames_end,
+            count,
+            alignment,
+        ) {
+            for frame in first..first + count {
+                self.set_used_lock(frame * Size4KiB::SIZE, Size4KiB::SIZE)?;
+            }
+            return Some(frame_range(first, count));
+        }
+
+        // Only falling back to the DMA zone here, keeping it for the devices that can't reach any higher
+        let first = find_free_run(
+            &self.dma_frames_bitflag,
+            0,
+            max_frame.min(DMA_FRAMES),
+            count,
+            alignment,
+        )?;
+        for frame in first..first + count {
+            self.dma_frames_bitflag[(frame >> 6) as usize] |= 1 << (frame & 63);
+        }
+        self.free_dma_frames -= count;
+        Some(frame_range(first, count))
+    }
+
+    unsafe fn deallocate_contiguous(&mut self, frames: PhysFrameRange<Size4KiB>) {
+        for frame in frames {
+            unsafe { self.deallocate_frame(frame) };
+        }
+    }
 }
@@ -0,0 +1,214 @@
+use core::fmt::Display;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use internal_utils::{kernel_information::KERNEL_INFORMATION, logln};
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    instructions::interrupts::without_interrupts,
+    structures::paging::{
+        Mapper, OffsetPageTable, Page, PageSize, PageTableFlags, PhysFrame, Size4KiB,
+        mapper::MapToError, page::PageRangeInclusive,
+    },
+};
+
+use crate::addressing::{ADDRESSES, GUARDED_STACK_SIZE, GUARDED_STACK_SLOTS, GUARDED_STACKS_START};
+use crate::smp::tlb::{AddressSpace, TlbFlush};
+
+use super::page_table::MEMORY_MAPPER;
+
+/// The size of the unmapped page below every stack.
+const GUARD_SIZE: u64 = Size4KiB::SIZE;
+
+/// The space taken by a single stack, including its guard page.
+const SLOT_SIZE: u64 = GUARD_SIZE + GUARDED_STACK_SIZE;
+
+struct StackSlots {
+    /// The lowest slot that has never been used.
+    next_slot: u64,
+    /// The slots freed by dropped stacks.
+    free_slots: Vec<u64>,
+}
+
+static STACK_SLOTS: Mutex<StackSlots> = Mutex::new(StackSlots {
+    next_slot: 0,
+    free_slots: Vec::new(),
+});
+
+/// What the stacks are used for, by slot.
+static STACK_OWNERS: Mutex<BTreeMap<u64, StackOwner>> = Mutex::new(BTreeMap::new());
+
+/// The page below the stack the bootloader runs the kernel on.
+const BOOT_STACK_GUARD_PAGE: u64 = ADDRESSES[1];
+
+/// What a kernel stack is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackOwner {
+    /// The stack the bootloader started the kernel on.
+    Boot,
+    /// The stack an application processor starts the kernel on.
+    Processor(usize),
+    /// An interrupt stack of the TSS.
+    Interrupt(&'static str),
+    /// A stack of a thread.
+    Thread { process_id: u64, thread_id: u64 },
+    /// A stack whose owner wasn't set.
+    Unknown,
+}
+
+impl Display for StackOwner {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StackOwner::Boot => write!(f, "the boot stack"),
+            StackOwner::Processor(cpu_index) => write!(f, "the boot stack of CPU {}", cpu_index),
+            StackOwner::Interrupt(name) => write!(f, "the {} interrupt stack", name),
+            StackOwner::Thread {
+                process_id,
+                thread_id,
+            } => write!(f, "thread {} of process {}", thread_id, process_id),
+            StackOwner::Unknown => write!(f, "a stack of unknown owner"),
+        }
+    }
+}
+
+/// Unmaps the page below the boot stack, so overflowing it can't go unnoticed either.
+///
+/// The bootloader maps the stack at a fixed address, and nothing else is mapped right below it.
+pub(super) fn guard_boot_stack(mapper: &mut OffsetPageTable) {
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(BOOT_STACK_GUARD_PAGE));
+    // The frame stays allocated, as it might still be counted as the bootloader's.
+    // Only the bootstrap processor runs yet, so flushing its own TLB is enough
+    if let Ok((_, flush)) = mapper.unmap(page) {
+        flush.flush();
+        logln!("Unmapped the page below the boot stack");
+    }
+}
+
+/// Returns the owner of the stack whose guard page holds the address,
+/// or `None` if the address is not in a guard page.
+///
+/// Doesn't wait for the owners' lock, so it can be used while handling a double fault.
+pub fn find_guard_page_owner(address: VirtAddr) -> Option<StackOwner> {
+    let address = address.as_u64();
+    if (BOOT_STACK_GUARD_PAGE..BOOT_STACK_GUARD_PAGE + GUARD_SIZE).contains(&address) {
+        return Some(StackOwner::Boot);
+    }
+    let offset = address.checked_sub(GUARDED_STACKS_START)?;
+    let slot = offset / SLOT_SIZE;
+    if slot >= GUARDED_STACK_SLOTS || offset % SLOT_SIZE >= GUARD_SIZE {
+        return None;
+    }
+    let owner = STACK_OWNERS
+        .try_lock()
+        .and_then(|owners| owners.get(&slot).copied());
+    Some(owner.unwrap_or(StackOwner::Unknown))
+}
+
+/// A kernel stack with an unmapped guard page below it,
+/// so overflowing the stack causes a page fault instead of overwriting other memory.
+///
+/// The stack is mapped in the kernel's part of the address space, so it's accessible from every process.
+/// It's unmapped and its memory is freed on drop.
+#[derive(Debug)]
+pub struct GuardedStack {
+    slot: u64,
+}
+
+impl GuardedStack {
+    /// Allocates and maps a new stack. Returns `None` if there is not enough memory for it.
+    pub fn new() -> Option<Self> {
+        // A thread interrupted while holding the locks would block everyone else from taking them
+        without_interrupts(|| {
+            let slot = {
+                let mut slots = STACK_SLOTS.lock();
+                match slots.free_slots.pop() {
+                    Some(slot) => slot,
+                    None if slots.next_slot < GUARDED_STACK_SLOTS => {
+                        slots.next_slot += 1;
+                        slots.next_slot - 1
+                    }
+                    None => return None,
+                }
+            };
+            let stack = GuardedStack { slot };
+            match stack.map() {
+                Ok(()) => Some(stack),
+                // Dropping the stack frees what's been mapped so far
+                Err(_) => None,
+            }
+        })
+    }
+
+    /// Returns the lowest address of the stack, right above the guard page.
+    pub fn bottom(&self) -> VirtAddr {
+        VirtAddr::new(GUARDED_STACKS_START + self.slot * SLOT_SIZE + GUARD_SIZE)
+    }
+
+    /// Returns the highest address of the stack, as the stack grows downwards.
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + GUARDED_STACK_SIZE
+    }
+
+    /// Returns the address of the guard page below the stack.
+    pub fn guard_page(&self) -> VirtAddr {
+        self.bottom() - GUARD_SIZE
+    }
+
+    /// Sets what the stack is used for, which is reported if the stack overflows.
+    pub fn set_owner(&self, owner: StackOwner) {
+        without_interrupts(|| {
+            STACK_OWNERS.lock().insert(self.slot, owner);
+        });
+    }
+
+    /// Checks if the address belongs to the stack.
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        (self.bottom()..self.top()).contains(&address)
+    }
+
+    fn pages(&self) -> PageRangeInclusive {
+        Page::range_inclusive(
+            Page::containing_address(self.bottom()),
+            Page::containing_address(self.top() - 1u64),
+        )
+    }
+
+    fn map(&self) -> Result<(), MapToError<Size4KiB>> {
+        let mut mapper = MEMORY_MAPPER.lock();
+        let mapper = mapper.as_mut().unwrap();
+        let allocator = KERNEL_INFORMATION.get().unwrap().allocator;
+        let mut allocator = allocator.lock();
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        for page in self.pages() {
+            let frame: PhysFrame<Size4KiB> = allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            unsafe { mapper.map_to(page, frame, flags, &mut *allocator)?.flush() };
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GuardedStack {
+    fn drop(&mut self) {
+        let pages = GUARDED_STACK_SIZE / Size4KiB::SIZE;
+        // The frames are added to the flush while the mapper is locked, so it must not allocate
+        let mut flush = TlbFlush::with_capacity(AddressSpace::Kernel, pages as usize);
+        without_interrupts(|| {
+            let mut mapper = MEMORY_MAPPER.lock();
+            let mapper = mapper.as_mut().unwrap();
+            for page in self.pages() {
+                if let Ok((frame, unmapped)) = mapper.unmap(page) {
+                    unmapped.ignore();
+                    flush.add_unmapped_page(page, frame);
+                }
+            }
+        });
+        // Every processor could have the stack in its TLB, so the slot is only reused after the shootdown
+        flush.shoot_down();
+        without_interrupts(|| {
+            STACK_OWNERS.lock().remove(&self.slot);
+            STACK_SLOTS.lock().free_slots.push(self.slot);
+        });
+    }
+}
@@ -1,42 +1,142 @@
-use internal_utils::kernel_information::allocator::ALLOCATOR;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use internal_utils::kernel_information::{
+    KERNEL_INFORMATION,
+    allocator::{ALLOCATOR, HeapGrowth},
+};
 use x86_64::{
     VirtAddr,
     structures::paging::{
-        FrameAllocator, Mapper, Page, PageTableFlags, Size2MiB, mapper::MapToError,
+        FrameAllocator, Mapper, Page, PageSize, PageTableFlags, Size2MiB, mapper::MapToError,
+        page::PageRangeInclusive,
     },
 };
 
-use crate::addressing::{HEAP_SIZE, HEAP_START};
+use crate::addressing::{HEAP_MAX_SIZE, HEAP_SIZE, HEAP_START};
+
+use super::{frame_allocator::BitmapFrameAllocator, page_table::MEMORY_MAPPER};
+
+const HEAP_FLAGS: PageTableFlags = PageTableFlags::PRESENT.union(PageTableFlags::WRITABLE);
+
+/// How much is mapped past the top of the heap, so it can grow while the locks are held elsewhere.
+const HEAP_RESERVE: u64 = Size2MiB::SIZE;
 
-use super::frame_allocator::BitmapFrameAllocator;
+/// The end of the mapped part of the heap's range, which is past the top of the heap by the reserve.
+///
+/// Only changed by `grow_heap`, which runs with the heap locked.
+static MAPPED_TOP: AtomicUsize = AtomicUsize::new(0);
 
 /// maps the kernels heap memory area to physical addresses
+///
+/// Only the first `HEAP_SIZE` bytes and the reserve are mapped, the heap maps more of its range as
+/// it needs them.
 pub fn init_heap(
     mapper: &mut impl Mapper<Size2MiB>,
     frame_allocator: &mut BitmapFrameAllocator,
 ) -> Result<(), MapToError<Size2MiB>> {
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
     // actually map all frames and exit on error
-    for page in page_range {
+    for page in heap_pages(HEAP_START, HEAP_SIZE + HEAP_RESERVE) {
         let frame = frame_allocator
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+        unsafe {
+            mapper
+                .map_to(page, frame, HEAP_FLAGS, frame_allocator)?
+                .flush()
+        };
     }
 
+    MAPPED_TOP.store(
+        (HEAP_START + HEAP_SIZE + HEAP_RESERVE) as usize,
+        Ordering::Relaxed,
+    );
     unsafe {
-        ALLOCATOR
-            .lock()
-            .init(HEAP_START as *mut u8, HEAP_SIZE as usize);
+        ALLOCATOR.init(
+            HEAP_START as *mut u8,
+            HEAP_SIZE as usize,
+            Some(HeapGrowth {
+                grow: grow_heap,
+                step: Size2MiB::SIZE as usize,
+                max_size: HEAP_MAX_SIZE as usize,
+            }),
+        );
     }
 
     Ok(())
 }
+
+fn heap_pages(start: u64, size: u64) -> PageRangeInclusive<Size2MiB> {
+    let start = VirtAddr::new(start);
+    let end = start + size - 1u64;
+    Page::range_inclusive(
+        Page::containing_address(start),
+        Page::containing_address(end),
+    )
+}
+
+/// Grows the heap by the range at its top, out of the reserve, and maps a new reserve past it.
+///
+/// It runs with the heap locked, so it doesn't wait for the locks - if they're held, by another
+/// processor or by a thread allocating while holding them, the heap grows into the reserve and
+/// the reserve is mapped again on the next growth.
+fn grow_heap(heap_top: usize, size: usize) -> bool {
+    let needed_top = heap_top + size;
+    let reserve_top =
+        (needed_top + HEAP_RESERVE as usize).min((HEAP_START + HEAP_MAX_SIZE) as usize);
+    // Without the frames for a new reserve, the heap can still grow by what it needs
+    for top in [reserve_top, needed_top] {
+        let mapped_top = MAPPED_TOP.load(Ordering::Relaxed);
+        if mapped_top >= top {
+            break;
+        }
+        if map_heap_range(mapped_top, top - mapped_top) {
+            MAPPED_TOP.store(top, Ordering::Relaxed);
+            break;
+        }
+    }
+    MAPPED_TOP.load(Ordering::Relaxed) >= needed_top
+}
+
+/// Maps the frames for the range past the mapped part of the heap, returning whether it could.
+fn map_heap_range(start: usize, size: usize) -> bool {
+    let Some(kernel_info) = KERNEL_INFORMATION.get() else {
+        return false;
+    };
+    let Some(mut mapper) = MEMORY_MAPPER.try_lock() else {
+        return false;
+    };
+    let Some(mapper) = mapper.as_mut() else {
+        return false;
+    };
+    let Some(mut allocator) = kernel_info.allocator.try_lock() else {
+        return false;
+    };
+
+    let pages = heap_pages(start as u64, size as u64);
+    for page in pages {
+        let frame: Option<_> = allocator.allocate_frame();
+        let mapped = frame.is_some_and(|frame| {
+            match unsafe { mapper.map_to(page, frame, HEAP_FLAGS, &mut *allocator) } {
+                Ok(flush) => {
+                    flush.flush();
+                    true
+                }
+                Err(_) => {
+                    unsafe { allocator.deallocate_frame(frame) };
+                    false
+                }
+            }
+        });
+        if !mapped {
+            // Unmapping the part that's been mapped, as the heap is mapped up to a single top
+            for page in Page::range(pages.start, page) {
+                if let Ok((frame, flush)) = mapper.unmap(page) {
+                    flush.flush();
+                    unsafe { allocator.deallocate_frame(frame) };
+                }
+            }
+            return false;
+        }
+    }
+    true
+}
@@ -1,5 +1,6 @@
 mod debug;
 mod frame_allocator;
+pub mod guarded_stack;
 mod heap;
 mod memory_init;
 mod page_table;
@@ -7,7 +8,10 @@ mod page_table;
 use alloc::sync::Arc;
 use bootloader_api::{BootInfo, info::FrameBuffer};
 use internal_utils::{
-    kernel_information::frame_allocator::{FullFrameAllocator, print_memory},
+    kernel_information::{
+        KERNEL_INFORMATION,
+        frame_allocator::{FullFrameAllocator, print_memory},
+    },
     logln,
 };
 use memory_init::init_page_tables;
@@ -15,18 +19,20 @@ use memory_init::init_page_tables;
 use spin::{Mutex, Once};
 use x86_64::{
     VirtAddr,
-    instructions::tlb,
+    instructions::{interrupts::without_interrupts, tlb},
     registers::control::{Cr3, Cr3Flags},
     structures::paging::{
-        Mapper, Page, PageTableFlags, PhysFrame, Translate, mapper::TranslateResult,
+        Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, Translate,
+        mapper::{MapToError, TranslateResult},
         page::PageRange,
     },
 };
 
 use crate::memory::{
-    debug::print_memory_map, frame_allocator::BitmapFrameAllocator, heap::init_heap,
-    page_table::MEMORY_MAPPER,
+    debug::print_memory_map, frame_allocator::BitmapFrameAllocator,
+    guarded_stack::guard_boot_stack, heap::init_heap, page_table::MEMORY_MAPPER,
 };
+use crate::smp::tlb::{AddressSpace, TlbFlush, enter_address_space};
 
 static KERNEL_CR3: Once<(PhysFrame, Cr3Flags)> = Once::new();
 
@@ -46,6 +52,7 @@ pub fn init_kernel_memory(
 
     let mut mapper = MEMORY_MAPPER.lock();
     init_heap(mapper.as_mut().unwrap(), &mut allocator).expect("heap initialization failed");
+    guard_boot_stack(mapper.as_mut().unwrap());
 
     let allocator = Arc::new(Mutex::new(allocator));
 
@@ -54,13 +61,51 @@ pub fn init_kernel_memory(
     allocator
 }
 
+/// Returns the level 4 page table frame of the kernel's paging table.
+pub fn kernel_level_4_frame() -> PhysFrame {
+    KERNEL_CR3
+        .get()
+        .expect("Kernel memory has to be initialized first")
+        .0
+}
+
+/// Maps the frame at its physical address in the kernel's paging table,
+/// for the code running on it before paging is enabled.
+pub fn identity_map(frame: PhysFrame) -> Result<(), MapToError<Size4KiB>> {
+    let mut mapper = MEMORY_MAPPER.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let allocator = KERNEL_INFORMATION.get().unwrap().allocator;
+    let mut allocator = allocator.lock();
+    let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { mapper.map_to(page, frame, flags, &mut *allocator)?.flush() };
+    Ok(())
+}
+
+/// Unmaps a frame mapped by `identity_map`, without freeing it.
+pub fn identity_unmap(frame: PhysFrame) {
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+    let mut flush = TlbFlush::new(AddressSpace::Kernel);
+    {
+        let mut mapper = MEMORY_MAPPER.lock();
+        let mapper = mapper.as_mut().unwrap();
+        if let Ok((_, unmapped)) = mapper.unmap(page) {
+            unmapped.ignore();
+            flush.add_page(page);
+        }
+    }
+    // The application processors could still have the page in their TLB
+    flush.shoot_down();
+}
+
 /// Switches the paging table used to the kernel's paging table.
-fn switch_to_kernel_memory() {
+pub fn switch_to_kernel_memory() {
     let kernel_cr3 = KERNEL_CR3.get();
     if let Some(guard) = kernel_cr3 {
-        unsafe {
+        without_interrupts(|| unsafe {
+            enter_address_space(guard.0);
             Cr3::write(guard.0, guard.1);
-        }
+        });
     }
 }
 
@@ -69,9 +114,10 @@ pub fn with_kernel_memory<V>(action: impl FnOnce() -> V) -> V {
     let cr3 = Cr3::read();
     switch_to_kernel_memory();
     let result = action();
-    unsafe {
+    without_interrupts(|| unsafe {
+        enter_address_space(cr3.0);
         Cr3::write(cr3.0, cr3.1);
-    }
+    });
     result
 }
 
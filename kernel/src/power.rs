@@ -0,0 +1,215 @@
+use core::{
+    fmt::Display,
+    ptr::{read_volatile, write_volatile},
+};
+
+use internal_utils::{
+    acpi::{AddressSpace, GenericAddress},
+    kernel_information::KERNEL_INFORMATION,
+    logln,
+};
+use rost_user::SysCallError;
+use x86_64::instructions::{
+    interrupts::{are_enabled, disable, enable},
+    port::Port,
+};
+
+/// The sleep state that powers the system off.
+const SOFT_OFF_STATE: u8 = 5;
+
+// The bits of the PM1 control registers
+const SCI_ENABLE: u32 = 1;
+const SLEEP_TYPE_SHIFT: u32 = 10;
+const SLEEP_TYPE_MASK: u32 = 0b111 << SLEEP_TYPE_SHIFT;
+const SLEEP_ENABLE: u32 = 1 << 13;
+
+const KEYBOARD_CONTROLLER_COMMAND_PORT: u16 = 0x64;
+const KEYBOARD_CONTROLLER_INPUT_FULL: u8 = 1 << 1;
+const KEYBOARD_CONTROLLER_RESET: u8 = 0xFE;
+
+/// How many times the hardware is polled before giving up on it.
+const HARDWARE_TIMEOUT: u32 = 1_000_000;
+
+/// The errors of powering off or rebooting, which only return if the system keeps running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerError {
+    /// The ACPI tables don't describe how to power off the system
+    NotSupported,
+    /// The system is still running after entering the soft off state
+    PowerOffFailed,
+    /// Neither the ACPI reset register nor the keyboard controller reset the system
+    RebootFailed,
+}
+
+impl Display for PowerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PowerError::NotSupported => write!(f, "The system can't be powered off through ACPI"),
+            PowerError::PowerOffFailed => write!(f, "The system didn't power off"),
+            PowerError::RebootFailed => write!(f, "The system didn't reboot"),
+        }
+    }
+}
+
+impl From<PowerError> for SysCallError {
+    fn from(error: PowerError) -> Self {
+        match error {
+            PowerError::NotSupported => SysCallError::NotSupported,
+            PowerError::PowerOffFailed | PowerError::RebootFailed => SysCallError::Unknown,
+        }
+    }
+}
+
+/// Powers the system off by entering the ACPI soft off state (S5).
+///
+/// Only returns if the system couldn't be powered off.
+pub fn power_off() -> PowerError {
+    let Some(acpi) = KERNEL_INFORMATION.get().and_then(|info| info.acpi) else {
+        return PowerError::NotSupported;
+    };
+    let (Some(fadt), Some((sleep_type_a, sleep_type_b))) =
+        (acpi.fadt(), acpi.sleep_types(SOFT_OFF_STATE))
+    else {
+        return PowerError::NotSupported;
+    };
+    let Some(pm1a_control) = fadt.pm1a_control_block() else {
+        return PowerError::NotSupported;
+    };
+
+    logln!("Powering off");
+    let interrupts_enabled = are_enabled();
+    disable();
+
+    // The sleep registers are ignored until the firmware hands the hardware over to the OS
+    if let Some((port, value)) = fadt.acpi_enable()
+        && read_register(&pm1a_control).is_some_and(|control| control & SCI_ENABLE == 0)
+    {
+        unsafe { Port::<u8>::new(port).write(value) };
+        poll_hardware(|| {
+            read_register(&pm1a_control).is_some_and(|control| control & SCI_ENABLE != 0)
+        });
+    }
+
+    let blocks = [
+        (Some(pm1a_control), sleep_type_a),
+        (fadt.pm1b_control_block(), sleep_type_b),
+    ];
+    for (block, sleep_type) in blocks {
+        if let Some(block) = block
+            && let Some(control) = read_register(&block)
+        {
+            let control = (control & !SLEEP_TYPE_MASK)
+                | (sleep_type as u32) << SLEEP_TYPE_SHIFT
+                | SLEEP_ENABLE;
+            write_register(&block, control);
+        }
+    }
+    poll_hardware(|| false);
+
+    if interrupts_enabled {
+        enable();
+    }
+    PowerError::PowerOffFailed
+}
+
+/// Resets the system through the ACPI reset register, or the keyboard controller if there is none.
+///
+/// Only returns if the system couldn't be reset.
+pub fn reboot() -> PowerError {
+    logln!("Rebooting");
+    let interrupts_enabled = are_enabled();
+    disable();
+
+    let reset_register = KERNEL_INFORMATION
+        .get()
+        .and_then(|info| info.acpi)
+        .and_then(|acpi| acpi.fadt())
+        .and_then(|fadt| fadt.reset_register());
+    if let Some((register, value)) = reset_register
+        && write_register(&register, value as u32)
+    {
+        poll_hardware(|| false);
+    }
+
+    // Pulsing the reset line of the CPU, once the controller takes commands
+    let mut keyboard_controller = Port::<u8>::new(KEYBOARD_CONTROLLER_COMMAND_PORT);
+    poll_hardware(|| unsafe { keyboard_controller.read() } & KEYBOARD_CONTROLLER_INPUT_FULL == 0);
+    unsafe { keyboard_controller.write(KEYBOARD_CONTROLLER_RESET) };
+    poll_hardware(|| false);
+
+    if interrupts_enabled {
+        enable();
+    }
+    PowerError::RebootFailed
+}
+
+/// Polls the hardware until the condition holds, or it times out.
+fn poll_hardware(mut condition: impl FnMut() -> bool) {
+    for _ in 0..HARDWARE_TIMEOUT {
+        if condition() {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Reads an ACPI register, if it's in the IO or the memory address space.
+fn read_register(register: &GenericAddress) -> Option<u32> {
+    let address = register.address;
+    let value = match register.address_space() {
+        AddressSpace::SystemIo => unsafe {
+            match register.bit_width {
+                8 => Port::<u8>::new(address as u16).read() as u32,
+                32 => Port::<u32>::new(address as u16).read(),
+                _ => Port::<u16>::new(address as u16).read() as u32,
+            }
+        },
+        AddressSpace::SystemMemory => {
+            let pointer = physical_pointer(address)?;
+            unsafe {
+                match register.bit_width {
+                    8 => read_volatile(pointer as *const u8) as u32,
+                    32 => read_volatile(pointer as *const u32),
+                    _ => read_volatile(pointer as *const u16) as u32,
+                }
+            }
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Writes an ACPI register, returning `false` if it's not in the IO or the memory address space.
+fn write_register(register: &GenericAddress, value: u32) -> bool {
+    let address = register.address;
+    match register.address_space() {
+        AddressSpace::SystemIo => unsafe {
+            match register.bit_width {
+                8 => Port::<u8>::new(address as u16).write(value as u8),
+                32 => Port::<u32>::new(address as u16).write(value),
+                _ => Port::<u16>::new(address as u16).write(value as u16),
+            }
+        },
+        AddressSpace::SystemMemory => {
+            let Some(pointer) = physical_pointer(address) else {
+                return false;
+            };
+            unsafe {
+                match register.bit_width {
+                    8 => write_volatile(pointer as *mut u8, value as u8),
+                    32 => write_volatile(pointer as *mut u32, value),
+                    _ => write_volatile(pointer as *mut u16, value as u16),
+                }
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Returns the address of the physical memory in the physical memory mapping.
+fn physical_pointer(address: u64) -> Option<u64> {
+    KERNEL_INFORMATION
+        .get()
+        .map(|info| info.physical_memory_offset + address)
+}
@@ -7,10 +7,14 @@ use x86_64::registers::control::Cr3;
 use x86_64::structures::paging::PhysFrame;
 
 use crate::interrupts::GDT;
+use crate::interrupts::gdt::set_privilege_stack;
 use crate::processes::registers_state::Flags;
+use crate::smp::tlb::enter_address_space;
+use crate::syscalls::set_syscall_stack;
 use crate::unpack_registers_state;
 
 use super::RegistersState;
+use super::fpu_state::prepare_fpu;
 use super::thread::Thread;
 
 /// Runs the thread immediately.
@@ -38,12 +42,18 @@ pub fn dispatch_thread(thread: Arc<Mutex<Thread>>) -> ! {
         };
         cr3 = process.cr3;
         state = thread_mut.registers_state.clone();
+        // Interrupts and system calls from ring 3 land on the thread's own kernel stack
+        let kernel_stack_top = thread_mut.kernel_stack.top();
+        set_privilege_stack(kernel_stack_top);
+        set_syscall_stack(kernel_stack_top);
     }
+    prepare_fpu(&thread);
 
     let flags = (state.rflags | Flags::IF) & Flags::NT.complement();
     unsafe {
         // We decrement the counter forcefully because that function doesn't return by Rust.
         Arc::decrement_strong_count(Arc::into_raw(thread));
+        enter_address_space(cr3.0);
         Cr3::write_raw(cr3.0, cr3.1);
         switch_to_thread(
             code_selector_id as u64,
@@ -0,0 +1,36 @@
+use core::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfLoadError {
+    /// The image is too small to contain the headers it describes
+    Truncated,
+    /// The image does not start with the ELF magic number
+    NotAnElf,
+    /// The image is not a 64-bit little-endian ELF of the current version
+    UnsupportedFormat,
+    /// The image is not built for x86_64
+    UnsupportedMachine,
+    /// The image is not a statically linked executable
+    NotAnExecutable,
+    /// A segment has inconsistent sizes or lies outside of the image or the user address space
+    InvalidSegment,
+    /// The entry point does not lie in an executable segment
+    InvalidEntryPoint,
+    /// There was not enough memory to create the address space or the thread of the process
+    OutOfMemory,
+}
+
+impl Display for ElfLoadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ElfLoadError::Truncated => write!(f, "Image is truncated"),
+            ElfLoadError::NotAnElf => write!(f, "Image is not an ELF file"),
+            ElfLoadError::UnsupportedFormat => write!(f, "Unsupported ELF format"),
+            ElfLoadError::UnsupportedMachine => write!(f, "Image is not built for x86_64"),
+            ElfLoadError::NotAnExecutable => write!(f, "Image is not a static executable"),
+            ElfLoadError::InvalidSegment => write!(f, "Invalid segment"),
+            ElfLoadError::InvalidEntryPoint => write!(f, "Invalid entry point"),
+            ElfLoadError::OutOfMemory => write!(f, "Out of memory"),
+        }
+    }
+}
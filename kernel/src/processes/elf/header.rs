@@ -0,0 +1,112 @@
+use super::error::ElfLoadError;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+const ELF_VERSION_CURRENT: u8 = 1;
+const ELF_TYPE_EXECUTABLE: u16 = 2;
+const ELF_MACHINE_X86_64: u16 = 0x3E;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_INTERP: u32 = 3;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+
+/// The fields of the ELF file header the loader needs.
+#[derive(Debug, Clone, Copy)]
+pub struct ElfHeader {
+    /// The virtual address of the program's entry point.
+    pub entry: u64,
+    /// The offset of the program header table in the image.
+    pub program_header_offset: u64,
+    /// The number of entries in the program header table.
+    pub program_header_count: u16,
+}
+
+/// A single entry of the program header table.
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub segment_type: u32,
+    pub flags: u32,
+    /// The offset of the segment's data in the image.
+    pub offset: u64,
+    /// The virtual address the segment is loaded at.
+    pub virtual_address: u64,
+    /// The size of the segment's data in the image.
+    pub file_size: u64,
+    /// The size of the segment in memory, the part past `file_size` is zeroed.
+    pub memory_size: u64,
+}
+
+fn read_u16(image: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(image[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(image: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(image: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(image[offset..offset + 8].try_into().unwrap())
+}
+
+impl ElfHeader {
+    /// Parses and validates the file header of an x86_64 executable.
+    pub fn parse(image: &[u8]) -> Result<Self, ElfLoadError> {
+        if image.len() < ELF_HEADER_SIZE {
+            return Err(ElfLoadError::Truncated);
+        }
+        if image[0..4] != ELF_MAGIC {
+            return Err(ElfLoadError::NotAnElf);
+        }
+        if image[4] != ELF_CLASS_64
+            || image[5] != ELF_DATA_LITTLE_ENDIAN
+            || image[6] != ELF_VERSION_CURRENT
+        {
+            return Err(ElfLoadError::UnsupportedFormat);
+        }
+        if read_u16(image, 18) != ELF_MACHINE_X86_64 {
+            return Err(ElfLoadError::UnsupportedMachine);
+        }
+        if read_u16(image, 16) != ELF_TYPE_EXECUTABLE {
+            return Err(ElfLoadError::NotAnExecutable);
+        }
+        if read_u16(image, 54) as usize != PROGRAM_HEADER_SIZE {
+            return Err(ElfLoadError::UnsupportedFormat);
+        }
+        let header = ElfHeader {
+            entry: read_u64(image, 24),
+            program_header_offset: read_u64(image, 32),
+            program_header_count: read_u16(image, 56),
+        };
+        let table_end = (header.program_header_count as u64 * PROGRAM_HEADER_SIZE as u64)
+            .checked_add(header.program_header_offset)
+            .ok_or(ElfLoadError::Truncated)?;
+        if table_end > image.len() as u64 {
+            return Err(ElfLoadError::Truncated);
+        }
+        Ok(header)
+    }
+
+    /// Returns the entries of the program header table.
+    ///
+    /// The table bounds are validated by `parse`.
+    pub fn program_headers<'a>(&self, image: &'a [u8]) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let start = self.program_header_offset as usize;
+        (0..self.program_header_count as usize).map(move |index| {
+            let entry = start + index * PROGRAM_HEADER_SIZE;
+            ProgramHeader {
+                segment_type: read_u32(image, entry),
+                flags: read_u32(image, entry + 4),
+                offset: read_u64(image, entry + 8),
+                virtual_address: read_u64(image, entry + 16),
+                file_size: read_u64(image, entry + 32),
+                memory_size: read_u64(image, entry + 40),
+            }
+        })
+    }
+}
@@ -0,0 +1,201 @@
+use alloc::{sync::Arc, vec::Vec};
+use internal_utils::{HexNumber, kernel_information::KERNEL_INFORMATION, logln};
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{Page, PageSize, PageTableFlags, PhysFrame, Size4KiB},
+};
+
+use crate::{
+    addressing::{USER_SPACE_START, USER_STACK_SIZE, USER_STACK_TOP},
+    processes::{
+        add_process, enqueue_thread,
+        memory_areas::{MemoryArea, MemoryAreas},
+        memory_mapper::{
+            clear_user_mode_mapping, get_user_mode_mapping, map_user_pages, write_user_mapping,
+        },
+        next_process_id,
+        process::Process,
+        running_thread,
+        thread::Thread,
+    },
+};
+
+mod error;
+mod header;
+
+pub use error::ElfLoadError;
+use header::{ElfHeader, PF_W, PF_X, PT_INTERP, PT_LOAD, ProgramHeader};
+
+/// Loads a statically linked x86_64 ELF executable into a new address space
+/// and adds it to the scheduler as a user process with a single thread at the entry point.
+///
+/// The process becomes a child of the running process (or of the kernel, if nothing runs yet).
+pub fn load_elf_process(image: &[u8]) -> Result<Arc<Mutex<Process>>, ElfLoadError> {
+    let header = ElfHeader::parse(image)?;
+    let segments = get_loadable_segments(&header, image)?;
+
+    let level_4_frame = unsafe { get_user_mode_mapping() }.ok_or(ElfLoadError::OutOfMemory)?;
+    let memory_areas = match unsafe { map_process_memory(level_4_frame, &segments, image) } {
+        Ok(memory_areas) => memory_areas,
+        Err(error) => {
+            unsafe { clear_user_mode_mapping(level_4_frame) }
+                .expect("Page tables are frame-aligned");
+            return Err(error);
+        }
+    };
+
+    let parent_id = running_thread().map_or(0, |thread| thread.lock().process.lock().id);
+    let mut process = Process::create_user(next_process_id(), parent_id, level_4_frame);
+    process.memory_areas = memory_areas;
+    let process = Arc::new(Mutex::new(process));
+    let thread = unsafe {
+        Thread::new_native(
+            header.entry as usize,
+            (USER_STACK_TOP - 16) as usize,
+            process.clone(),
+        )
+    };
+    // Dropping the process frees its address space
+    let Some(thread) = thread else {
+        return Err(ElfLoadError::OutOfMemory);
+    };
+    add_process(&process);
+    enqueue_thread(thread);
+    logln!(
+        "Loaded process {} with entry point at {}",
+        process.lock().id,
+        header.entry.to_separated_hex()
+    );
+    Ok(process)
+}
+
+/// Loads the init image the bootloader was given with the kernel, like `load_elf_process`.
+///
+/// Returns `None` if the kernel was booted without one.
+pub fn start_init_process() -> Option<Result<Arc<Mutex<Process>>, ElfLoadError>> {
+    let image = KERNEL_INFORMATION.get()?.init_image?;
+    Some(load_elf_process(image))
+}
+
+/// Returns the PT_LOAD segments of the image, checking they can be loaded into the user address space.
+fn get_loadable_segments(
+    header: &ElfHeader,
+    image: &[u8],
+) -> Result<Vec<ProgramHeader>, ElfLoadError> {
+    let mut segments = Vec::new();
+    for program_header in header.program_headers(image) {
+        match program_header.segment_type {
+            PT_INTERP => return Err(ElfLoadError::NotAnExecutable),
+            PT_LOAD => {}
+            _ => continue,
+        }
+        if program_header.memory_size == 0 {
+            continue;
+        }
+        let file_end = program_header
+            .offset
+            .checked_add(program_header.file_size)
+            .ok_or(ElfLoadError::InvalidSegment)?;
+        let memory_end = program_header
+            .virtual_address
+            .checked_add(program_header.memory_size)
+            .ok_or(ElfLoadError::InvalidSegment)?;
+        if program_header.file_size > program_header.memory_size
+            || file_end > image.len() as u64
+            || program_header.virtual_address < USER_SPACE_START
+            || memory_end > USER_STACK_TOP - USER_STACK_SIZE
+        {
+            return Err(ElfLoadError::InvalidSegment);
+        }
+        segments.push(program_header);
+    }
+
+    let entry_is_executable = segments.iter().any(|segment| {
+        segment.flags & PF_X != 0
+            && (segment.virtual_address..segment.virtual_address + segment.memory_size)
+                .contains(&header.entry)
+    });
+    if !entry_is_executable {
+        return Err(ElfLoadError::InvalidEntryPoint);
+    }
+    Ok(segments)
+}
+
+/// Sets up the memory areas of the segments and the user stack, copying the segments' data.
+///
+/// Only the pages holding data from the file are mapped right away,
+/// the rest (e.g. `.bss` and the stack) gets mapped on the first access.
+unsafe fn map_process_memory(
+    level_4_frame: PhysFrame,
+    segments: &[ProgramHeader],
+    image: &[u8],
+) -> Result<MemoryAreas, ElfLoadError> {
+    let mut memory_areas = MemoryAreas::default();
+    for segment in segments {
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if segment.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if segment.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        let start = VirtAddr::new(segment.virtual_address);
+        let mut area = MemoryArea::new(start, segment.memory_size, flags);
+
+        // Segments can share a page with the previous one - it's mapped right away with the permissions of both
+        if let Some(previous) = memory_areas.last()
+            && previous.end > area.start
+        {
+            if previous.end > area.start + Size4KiB::SIZE {
+                return Err(ElfLoadError::InvalidSegment);
+            }
+            let shared_page = Page::containing_address(area.start);
+            // Mapping an already mapped page merges the flags
+            for flags in [previous.flags, flags] {
+                unsafe {
+                    map_user_pages(
+                        level_4_frame,
+                        Page::range_inclusive(shared_page, shared_page),
+                        flags,
+                    )
+                }
+                .map_err(|_| ElfLoadError::OutOfMemory)?;
+            }
+            area.start += Size4KiB::SIZE;
+        }
+
+        if segment.file_size > 0 {
+            let data_end = start + (segment.file_size - 1);
+            let data_pages = Page::range_inclusive(
+                Page::containing_address(start),
+                Page::containing_address(data_end),
+            );
+            unsafe { map_user_pages(level_4_frame, data_pages, flags) }
+                .map_err(|_| ElfLoadError::OutOfMemory)?;
+
+            let data =
+                &image[segment.offset as usize..(segment.offset + segment.file_size) as usize];
+            unsafe { write_user_mapping(level_4_frame, start, data) }
+                .expect("The segment has just been mapped");
+        }
+        if !area.is_empty() {
+            memory_areas
+                .add(area)
+                .map_err(|_| ElfLoadError::InvalidSegment)?;
+        }
+    }
+
+    let stack_flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::NO_EXECUTE;
+    memory_areas
+        .add(MemoryArea::new(
+            VirtAddr::new(USER_STACK_TOP - USER_STACK_SIZE),
+            USER_STACK_SIZE,
+            stack_flags,
+        ))
+        .map_err(|_| ElfLoadError::InvalidSegment)?;
+    Ok(memory_areas)
+}
@@ -0,0 +1,105 @@
+use core::fmt::Display;
+
+use alloc::sync::Arc;
+use internal_utils::logln;
+use rost_user::SysCallError;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::smp::tlb::{AddressSpace, TlbFlush};
+
+use super::{
+    add_process, enqueue_thread, fpu_state::FpuState, memory_mapper::clone_user_mapping,
+    next_process_id, process::Process, thread::Thread,
+};
+
+/// The errors of cloning a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForkError {
+    /// Kernel processes share the kernel's address space, so they can't be cloned
+    NotUserProcess,
+    /// There is not enough memory for the page tables or the thread of the copy
+    OutOfMemory,
+}
+
+impl Display for ForkError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ForkError::NotUserProcess => write!(f, "Only user processes can be cloned"),
+            ForkError::OutOfMemory => write!(f, "Not enough memory to clone the process"),
+        }
+    }
+}
+
+impl From<ForkError> for SysCallError {
+    fn from(error: ForkError) -> Self {
+        match error {
+            ForkError::NotUserProcess => SysCallError::InvalidArgument,
+            ForkError::OutOfMemory => SysCallError::OutOfMemory,
+        }
+    }
+}
+
+/// Clones the process of the thread into a new child process,
+/// with a single thread continuing from the saved state of the given one.
+///
+/// The address spaces share their frames until either of the processes writes to them,
+/// so cloning costs only the page tables, whatever the size of the process.
+/// Like with `fork`, the thread of the copy sees 0 in RAX.
+pub fn fork_process(thread: &Arc<Mutex<Thread>>) -> Result<Arc<Mutex<Process>>, ForkError> {
+    let (process, registers_state) = {
+        let thread = thread.lock();
+        (thread.process.clone(), thread.registers_state.clone())
+    };
+    let fpu_state = FpuState::copy_of(thread);
+
+    // The areas and the pages are copied at once, so another thread can't map or unmap in between.
+    // The timer interrupt locks the processes too
+    let (parent_id, memory_areas, child_level_4_frame, flush) = without_interrupts(|| {
+        let process = process.lock();
+        if process.kernel_process {
+            return Err(ForkError::NotUserProcess);
+        }
+        let level_4_frame = process.cr3.0;
+        let mut flush = TlbFlush::new(AddressSpace::User(level_4_frame));
+        let child_level_4_frame = unsafe { clone_user_mapping(level_4_frame, &mut flush) };
+        Ok((
+            process.id,
+            process.memory_areas.clone(),
+            child_level_4_frame,
+            flush,
+        ))
+    })?;
+    // The other threads of the process have to lose their write access before the child can run
+    flush.shoot_down();
+    let child_level_4_frame = child_level_4_frame.ok_or(ForkError::OutOfMemory)?;
+    let mut child = Process::create_user(next_process_id(), parent_id, child_level_4_frame);
+    child.memory_areas = memory_areas;
+    let child = Arc::new(Mutex::new(child));
+
+    // The thread is only queued once it's fully set up
+    let child_thread = without_interrupts(|| {
+        let child_thread = unsafe {
+            Thread::new_native(
+                registers_state.rip.as_u64() as usize,
+                registers_state.rsp.as_u64() as usize,
+                child.clone(),
+            )
+        }?;
+        {
+            let mut child_thread = child_thread.lock();
+            child_thread.registers_state = registers_state;
+            child_thread.registers_state.rax = 0;
+            child_thread.fpu_state = fpu_state;
+        }
+        add_process(&child);
+        Some(child_thread)
+    });
+    // Dropping the child frees its address space
+    let Some(child_thread) = child_thread else {
+        return Err(ForkError::OutOfMemory);
+    };
+    enqueue_thread(child_thread);
+    logln!("Process {} cloned into {}", parent_id, child.lock().id);
+    Ok(child)
+}
@@ -0,0 +1,252 @@
+use core::{
+    alloc::Layout,
+    arch::{
+        asm,
+        x86_64::{__cpuid, __cpuid_count},
+    },
+    fmt::Debug,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use alloc::{
+    alloc::{alloc_zeroed, dealloc, handle_alloc_error},
+    sync::{Arc, Weak},
+};
+use internal_utils::logln;
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts::without_interrupts,
+    registers::{
+        control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+        xcontrol::{XCr0, XCr0Flags},
+    },
+};
+
+use super::{running_thread, thread::Thread};
+use crate::smp::{MAX_CPUS, current_cpu_index};
+
+/// The size of the legacy `FXSAVE` area, which is also the beginning of the `XSAVE` area.
+const FXSAVE_AREA_SIZE: usize = 512;
+/// `XSAVE` needs its area aligned to 64 bytes (`FXSAVE` only needs 16).
+const SAVE_AREA_ALIGNMENT: usize = 64;
+
+/// The default x87 control word - all exceptions masked, 64-bit precision, rounding to nearest.
+const DEFAULT_FCW: u16 = 0x037F;
+/// The default MXCSR - all exceptions masked, rounding to nearest.
+const DEFAULT_MXCSR: u32 = 0x1F80;
+
+/// Whether the CPU saves the state with `XSAVE` (and so also saves the AVX registers).
+static USE_XSAVE: AtomicBool = AtomicBool::new(false);
+/// The size of a save area, depending on the state components enabled.
+static SAVE_AREA_SIZE: AtomicUsize = AtomicUsize::new(FXSAVE_AREA_SIZE);
+
+/// The thread whose FPU/SSE/AVX state is currently loaded in every processor, by CPU index.
+///
+/// A thread's state stays in its processor until another thread uses the FPU there,
+/// so the threads owning an FPU can't run on other processors.
+static FPU_OWNERS: [Mutex<Option<Weak<Mutex<Thread>>>>; MAX_CPUS] =
+    [const { Mutex::new(None) }; MAX_CPUS];
+
+/// Enables the FPU, SSE and (if supported) AVX for the threads, and picks the way to save their state.
+///
+/// Has to be called before any thread is created.
+pub fn init_fpu() {
+    if let Some(enabled) = enable_fpu() {
+        // EBX holds the size needed for the components enabled in XCR0
+        let size = __cpuid_count(0xD, 0).ebx as usize;
+        SAVE_AREA_SIZE.store(size.max(FXSAVE_AREA_SIZE), Ordering::Relaxed);
+        USE_XSAVE.store(true, Ordering::Relaxed);
+        logln!("FPU state saved with XSAVE ({:?}, {} bytes)", enabled, size);
+    } else {
+        logln!("FPU state saved with FXSAVE");
+    }
+}
+
+/// Enables the FPU, SSE and (if supported) AVX on the running processor.
+///
+/// Returns the state components `XSAVE` saves, or `None` if the processor doesn't support it.
+pub fn enable_fpu() -> Option<XCr0Flags> {
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+
+    let supports_xsave = __cpuid(1).ecx & (1 << 26) != 0;
+    if !supports_xsave {
+        return None;
+    }
+    let supported = XCr0Flags::from_bits_truncate(__cpuid_count(0xD, 0).eax as u64);
+    let enabled = supported
+        & (XCr0Flags::X87
+            | XCr0Flags::SSE
+            | XCr0Flags::AVX
+            | XCr0Flags::OPMASK
+            | XCr0Flags::ZMM_HI256
+            | XCr0Flags::HI16_ZMM);
+    unsafe {
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSXSAVE));
+        XCr0::write(enabled);
+    }
+    Some(enabled)
+}
+
+/// The saved FPU/SSE/AVX registers of a thread.
+pub struct FpuState {
+    area: NonNull<u8>,
+}
+
+// The area is only ever accessed through the thread owning it.
+unsafe impl Send for FpuState {}
+
+impl FpuState {
+    /// Creates the state a thread starts with - empty registers and all exceptions masked.
+    pub fn new() -> Self {
+        let layout = Self::layout();
+        let area = NonNull::new(unsafe { alloc_zeroed(layout) })
+            .unwrap_or_else(|| handle_alloc_error(layout));
+        // A zeroed XSAVE header makes XRSTOR initialize all the components except for MXCSR
+        unsafe {
+            area.cast::<u16>().write(DEFAULT_FCW);
+            area.byte_add(24).cast::<u32>().write(DEFAULT_MXCSR);
+        }
+        FpuState { area }
+    }
+
+    /// Returns a copy of the thread's FPU/SSE/AVX registers.
+    pub fn copy_of(thread: &Arc<Mutex<Thread>>) -> Self {
+        let copy = FpuState::new();
+        without_interrupts(|| {
+            // The thread's newest state could still be only in the CPU
+            if is_fpu_owner(thread) {
+                save_loaded_state(thread);
+            }
+            unsafe {
+                copy.area.copy_from_nonoverlapping(
+                    thread.lock().fpu_state.area,
+                    SAVE_AREA_SIZE.load(Ordering::Relaxed),
+                );
+            }
+        });
+        copy
+    }
+
+    fn layout() -> Layout {
+        Layout::from_size_align(SAVE_AREA_SIZE.load(Ordering::Relaxed), SAVE_AREA_ALIGNMENT)
+            .unwrap()
+    }
+
+    /// Stores the CPU's FPU/SSE/AVX registers in the area.
+    fn save(&mut self) {
+        let area = self.area.as_ptr();
+        unsafe {
+            if USE_XSAVE.load(Ordering::Relaxed) {
+                asm!("xsave64 [{}]", in(reg) area, in("eax") u32::MAX, in("edx") u32::MAX, options(nostack));
+            } else {
+                asm!("fxsave64 [{}]", in(reg) area, options(nostack));
+            }
+        }
+    }
+
+    /// Loads the CPU's FPU/SSE/AVX registers from the area.
+    fn restore(&self) {
+        let area = self.area.as_ptr();
+        unsafe {
+            if USE_XSAVE.load(Ordering::Relaxed) {
+                asm!("xrstor64 [{}]", in(reg) area, in("eax") u32::MAX, in("edx") u32::MAX, options(nostack, readonly));
+            } else {
+                asm!("fxrstor64 [{}]", in(reg) area, options(nostack, readonly));
+            }
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.area.as_ptr(), Self::layout()) };
+    }
+}
+
+impl Debug for FpuState {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FpuState")
+            .field("xsave", &USE_XSAVE.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Prepares the lazy FPU switch before the thread gets dispatched.
+///
+/// If the CPU holds another thread's FPU state, the first FPU instruction of the thread
+/// causes a #NM exception, which swaps the states in `handle_fpu_trap`.
+pub fn prepare_fpu(thread: &Arc<Mutex<Thread>>) {
+    let owns_fpu = is_fpu_owner(thread);
+    unsafe { Cr0::update(|flags| flags.set(Cr0Flags::TASK_SWITCHED, !owns_fpu)) };
+}
+
+/// Checks if the thread's FPU state is the one loaded in the CPU.
+fn is_fpu_owner(thread: &Arc<Mutex<Thread>>) -> bool {
+    owns_fpu(current_cpu_index(), thread)
+}
+
+/// Checks if the thread's FPU state is the one loaded in the processor.
+pub(super) fn owns_fpu(cpu_index: usize, thread: &Arc<Mutex<Thread>>) -> bool {
+    FPU_OWNERS[cpu_index]
+        .lock()
+        .as_ref()
+        .is_some_and(|owner| owner.as_ptr() == Arc::as_ptr(thread))
+}
+
+/// Saves the thread's FPU state if it's loaded in the CPU, so the thread can run on another processor.
+pub(super) fn release_fpu(thread: &Arc<Mutex<Thread>>) {
+    without_interrupts(|| {
+        let mut owner = FPU_OWNERS[current_cpu_index()].lock();
+        if owner
+            .as_ref()
+            .is_some_and(|owner| owner.as_ptr() == Arc::as_ptr(thread))
+        {
+            save_loaded_state(thread);
+            *owner = None;
+        }
+    });
+}
+
+/// Saves the FPU state loaded in the CPU into the thread, keeping the lazy switch prepared.
+fn save_loaded_state(thread: &Arc<Mutex<Thread>>) {
+    let task_switched = Cr0::read().contains(Cr0Flags::TASK_SWITCHED);
+    unsafe { asm!("clts", options(nomem, nostack)) };
+    thread.lock().fpu_state.save();
+    if task_switched {
+        unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED)) };
+    }
+}
+
+/// Gives the FPU to the running thread, saving the state of the previous owner.
+///
+/// Returns `false` if there is no running thread the FPU could be given to.
+pub fn handle_fpu_trap() -> bool {
+    let Some(thread) = running_thread() else {
+        return false;
+    };
+    unsafe { asm!("clts", options(nomem, nostack)) };
+
+    let mut owner = FPU_OWNERS[current_cpu_index()].lock();
+    if let Some(previous_owner) = owner.as_ref().and_then(Weak::upgrade) {
+        if Arc::ptr_eq(&previous_owner, &thread) {
+            return true;
+        }
+        previous_owner.lock().fpu_state.save();
+    }
+    thread.lock().fpu_state.restore();
+    *owner = Some(Arc::downgrade(&thread));
+    true
+}
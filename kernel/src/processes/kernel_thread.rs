@@ -0,0 +1,81 @@
+use alloc::{boxed::Box, sync::Arc};
+use spin::Mutex;
+use x86_64::instructions::interrupts::{self, without_interrupts};
+
+use crate::{memory::guarded_stack::GuardedStack, smp::CpuMask};
+
+use super::{
+    KERNEL_PROCESS,
+    scheduler::{
+        drop_unused_exited_threads, enqueue_thread, exit_thread, release_exited_thread,
+        run_processes, running_thread,
+    },
+    thread::Thread,
+};
+
+type KernelThreadFunction = Box<dyn FnOnce() + Send>;
+
+/// Spawns a thread of the kernel process, running the function in ring 0.
+///
+/// The thread gets its own guarded stack, which is freed after the thread exits.
+/// The thread exits when the function returns, or by calling `exit_kernel_thread`.
+pub fn spawn_kernel_thread<F>(function: F) -> Arc<Mutex<Thread>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_kernel_thread_on(CpuMask::ALL, function)
+}
+
+/// Spawns a kernel thread like `spawn_kernel_thread`, which only runs on the processors of the affinity.
+pub fn spawn_kernel_thread_on<F>(affinity: CpuMask, function: F) -> Arc<Mutex<Thread>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    drop_unused_exited_threads();
+    let process = KERNEL_PROCESS
+        .get()
+        .expect("The scheduler has to be initialized first");
+    let stack = GuardedStack::new().expect("Not enough memory for a kernel thread stack");
+    // Boxing twice, as the trampoline gets the function as a thin pointer
+    let function: Box<KernelThreadFunction> = Box::new(Box::new(function));
+
+    // The thread is only queued once it's fully set up
+    let thread = without_interrupts(|| {
+        let thread = unsafe {
+            Thread::new_native(
+                kernel_thread_trampoline as *const () as usize,
+                // Aligning the stack like a `call` would
+                (stack.top().as_u64() - 8) as usize,
+                process,
+            )
+        }
+        .expect("Not enough memory for a kernel thread's kernel stack");
+        {
+            let mut thread_mut = thread.lock();
+            thread_mut.registers_state.rdi = Box::into_raw(function) as u64;
+            stack.set_owner(thread_mut.stack_owner());
+            thread_mut.stack = Some(stack);
+            thread_mut.affinity = affinity;
+        }
+        thread
+    });
+    enqueue_thread(thread.clone());
+    thread
+}
+
+/// The entry point of every kernel thread, which runs its function and exits.
+extern "sysv64" fn kernel_thread_trampoline(function: *mut KernelThreadFunction) -> ! {
+    let function = unsafe { Box::from_raw(function) };
+    function();
+    exit_kernel_thread();
+}
+
+/// Exits the running kernel thread and switches to another thread.
+pub fn exit_kernel_thread() -> ! {
+    interrupts::disable();
+    let thread = running_thread().expect("Only a running thread can exit");
+    exit_thread(thread.clone(), 0);
+    // We're still running on the thread's stack, so it has to outlive this function
+    release_exited_thread(thread);
+    run_processes();
+}
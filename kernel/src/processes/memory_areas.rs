@@ -0,0 +1,193 @@
+use core::fmt::Display;
+
+use alloc::vec::Vec;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{Page, PageSize, PageTableFlags, Size4KiB, page::PageRange},
+};
+
+/// The kinds of memory accesses a page fault can be caused by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+    Execute,
+}
+
+impl MemoryAccess {
+    /// Checks if pages mapped with the flags allow the access.
+    pub fn is_allowed_by(self, flags: PageTableFlags) -> bool {
+        match self {
+            MemoryAccess::Read => true,
+            MemoryAccess::Write => flags.contains(PageTableFlags::WRITABLE),
+            MemoryAccess::Execute => !flags.contains(PageTableFlags::NO_EXECUTE),
+        }
+    }
+}
+
+/// A page-aligned range of a process's address space, which the process is allowed to use.
+///
+/// The pages of an area are only backed by frames once the process touches them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryArea {
+    /// The first address of the area.
+    pub start: VirtAddr,
+    /// The first address after the area.
+    pub end: VirtAddr,
+    /// The flags the pages of the area are mapped with.
+    pub flags: PageTableFlags,
+}
+
+impl MemoryArea {
+    /// Creates an area covering all pages the given range touches.
+    pub fn new(start: VirtAddr, length: u64, flags: PageTableFlags) -> Self {
+        MemoryArea {
+            start: start.align_down(Size4KiB::SIZE),
+            end: (start + length).align_up(Size4KiB::SIZE),
+            flags,
+        }
+    }
+
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// Returns the pages of the area.
+    pub fn pages(&self) -> PageRange {
+        Page::range(
+            Page::containing_address(self.start),
+            Page::containing_address(self.end),
+        )
+    }
+
+    /// Checks if the process is allowed the access to the area.
+    pub fn allows(&self, access: MemoryAccess) -> bool {
+        access.is_allowed_by(self.flags)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAreaError {
+    /// The area overlaps an area the process already has
+    Overlapping,
+    /// A part of the range is not inside any area
+    NotMapped,
+}
+
+impl Display for MemoryAreaError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MemoryAreaError::Overlapping => write!(f, "The memory area overlaps another one"),
+            MemoryAreaError::NotMapped => write!(f, "The range is not fully mapped"),
+        }
+    }
+}
+
+/// The memory areas of a process, sorted by their addresses.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryAreas {
+    areas: Vec<MemoryArea>,
+}
+
+impl MemoryAreas {
+    /// Adds the area, which can't overlap any of the existing ones.
+    pub fn add(&mut self, area: MemoryArea) -> Result<(), MemoryAreaError> {
+        let index = self.areas.partition_point(|other| other.start < area.start);
+        let overlaps_previous = index > 0 && self.areas[index - 1].end > area.start;
+        let overlaps_next = self
+            .areas
+            .get(index)
+            .is_some_and(|other| other.start < area.end);
+        if overlaps_previous || overlaps_next {
+            return Err(MemoryAreaError::Overlapping);
+        }
+        self.areas.insert(index, area);
+        Ok(())
+    }
+
+    /// Returns the area containing the address.
+    pub fn find(&self, address: VirtAddr) -> Option<&MemoryArea> {
+        let index = self.areas.partition_point(|area| area.start <= address);
+        self.areas[..index]
+            .last()
+            .filter(|area| area.contains(address))
+    }
+
+    /// Returns the lowest free range of the given length between the two addresses.
+    pub fn find_free_range(&self, length: u64, start: VirtAddr, end: VirtAddr) -> Option<VirtAddr> {
+        let mut candidate = start.as_u64();
+        for area in self.areas.iter() {
+            if area.end.as_u64() <= candidate {
+                continue;
+            }
+            if area.start.as_u64() >= candidate.checked_add(length)? {
+                break;
+            }
+            candidate = area.end.as_u64();
+        }
+        (candidate.checked_add(length)? <= end.as_u64()).then(|| VirtAddr::new(candidate))
+    }
+
+    /// Removes the parts of the areas inside the page-aligned range, returning them.
+    pub fn remove_range(&mut self, start: VirtAddr, end: VirtAddr) -> Vec<MemoryArea> {
+        self.split_at(start);
+        self.split_at(end);
+        self.areas
+            .extract_if(.., |area| start <= area.start && area.end <= end)
+            .collect()
+    }
+
+    /// Changes the flags of the page-aligned range, which has to be fully covered by the areas.
+    pub fn protect_range(
+        &mut self,
+        start: VirtAddr,
+        end: VirtAddr,
+        flags: PageTableFlags,
+    ) -> Result<(), MemoryAreaError> {
+        let mut covered_until = start;
+        for area in self.areas.iter() {
+            if area.end <= covered_until || covered_until >= end {
+                continue;
+            }
+            if area.start > covered_until {
+                break;
+            }
+            covered_until = area.end;
+        }
+        if covered_until < end {
+            return Err(MemoryAreaError::NotMapped);
+        }
+
+        self.split_at(start);
+        self.split_at(end);
+        self.areas
+            .iter_mut()
+            .filter(|area| start <= area.start && area.end <= end)
+            .for_each(|area| area.flags = flags);
+        Ok(())
+    }
+
+    /// Splits the area containing the address in two, so that an area starts at the address.
+    fn split_at(&mut self, address: VirtAddr) {
+        let index = self.areas.partition_point(|area| area.start < address);
+        if index > 0 && self.areas[index - 1].end > address {
+            let mut second_half = self.areas[index - 1];
+            self.areas[index - 1].end = address;
+            second_half.start = address;
+            self.areas.insert(index, second_half);
+        }
+    }
+
+    /// Returns the area with the highest addresses.
+    pub fn last(&self) -> Option<&MemoryArea> {
+        self.areas.last()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MemoryArea> {
+        self.areas.iter()
+    }
+}
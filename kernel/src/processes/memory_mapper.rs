@@ -1,128 +1,444 @@
 use core::sync::atomic::fence;
 
-use internal_utils::{kernel_information::KERNEL_INFORMATION, logln};
+use internal_utils::{
+    kernel_information::{KERNEL_INFORMATION, frame_allocator::FullFrameAllocator},
+    logln,
+};
 use x86_64::{
-    PhysAddr,
+    PhysAddr, VirtAddr,
     structures::paging::{
-        PageTable, PageTableFlags, PhysFrame, Size2MiB, Size4KiB, page::AddressNotAligned,
+        Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PageTableIndex,
+        PhysFrame, Size2MiB, Size4KiB, Translate,
+        mapper::{MapToError, MappedFrame, TranslateResult},
+        page::{AddressNotAligned, PageRange, PageRangeInclusive},
+        page_table::PageTableEntry,
     },
 };
 
-/// Initializes and returns the level-4 page table that maps memory for a user-mode process.
-pub unsafe fn get_user_mode_mapping() -> Option<(PhysFrame, PhysAddr)> {
+use crate::{addressing::USER_SPACE_END, memory::kernel_level_4_frame, smp::tlb::TlbFlush};
+
+/// The index of the first level 4 entry that is shared with the kernel's page table.
+const FIRST_KERNEL_ENTRY: usize = (USER_SPACE_END >> 39) as usize;
+
+/// Marks a user page whose frame is shared with another address space until either of them writes to it.
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// The flags of the page tables leading to user pages.
+const USER_TABLE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+/// Returns a reference to the page table stored in the given frame.
+unsafe fn table_at(frame: PhysFrame, pmo: u64) -> &'static mut PageTable {
+    let table = (frame.start_address().as_u64() + pmo) as *mut PageTable;
+    unsafe { table.as_mut().unwrap() }
+}
+
+/// Returns a mapper for the page tables of a (not necessarily active) address space.
+unsafe fn user_mode_mapper(level_4_frame: PhysFrame, pmo: u64) -> OffsetPageTable<'static> {
+    unsafe { OffsetPageTable::new(table_at(level_4_frame, pmo), VirtAddr::new(pmo)) }
+}
+
+/// Initializes and returns the level-4 page table for a new user-mode address space.
+///
+/// The user half starts empty, while the rest of the entries are shared with the kernel's page table,
+/// so the kernel code, stacks and heap stay mapped while the process is running.
+pub unsafe fn get_user_mode_mapping() -> Option<PhysFrame> {
     let kernel_info = KERNEL_INFORMATION.get().unwrap();
     let pmo = kernel_info.physical_memory_offset;
-    let allocator = kernel_info.allocator;
 
     logln!("Creating user mode mapping");
 
-    let mut allocator = allocator.lock();
-    let level_4_frame: PhysFrame<Size4KiB> = allocator.allocate_frame()?;
-    let level_3_frame: PhysFrame<Size4KiB> = allocator.allocate_frame()?;
-    let level_2_frame: PhysFrame<Size4KiB> = allocator.allocate_frame()?;
-
-    let page_table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-    let user_page_table_flags = page_table_flags | PageTableFlags::USER_ACCESSIBLE;
-
-    let level_4_table_address = level_4_frame.start_address();
-    let level_3_table_address = level_3_frame.start_address();
-    let level_2_table_address = level_2_frame.start_address();
-    // Just take the mapping from the bootloader's page tables
-    let (level_2_kernel_data_table_address, level_2_kernel_stack_table_address) =
-        unsafe { get_kernel_data_and_stack_level_2_table_addresses(pmo) };
-
-    let level_4_table = (level_4_table_address.as_u64() + pmo) as *mut PageTable;
-    let level_4_table = unsafe { level_4_table.as_mut().unwrap() };
-    // Mapping 0x0000_0000_0000 to level 3 table
-    level_4_table[0].set_addr(level_3_table_address, user_page_table_flags);
-
-    let level_3_table = (level_3_table_address.as_u64() + pmo) as *mut PageTable;
-    let level_3_table = unsafe { level_3_table.as_mut().unwrap() };
-    // Mapping 0x0000_0000_0000 to level 2 table
-    level_3_table[0].set_addr(level_2_table_address, user_page_table_flags);
-    // Mapping 0x007F_8000_0000 to kernel stack
-    level_3_table[510].set_addr(level_2_kernel_stack_table_address, page_table_flags);
-    // Mapping 0x007F_C000_0000 to kernel data
-    level_3_table[511].set_addr(level_2_kernel_data_table_address, page_table_flags);
-
-    let level_2_table = (level_2_table_address.as_u64() + pmo) as *mut PageTable;
-    // Mapping level 2 entries to 2mb frames
-    let level_2_table = unsafe { level_2_table.as_mut().unwrap() };
-    level_2_table
-        .iter_mut()
-        .take(8) // We're mapping 16mb for now, e.g. 0x0100_0000
-        .for_each(|entry| {
-            let frame: PhysFrame<Size2MiB> = allocator
+    let level_4_frame: PhysFrame<Size4KiB> = kernel_info.allocator.lock().allocate_frame()?;
+    let level_4_table = unsafe { table_at(level_4_frame, pmo) };
+    let kernel_level_4_table = unsafe { table_at(kernel_level_4_frame(), pmo) };
+
+    for (index, entry) in level_4_table.iter_mut().enumerate() {
+        let kernel_entry = &kernel_level_4_table[index];
+        if index < FIRST_KERNEL_ENTRY || kernel_entry.is_unused() {
+            entry.set_unused();
+        } else {
+            entry.set_addr(kernel_entry.addr(), kernel_entry.flags());
+        }
+    }
+
+    Some(level_4_frame)
+}
+
+/// Maps zeroed frames to the given pages of a user-mode address space.
+///
+/// Pages that are already mapped keep their frame and get the permissions of both mappings,
+/// so e.g. two ELF segments sharing a page can both be accessed.
+pub unsafe fn map_user_pages(
+    level_4_frame: PhysFrame,
+    pages: PageRangeInclusive,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let kernel_info = KERNEL_INFORMATION.get().unwrap();
+    let pmo = kernel_info.physical_memory_offset;
+    let mut allocator = kernel_info.allocator.lock();
+    let mut mapper = unsafe { user_mode_mapper(level_4_frame, pmo) };
+
+    for page in pages {
+        if let TranslateResult::Mapped {
+            flags: current_flags,
+            ..
+        } = mapper.translate(page.start_address())
+        {
+            let no_execute = current_flags & flags & PageTableFlags::NO_EXECUTE;
+            let merged_flags = ((current_flags | flags) - PageTableFlags::NO_EXECUTE) | no_execute;
+            if let Ok(flush) = unsafe { mapper.update_flags(page, merged_flags) } {
+                flush.ignore();
+            }
+        } else {
+            let frame: PhysFrame<Size4KiB> = allocator
                 .allocate_frame()
-                .expect("Failed to allocate user process frame");
-            entry.set_addr(
-                frame.start_address(),
-                PageTableFlags::HUGE_PAGE | user_page_table_flags,
-            );
-        });
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            unsafe {
+                core::ptr::write_bytes(
+                    (frame.start_address().as_u64() + pmo) as *mut u8,
+                    0,
+                    frame.size() as usize,
+                );
+                mapper
+                    .map_to_with_table_flags(page, frame, flags, USER_TABLE_FLAGS, &mut *allocator)?
+                    .ignore();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Unmaps the mapped pages of the range from a user-mode address space.
+///
+/// Their frames are released once the flush is shot down.
+pub unsafe fn unmap_user_pages(level_4_frame: PhysFrame, pages: PageRange, flush: &mut TlbFlush) {
+    let pmo = KERNEL_INFORMATION.get().unwrap().physical_memory_offset;
+    let mut mapper = unsafe { user_mode_mapper(level_4_frame, pmo) };
+
+    for page in pages {
+        if let Ok((frame, unmapped)) = mapper.unmap(page) {
+            unmapped.ignore();
+            flush.add_unmapped_page(page, frame);
+        }
+    }
+}
+
+/// Changes the flags of the mapped pages of the range in a user-mode address space.
+///
+/// Pages whose frames are shared with another address space stay copy-on-write instead of becoming writable.
+pub unsafe fn protect_user_pages(
+    level_4_frame: PhysFrame,
+    pages: PageRange,
+    flags: PageTableFlags,
+    flush: &mut TlbFlush,
+) {
+    let kernel_info = KERNEL_INFORMATION.get().unwrap();
+    let pmo = kernel_info.physical_memory_offset;
+    let allocator = kernel_info.allocator.lock();
+    let mut mapper = unsafe { user_mode_mapper(level_4_frame, pmo) };
 
-    Some((level_4_frame, level_2_table[0].addr()))
+    for page in pages {
+        let TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            ..
+        } = mapper.translate(page.start_address())
+        else {
+            continue;
+        };
+        let mut page_flags = flags;
+        if flags.contains(PageTableFlags::WRITABLE) && allocator.get_frame_references(frame) > 1 {
+            page_flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+        }
+        if let Ok(updated) = unsafe { mapper.update_flags(page, page_flags) } {
+            updated.ignore();
+            flush.add_page(page);
+        }
+    }
 }
 
-unsafe fn get_kernel_data_and_stack_level_2_table_addresses(pmo: u64) -> (PhysAddr, PhysAddr) {
-    use x86_64::registers::control::Cr3;
-    let level4 = (Cr3::read().0.start_address().as_u64() + pmo) as *const PageTable;
-    let level4 = unsafe { level4.as_ref().unwrap() };
+/// Returns the flags of a page of a user-mode address space, if it's mapped.
+pub unsafe fn get_user_page_flags(level_4_frame: PhysFrame, page: Page) -> Option<PageTableFlags> {
+    let pmo = KERNEL_INFORMATION.get().unwrap().physical_memory_offset;
+    let mapper = unsafe { user_mode_mapper(level_4_frame, pmo) };
+    match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped { flags, .. } => Some(flags),
+        _ => None,
+    }
+}
 
-    let level3 = unsafe {
-        ((level4[0].addr().as_u64() + pmo) as *const PageTable)
-            .as_ref()
-            .unwrap()
+/// Makes a copy-on-write page writable again, copying its frame if another address space still uses it.
+///
+/// The frame the page used is only released once the flush is shot down,
+/// as the other threads of the process might still read it through their TLB.
+/// Returns `None` if the page is not mapped or there's no memory for the copy.
+pub unsafe fn copy_on_write(
+    level_4_frame: PhysFrame,
+    page: Page,
+    flush: &mut TlbFlush,
+) -> Option<()> {
+    let kernel_info = KERNEL_INFORMATION.get().unwrap();
+    let pmo = kernel_info.physical_memory_offset;
+    let mut allocator = kernel_info.allocator.lock();
+    let mut mapper = unsafe { user_mode_mapper(level_4_frame, pmo) };
+
+    let TranslateResult::Mapped {
+        frame: MappedFrame::Size4KiB(frame),
+        flags,
+        ..
+    } = mapper.translate(page.start_address())
+    else {
+        return None;
+    };
+    let flags = (flags | PageTableFlags::WRITABLE) - COPY_ON_WRITE;
+
+    // The last user of a frame can just take it over
+    if allocator.get_frame_references(frame) == 1 {
+        unsafe { mapper.update_flags(page, flags) }.ok()?.ignore();
+        flush.add_page(page);
+        return Some(());
+    }
+
+    let new_frame: PhysFrame<Size4KiB> = allocator.allocate_frame()?;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            (frame.start_address().as_u64() + pmo) as *const u8,
+            (new_frame.start_address().as_u64() + pmo) as *mut u8,
+            Size4KiB::SIZE as usize,
+        );
+        mapper.unmap(page).ok()?.1.ignore();
+        mapper
+            .map_to_with_table_flags(page, new_frame, flags, USER_TABLE_FLAGS, &mut *allocator)
+            .ok()?
+            .ignore();
+    }
+    // Adding the frame can allocate, which needs the frame allocator
+    drop(allocator);
+    flush.add_unmapped_page(page, frame);
+    Some(())
+}
+
+/// Creates a copy of a user-mode address space, sharing the frames between the two.
+///
+/// The writable pages of both address spaces become copy-on-write,
+/// so they only get copied once either of the address spaces writes to them.
+/// The threads of the original address space keep their write access until the flush is shot down.
+pub unsafe fn clone_user_mapping(
+    level_4_frame: PhysFrame,
+    flush: &mut TlbFlush,
+) -> Option<PhysFrame> {
+    let new_level_4_frame = unsafe { get_user_mode_mapping() }?;
+    let cloned = {
+        let kernel_info = KERNEL_INFORMATION.get().unwrap();
+        let pmo = kernel_info.physical_memory_offset;
+        let mut allocator = kernel_info.allocator.lock();
+        let mut new_mapper = unsafe { user_mode_mapper(new_level_4_frame, pmo) };
+        let level_4_table = unsafe { table_at(level_4_frame, pmo) };
+        unsafe { clone_user_pages(level_4_table, &mut new_mapper, &mut *allocator, pmo) }
     };
-    (level3[511].addr(), level3[510].addr())
+    // Even a partial copy took away write access from the original address space
+    flush.add_everything();
+
+    if cloned.is_none() {
+        unsafe { clear_user_mode_mapping(new_level_4_frame) }
+            .expect("Page tables are frame-aligned");
+        return None;
+    }
+    Some(new_level_4_frame)
+}
+
+fn is_present((_, entry): &(usize, &mut PageTableEntry)) -> bool {
+    !entry.is_unused()
+}
+
+/// Maps every user page of the level 4 table into the mapper, sharing the frames.
+unsafe fn clone_user_pages(
+    level_4_table: &mut PageTable,
+    new_mapper: &mut OffsetPageTable,
+    allocator: &mut (dyn FullFrameAllocator + Send + Sync),
+    pmo: u64,
+) -> Option<()> {
+    for (level_4_index, level_4_entry) in level_4_table
+        .iter_mut()
+        .enumerate()
+        .take(FIRST_KERNEL_ENTRY)
+        .filter(is_present)
+    {
+        let level_3_table =
+            unsafe { table_at(PhysFrame::containing_address(level_4_entry.addr()), pmo) };
+        for (level_3_index, level_3_entry) in
+            level_3_table.iter_mut().enumerate().filter(is_present)
+        {
+            // User address spaces are only made of 4K pages
+            if level_3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                return None;
+            }
+            let level_2_table =
+                unsafe { table_at(PhysFrame::containing_address(level_3_entry.addr()), pmo) };
+            for (level_2_index, level_2_entry) in
+                level_2_table.iter_mut().enumerate().filter(is_present)
+            {
+                if level_2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                    return None;
+                }
+                let level_1_table =
+                    unsafe { table_at(PhysFrame::containing_address(level_2_entry.addr()), pmo) };
+                for (level_1_index, level_1_entry) in
+                    level_1_table.iter_mut().enumerate().filter(is_present)
+                {
+                    let mut flags = level_1_entry.flags();
+                    if flags.contains(PageTableFlags::WRITABLE) {
+                        flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+                        level_1_entry.set_flags(flags);
+                    }
+                    let frame = PhysFrame::<Size4KiB>::containing_address(level_1_entry.addr());
+                    let page = Page::from_page_table_indices(
+                        PageTableIndex::new(level_4_index as u16),
+                        PageTableIndex::new(level_3_index as u16),
+                        PageTableIndex::new(level_2_index as u16),
+                        PageTableIndex::new(level_1_index as u16),
+                    );
+                    allocator.share_frame(frame);
+                    let mapped = unsafe {
+                        new_mapper.map_to_with_table_flags(
+                            page,
+                            frame,
+                            flags,
+                            USER_TABLE_FLAGS,
+                            &mut *allocator,
+                        )
+                    };
+                    match mapped {
+                        Ok(flush) => flush.ignore(),
+                        Err(_) => {
+                            unsafe { allocator.release_frame(frame) };
+                            return None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// Copies the data into already mapped memory of a user-mode address space.
+///
+/// Returns `None` if any of the target pages is not mapped.
+pub unsafe fn write_user_mapping(
+    level_4_frame: PhysFrame,
+    address: VirtAddr,
+    data: &[u8],
+) -> Option<()> {
+    let pmo = KERNEL_INFORMATION.get().unwrap().physical_memory_offset;
+    let mapper = unsafe { user_mode_mapper(level_4_frame, pmo) };
+
+    let mut written = 0;
+    while written < data.len() {
+        let target = address + written as u64;
+        let physical = mapper.translate_addr(target)?;
+        // We can only copy up to the end of the current page
+        let chunk = (Size4KiB::SIZE - (target.as_u64() % Size4KiB::SIZE)) as usize;
+        let chunk = chunk.min(data.len() - written);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data[written..].as_ptr(),
+                (physical.as_u64() + pmo) as *mut u8,
+                chunk,
+            );
+        }
+        written += chunk;
+    }
+    Some(())
+}
+
+/// Translates an address of a user-mode address space, checking that every level of the page tables
+/// allows user access to it (and writes, if requested).
+pub unsafe fn translate_user_address(
+    level_4_frame: PhysFrame,
+    address: VirtAddr,
+    write: bool,
+) -> Option<PhysAddr> {
+    let pmo = KERNEL_INFORMATION.get().unwrap().physical_memory_offset;
+    let mut required_flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if write {
+        required_flags |= PageTableFlags::WRITABLE;
+    }
+
+    let indices = [
+        address.p4_index(),
+        address.p3_index(),
+        address.p2_index(),
+        address.p1_index(),
+    ];
+    let mut table = unsafe { table_at(level_4_frame, pmo) };
+    for (level, index) in indices.into_iter().enumerate() {
+        let entry = &table[index];
+        if !entry.flags().contains(required_flags) {
+            return None;
+        }
+        // Level 3 and level 2 entries can map 1GiB and 2MiB pages directly
+        let is_huge_page = level > 0 && entry.flags().contains(PageTableFlags::HUGE_PAGE);
+        if level == indices.len() - 1 || is_huge_page {
+            let page_size = 1u64 << (12 + 9 * (indices.len() - 1 - level));
+            return Some(entry.addr() + (address.as_u64() & (page_size - 1)));
+        }
+        table = unsafe { table_at(PhysFrame::containing_address(entry.addr()), pmo) };
+    }
+    None
 }
 
 /// Clears the memory and page-table mapping for a given level 4 page table (assuming user process).
+///
+/// Only the user half of the address space is freed, as the rest is shared with the kernel.
 pub unsafe fn clear_user_mode_mapping(level_4_frame: PhysFrame) -> Result<(), AddressNotAligned> {
     let kernel_info = KERNEL_INFORMATION.get().unwrap();
     let pmo = kernel_info.physical_memory_offset;
     let allocator = kernel_info.allocator;
     let mut allocator = allocator.lock();
-    let level_4_table = {
-        let level_4_table = (level_4_frame.start_address().as_u64() + pmo) as *mut PageTable;
-        unsafe { level_4_table.as_mut().unwrap() }
-    };
+    let level_4_table = unsafe { table_at(level_4_frame, pmo) };
 
-    let level_3_addr = level_4_table[0].addr();
-    let level_3_frame: PhysFrame<Size4KiB> = PhysFrame::from_start_address(level_3_addr)?;
-    let level_3_table = {
-        let level_3_table = (level_3_addr.as_u64() + pmo) as *mut PageTable;
-        unsafe { level_3_table.as_mut().unwrap() }
-    };
-
-    let level_2_addr = level_3_table[0].addr();
-    let level_2_frame: PhysFrame<Size4KiB> = PhysFrame::from_start_address(level_2_addr)?;
-    let level_2_table = {
-        let level_2_table = (level_2_addr.as_u64() + pmo) as *mut PageTable;
-        unsafe { level_2_table.as_mut().unwrap() }
-    };
     fence(core::sync::atomic::Ordering::SeqCst);
-    // First we go through the memory allocations and free them
-    level_2_table
+    for level_4_entry in level_4_table
         .iter_mut()
+        .take(FIRST_KERNEL_ENTRY)
         .filter(|entry| !entry.is_unused())
-        .for_each(|entry| {
-            if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
-                unsafe {
-                    allocator
-                        .deallocate_frame(PhysFrame::<Size2MiB>::containing_address(entry.addr()))
-                };
-            } else {
-                unsafe { allocator.deallocate_frame(entry.frame().unwrap()) };
+    {
+        let level_3_frame = PhysFrame::<Size4KiB>::from_start_address(level_4_entry.addr())?;
+        let level_3_table = unsafe { table_at(level_3_frame, pmo) };
+        for level_3_entry in level_3_table.iter().filter(|entry| !entry.is_unused()) {
+            let level_2_frame = PhysFrame::<Size4KiB>::from_start_address(level_3_entry.addr())?;
+            let level_2_table = unsafe { table_at(level_2_frame, pmo) };
+            for level_2_entry in level_2_table.iter().filter(|entry| !entry.is_unused()) {
+                if level_2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                    unsafe {
+                        allocator.deallocate_frame(PhysFrame::<Size2MiB>::containing_address(
+                            level_2_entry.addr(),
+                        ))
+                    };
+                    continue;
+                }
+                // First we go through the memory allocations and free them
+                let level_1_frame =
+                    PhysFrame::<Size4KiB>::from_start_address(level_2_entry.addr())?;
+                let level_1_table = unsafe { table_at(level_1_frame, pmo) };
+                for level_1_entry in level_1_table.iter().filter(|entry| !entry.is_unused()) {
+                    // The frame could still be shared with another address space
+                    unsafe {
+                        allocator.release_frame(PhysFrame::<Size4KiB>::containing_address(
+                            level_1_entry.addr(),
+                        ))
+                    };
+                }
+                // Then we free the page tables themselves
+                unsafe { allocator.deallocate_frame(level_1_frame) };
             }
-        });
-
-    // Then we free the page tables themselves
-    unsafe {
-        allocator.deallocate_frame(level_2_frame);
-        allocator.deallocate_frame(level_3_frame);
-        allocator.deallocate_frame(level_4_frame);
+            unsafe { allocator.deallocate_frame(level_2_frame) };
+        }
+        unsafe { allocator.deallocate_frame(level_3_frame) };
+        level_4_entry.set_unused();
     }
+    unsafe { allocator.deallocate_frame(level_4_frame) };
     logln!("Cleared user mode mapping");
 
     Ok(())
@@ -1,71 +1,98 @@
 pub mod dispatcher;
 
+pub mod elf;
+
+mod fork;
+pub use fork::{ForkError, fork_process};
+
+mod fpu_state;
+pub use fpu_state::{enable_fpu, handle_fpu_trap, init_fpu};
+
+mod kernel_thread;
+pub use kernel_thread::{exit_kernel_thread, spawn_kernel_thread, spawn_kernel_thread_on};
+
+pub mod memory_areas;
+
 mod memory_mapper;
 
 pub mod process;
 
 pub mod thread;
 
-mod registers_state;
-use core::ptr::Alignment;
+pub mod user_mappings;
 
-use alloc::boxed::Box;
+pub mod user_memory;
+
+pub(crate) mod registers_state;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::{boxed::Box, sync::Arc};
 use internal_utils::{
-    HexNumber,
     gpu_device::{GPU_DEVICE, GPUDeviceCapabilityMut, GPUDeviceCapabilityRequest, RED, WHITE},
     logln,
+    structures::OnceClone,
 };
 pub use registers_state::RegistersState;
 
+mod round_robin_scheduler;
 mod scheduler;
 mod scheduler_table;
 use process::Process;
-use scheduler::{FirstComeFirstServedScheduler, Scheduler};
-pub use scheduler::{SCHEDULER, add_process, run_processes};
-use x86_64::VirtAddr;
+pub use round_robin_scheduler::RoundRobinConfig;
+use round_robin_scheduler::RoundRobinScheduler;
+use scheduler::{FirstComeFirstServedScheduler, Scheduler, init_schedulers};
+pub use scheduler::{
+    SCHEDULERS, add_process, enqueue_thread, exit_thread, on_timer_tick, release_exited_thread,
+    run_processes, running_thread, scheduler_table, terminate_process, yield_running_thread,
+};
+pub use scheduler_table::SchedulerTable;
+use spin::Mutex;
 
-use crate::{ikd_check, processes::thread::Thread};
-use alloc::alloc::{Layout, alloc};
+use crate::{ikd_check, smp::CpuMask};
 
-mod wakers;
+pub mod wait;
 
-pub fn init_scheduler() {
-    SCHEDULER.call_once(|| {
-        let mut scheduler = FirstComeFirstServedScheduler::default();
-        create_idle_process(&mut scheduler);
-        Box::new(scheduler)
-    });
+pub mod wakers;
+
+/// The process all kernel threads belong to.
+static KERNEL_PROCESS: OnceClone<Arc<Mutex<Process>>> = OnceClone::new();
+
+/// The next free process ID - the kernel process always has ID 0.
+static NEXT_PROCESS_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Returns a new, unique process ID.
+pub fn next_process_id() -> u64 {
+    NEXT_PROCESS_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The scheduling algorithms the kernel can run with, on every processor.
+#[derive(Clone, Copy)]
+pub enum SchedulerKind {
+    /// Runs the processes in turns, switching them on every timer tick.
+    FirstComeFirstServed,
+    /// Runs the threads with time slices and priorities.
+    RoundRobin(RoundRobinConfig),
 }
 
-fn create_idle_process(scheduler: &mut FirstComeFirstServedScheduler) {
-    const STACK_SIZE: usize = 4 * 4096;
-
-    let stack = unsafe {
-        let alignment = Alignment::new(16).unwrap();
-        let layout = Layout::new::<[u8; STACK_SIZE]>()
-            .adjust_alignment_to(alignment)
-            .unwrap();
-        alloc(layout) as *mut [u8; STACK_SIZE]
-    };
-
-    let stack_start = VirtAddr::from_ptr(stack);
-
-    let idle_process = Process::create_blank(0);
-    let idle_process = scheduler.add_process(idle_process);
-    unsafe {
-        let thread = Thread::new_native(
-            idle_process_entry as *const u8 as usize,
-            stack_start.as_u64() as usize + STACK_SIZE,
-            idle_process.clone(),
-        );
-        logln!("Thread stack:");
-        logln!(
-            " start: {}",
-            ((stack_start.as_u64() as usize + STACK_SIZE - 8) as u64).to_separated_hex()
-        );
-        logln!(" end: {}", stack_start.to_separated_hex());
-        thread
-    };
+/// Creates the schedulers of the running processors, and the kernel process with its idle thread.
+///
+/// Has to be called after the application processors are started.
+pub fn init_scheduler(kind: SchedulerKind) {
+    init_schedulers(|| -> Box<dyn Scheduler> {
+        match kind {
+            SchedulerKind::FirstComeFirstServed => {
+                Box::new(FirstComeFirstServedScheduler::default())
+            }
+            SchedulerKind::RoundRobin(config) => Box::new(RoundRobinScheduler::new(config)),
+        }
+    });
+    KERNEL_PROCESS.call_once(|| {
+        let process = Arc::new(Mutex::new(Process::create_blank(0)));
+        add_process(&process);
+        process
+    });
+    // The IKD and the screen belong to the bootstrap processor
+    spawn_kernel_thread_on(CpuMask::single(0), || idle_process_entry());
 }
 
 #[unsafe(no_mangle)]
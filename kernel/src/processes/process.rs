@@ -1,18 +1,46 @@
 use alloc::sync::Arc;
-use internal_utils::clocks::get_current_tick;
+use internal_utils::clocks::{get_current_tick, get_timer_ticks};
 use spin::Mutex;
-use x86_64::{registers::control::Cr3, structures::paging::PhysFrame};
+use x86_64::{
+    VirtAddr,
+    registers::control::Cr3,
+    structures::paging::{Page, PhysFrame},
+};
 
 use alloc::vec::Vec;
 
-use super::thread::{Thread, ThreadState};
+use crate::{memory::switch_to_kernel_memory, smp::tlb::TlbFlush};
+
+use super::{
+    memory_areas::{MemoryAccess, MemoryAreas},
+    memory_mapper::{
+        COPY_ON_WRITE, clear_user_mode_mapping, copy_on_write, get_user_page_flags, map_user_pages,
+    },
+    thread::{Thread, ThreadState},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    /// The process has threads that can still run.
+    Alive,
+    /// The process has terminated, and its address space is released once its threads are gone.
+    Zombie,
+}
 
 #[derive(Debug)]
 pub struct Process {
     /// The process's ID.
     pub id: u64,
+    /// The ID of the process that created this one, which can wait for it to terminate.
+    pub parent_id: Option<u64>,
+    /// The process's current state.
+    pub state: ProcessState,
+    /// The exit code of the process, once it has terminated.
+    pub exit_code: Option<u64>,
     /// The page table the process is using.
     pub cr3: (PhysFrame, u16),
+    /// The parts of the user address space the process may use.
+    pub memory_areas: MemoryAreas,
     /// Total ticks the process has been running for.
     pub total_ticks: u64,
     /// The tick the process has been created on.
@@ -29,6 +57,8 @@ pub struct Process {
     pub ready_threads: Vec<Arc<Mutex<Thread>>>,
     /// The threads of the process that are sleeping.
     pub sleeping_threads: Vec<Arc<Mutex<Thread>>>,
+    /// The threads of the process that are blocked on a wake handle.
+    pub blocked_threads: Vec<Arc<Mutex<Thread>>>,
 }
 
 impl Process {
@@ -39,14 +69,14 @@ impl Process {
     }
 
     /// Creates a new process in kernel space.
-    ///
-    // TODO: Loading the process from e.g. an ELF file
-    // We have to look up the structure of an ELF file and prepare the user memory mapping according to it.
-    // Then we can load the program and it's data to proper places and create a process out of it.
     pub fn create_blank(id: u64) -> Self {
         Process {
             id,
+            parent_id: None,
+            state: ProcessState::Alive,
+            exit_code: None,
             cr3: Cr3::read_raw(),
+            memory_areas: MemoryAreas::default(),
             total_ticks: 0,
             start_tick: get_current_tick(),
             last_tick: 0,
@@ -55,22 +85,85 @@ impl Process {
             not_started_threads: Vec::new(),
             ready_threads: Vec::new(),
             sleeping_threads: Vec::new(),
+            blocked_threads: Vec::new(),
+        }
+    }
+
+    /// Creates a new user process running in the given address space.
+    ///
+    /// Use `elf::load_elf_process` to create a process out of a program.
+    pub fn create_user(id: u64, parent_id: u64, level_4_frame: PhysFrame) -> Self {
+        Process {
+            id,
+            parent_id: Some(parent_id),
+            state: ProcessState::Alive,
+            exit_code: None,
+            cr3: (level_4_frame, 0),
+            memory_areas: MemoryAreas::default(),
+            total_ticks: 0,
+            start_tick: get_current_tick(),
+            last_tick: 0,
+            total_threads_created: 0,
+            kernel_process: false,
+            not_started_threads: Vec::new(),
+            ready_threads: Vec::new(),
+            sleeping_threads: Vec::new(),
+            blocked_threads: Vec::new(),
+        }
+    }
+
+    /// Backs the page containing the address with a zeroed frame (or its own copy of a copy-on-write frame),
+    /// if the address is inside one of the process's memory areas and the area allows the access.
+    ///
+    /// Returns whether the access can be retried, which has to wait for the flush to be shot down.
+    pub fn resolve_page_fault(
+        &self,
+        address: VirtAddr,
+        access: MemoryAccess,
+        flush: &mut TlbFlush,
+    ) -> bool {
+        if self.kernel_process {
+            return false;
+        }
+        let Some(area) = self.memory_areas.find(address) else {
+            return false;
+        };
+        if !area.allows(access) {
+            return false;
+        }
+        let page = Page::containing_address(address);
+        match unsafe { get_user_page_flags(self.cr3.0, page) } {
+            None => unsafe {
+                map_user_pages(self.cr3.0, Page::range_inclusive(page, page), area.flags).is_ok()
+            },
+            Some(flags) if access == MemoryAccess::Write && flags.contains(COPY_ON_WRITE) => {
+                unsafe { copy_on_write(self.cr3.0, page, flush) }.is_some()
+            }
+            // Another processor gave the page the permissions, and our TLB still had the old ones
+            Some(flags) if access.is_allowed_by(flags) => true,
+            // The page is mapped, but without the permissions for the access
+            Some(_) => false,
         }
     }
 
-    /// Updates the sleeping threads, waking them up if they are sleeping for too long.
-    pub fn update_sleeping_threads(this: &Arc<Mutex<Process>>) {
+    /// Updates the sleeping threads, waking them up if their wake-up tick has passed.
+    ///
+    /// Returns the threads woken up, which still have to be queued on a processor.
+    pub fn update_sleeping_threads(this: &Arc<Mutex<Process>>) -> Vec<Arc<Mutex<Thread>>> {
         let mut process = this.lock();
         if process.sleeping_threads.is_empty() {
-            return;
+            return Vec::new();
         }
+        let current_timer_tick = get_timer_ticks();
         let mut drained = Vec::new();
         process.sleeping_threads.retain(|thread| {
-            let mut borrowed_thread = thread.lock();
+            // The threads are usually locked before their process, so a locked one waits for the next tick
+            let Some(mut borrowed_thread) = thread.try_lock() else {
+                return true;
+            };
             match borrowed_thread.state {
-                ThreadState::Sleeping(ref mut sleep_ticks) => {
-                    if *sleep_ticks > 0 {
-                        *sleep_ticks -= 1;
+                ThreadState::Sleeping(wake_up_tick) => {
+                    if wake_up_tick > current_timer_tick {
                         true
                     } else {
                         borrowed_thread.state = ThreadState::Ready;
@@ -81,6 +174,21 @@ impl Process {
                 _ => unreachable!(),
             }
         });
-        process.ready_threads.extend(drained);
+        process.ready_threads.extend(drained.iter().cloned());
+        drained
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        if self.kernel_process {
+            return;
+        }
+        // Every thread keeps its process alive, and an exited thread is only dropped once the processor
+        // it ran on switched away from it, so no other processor can be using the page tables anymore
+        if Cr3::read().0 == self.cr3.0 {
+            switch_to_kernel_memory();
+        }
+        unsafe { clear_user_mode_mapping(self.cr3.0) }.expect("Page tables are frame-aligned");
     }
 }
@@ -0,0 +1,194 @@
+use alloc::{sync::Arc, vec::Vec};
+use spin::Mutex;
+
+use super::{
+    RegistersState,
+    scheduler::{RunQueue, Scheduler, account_tick, can_run_on},
+    thread::{Thread, ThreadState},
+};
+
+/// The settings of the round-robin scheduler.
+#[derive(Debug, Clone, Copy)]
+pub struct RoundRobinConfig {
+    /// The number of priority levels, level 0 being the highest.
+    pub priority_levels: u8,
+    /// The time slice of the highest priority level, in timer ticks. Every lower level doubles it.
+    pub base_time_slice: u64,
+    /// How often all threads are boosted back to the highest priority, in timer ticks.
+    pub boost_interval: u64,
+}
+
+impl Default for RoundRobinConfig {
+    fn default() -> Self {
+        RoundRobinConfig {
+            priority_levels: 4,
+            base_time_slice: 2,
+            boost_interval: 100,
+        }
+    }
+}
+
+/// A round-robin scheduler with multiple priority levels (multi-level feedback queue).
+///
+/// Threads run for a time slice depending on their priority, and threads of the same priority take turns.
+/// A thread using up its whole time slice is demoted, while a thread giving up the CPU early is promoted,
+/// so interactive threads stay responsive and CPU hogs share the remaining time.
+/// Every once in a while all queued threads get boosted to the highest priority, so no thread starves.
+pub struct RoundRobinScheduler {
+    config: RoundRobinConfig,
+    running_thread: Option<Arc<Mutex<Thread>>>,
+    /// Whether the running thread gave up the rest of its time slice.
+    running_thread_yielded: bool,
+    /// The threads queued on the processor.
+    queue: RunQueue,
+    /// Timer ticks since the last priority boost.
+    ticks_since_boost: u64,
+}
+
+impl RoundRobinScheduler {
+    pub fn new(config: RoundRobinConfig) -> Self {
+        assert!(
+            (1..=32).contains(&config.priority_levels),
+            "The scheduler needs between 1 and 32 priority levels"
+        );
+        RoundRobinScheduler {
+            config,
+            running_thread: None,
+            running_thread_yielded: false,
+            queue: RunQueue::default(),
+            ticks_since_boost: 0,
+        }
+    }
+
+    /// Returns the time slice of the priority level.
+    fn time_slice(&self, priority: u8) -> u64 {
+        self.config.base_time_slice << priority
+    }
+
+    /// Moves the queued threads to the highest priority level.
+    fn boost_priorities(&self) {
+        self.queue
+            .iter()
+            .chain(self.running_thread.iter())
+            .for_each(|thread| thread.lock().priority = 0);
+    }
+
+    /// Returns the thread which can run on the processor with the highest priority, which has been waiting the longest.
+    fn find_best_thread(&self, cpu_index: usize) -> Option<Arc<Mutex<Thread>>> {
+        let mut best_thread = None;
+        let mut best_key = (u8::MAX, u64::MAX);
+        for thread in self.queue.iter() {
+            let key = {
+                let thread = thread.lock();
+                if !can_run_on(&thread, cpu_index) {
+                    continue;
+                }
+                (thread.priority, thread.last_tick)
+            };
+            if best_thread.is_none() || key < best_key {
+                best_thread = Some(thread.clone());
+                best_key = key;
+            }
+        }
+        best_thread
+    }
+}
+
+impl Scheduler for RoundRobinScheduler {
+    fn get_running_thread(&self) -> Option<Arc<Mutex<Thread>>> {
+        self.running_thread.clone()
+    }
+
+    fn clear_running_thread(&mut self) {
+        self.running_thread = None;
+        self.running_thread_yielded = false;
+    }
+
+    fn add_thread(&mut self, thread: Arc<Mutex<Thread>>) {
+        self.queue.push(thread);
+    }
+
+    fn steal_thread(&mut self, cpu_index: usize, thief_index: usize) -> Option<Arc<Mutex<Thread>>> {
+        self.queue
+            .steal(self.running_thread.as_ref(), cpu_index, thief_index)
+    }
+
+    fn take_disallowed_threads(&mut self, cpu_index: usize) -> Vec<Arc<Mutex<Thread>>> {
+        self.queue
+            .take_disallowed(self.running_thread.as_ref(), cpu_index)
+    }
+
+    fn queued_threads(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Manages scheduler operations on a timer tick
+    fn on_tick(&mut self, registers_state: RegistersState, tick: u64) {
+        if let Some(thread) = &self.running_thread {
+            account_tick(thread, registers_state, tick);
+            let mut thread = thread.lock();
+            thread.remaining_quantum = thread.remaining_quantum.saturating_sub(1);
+        }
+        self.ticks_since_boost += 1;
+        if self.ticks_since_boost >= self.config.boost_interval {
+            self.ticks_since_boost = 0;
+            self.boost_priorities();
+        }
+    }
+
+    fn yield_running_thread(&mut self) {
+        self.running_thread_yielded = true;
+    }
+
+    fn schedule(&mut self, cpu_index: usize) -> Option<Arc<Mutex<Thread>>> {
+        self.queue.remove_stopped();
+        let yielded = core::mem::take(&mut self.running_thread_yielded);
+
+        // Adjusting the priority of the previous thread depending on how it used its time slice
+        let mut keep_running = None;
+        if let Some(previous_thread) = self.running_thread.take() {
+            let mut thread = previous_thread.lock();
+            if thread.remaining_quantum == 0 {
+                thread.priority = (thread.priority + 1).min(self.config.priority_levels - 1);
+            } else if yielded || !matches!(thread.state, ThreadState::Ready) {
+                thread.priority = thread.priority.saturating_sub(1);
+                thread.remaining_quantum = 0;
+            } else if thread.affinity.contains(cpu_index) {
+                drop(thread);
+                keep_running = Some(previous_thread);
+            }
+        }
+
+        let best_thread = self.find_best_thread(cpu_index);
+
+        // The previous thread keeps running until a thread with a higher priority shows up
+        let keep_running = keep_running.filter(|previous_thread| {
+            let Some(best_thread) = &best_thread else {
+                return true;
+            };
+            if Arc::ptr_eq(previous_thread, best_thread) {
+                return true;
+            }
+            let previous_priority = previous_thread.lock().priority;
+            previous_priority <= best_thread.lock().priority
+        });
+        let thread = match keep_running {
+            Some(previous_thread) => previous_thread,
+            None => {
+                let best_thread = best_thread?;
+                let (state, priority) = {
+                    let thread = best_thread.lock();
+                    (thread.state, thread.priority)
+                };
+                if let ThreadState::NotStarted = state {
+                    Thread::change_state(best_thread.clone(), ThreadState::Ready);
+                }
+                best_thread.lock().remaining_quantum = self.time_slice(priority);
+                best_thread
+            }
+        };
+
+        self.running_thread = Some(thread.clone());
+        Some(thread)
+    }
+}
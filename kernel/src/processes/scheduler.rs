@@ -1,55 +1,389 @@
-use alloc::{boxed::Box, collections::VecDeque, sync::Arc};
-use internal_utils::{clocks::get_current_tick, logln, structures::OnceMutex};
-use spin::{Mutex, MutexGuard};
-use x86_64::structures::paging::page::AddressNotAligned;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use super::{RegistersState, process::Process, thread::Thread};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use internal_utils::{
+    clocks::get_current_tick,
+    kernel_information::heap_tracking::{AllocationTag, set_allocation_tag},
+    logln,
+    structures::OnceMutex,
+};
+use spin::{Mutex, MutexGuard};
+use x86_64::{VirtAddr, instructions::interrupts::without_interrupts};
+
+use super::{
+    RegistersState,
+    fpu_state::{owns_fpu, release_fpu},
+    process::{Process, ProcessState},
+    thread::Thread,
+    wait::{record_process_exit, register_process},
+};
 use crate::processes::{
     dispatcher::dispatch_thread,
-    memory_mapper::clear_user_mode_mapping,
-    scheduler_table::{ProcessInfo, SchedulerTable, ThreadInfo},
+    scheduler_table::{CpuInfo, SchedulerTable},
     thread::ThreadState,
 };
+use crate::smp::{CpuMask, MAX_CPUS, cpu, cpu_count, current_cpu_index, idle};
 
-pub static SCHEDULER: OnceMutex<Box<dyn Scheduler>> = OnceMutex::new();
+/// The scheduler of every processor, by CPU index, deciding in which order its threads run.
+///
+/// Only one of them is ever locked at once, and always before any thread or process.
+pub static SCHEDULERS: [OnceMutex<Box<dyn Scheduler>>; MAX_CPUS] =
+    [const { OnceMutex::new() }; MAX_CPUS];
+
+/// The number of threads queued on every processor, so the busiest one can be found without locking them all.
+static QUEUE_LENGTHS: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+/// The processes that haven't terminated yet.
+///
+/// It's locked before the processes, so a process has to be unlocked to be removed.
+static PROCESSES: Mutex<Vec<Arc<Mutex<Process>>>> = Mutex::new(Vec::new());
 
+/// Decides in which order the threads queued on a processor run.
+///
+/// A thread is queued on a single processor at once, and only runs there.
+/// The threads blocking or going to sleep leave the queue, and are queued again when they become ready.
 pub trait Scheduler: Send {
-    /// Returns the thread that should be ran next, and sets that thread as currently running.
-    fn schedule(&mut self) -> Arc<Mutex<Thread>>;
+    /// Returns the thread that should be ran next on the processor, and sets that thread as currently running.
+    ///
+    /// Returns `None` if none of the queued threads can run.
+    fn schedule(&mut self, cpu_index: usize) -> Option<Arc<Mutex<Thread>>>;
+
+    /// Adds the thread to the queue, if it's not queued already.
+    fn add_thread(&mut self, thread: Arc<Mutex<Thread>>);
 
-    /// Adds a process to the scheduling queue so it will be ran.
-    fn add_process(&mut self, process: Process) -> Arc<Mutex<Process>>;
+    /// Removes a queued thread which can run on the other processor, for it to take over.
+    fn steal_thread(&mut self, cpu_index: usize, thief_index: usize) -> Option<Arc<Mutex<Thread>>>;
 
-    /// Removes the process from the scheduling queue.
-    fn remove_process(&mut self, process: &Arc<Mutex<Process>>);
+    /// Removes the queued threads which aren't allowed to run on the processor anymore.
+    fn take_disallowed_threads(&mut self, cpu_index: usize) -> Vec<Arc<Mutex<Thread>>>;
 
     /// Keeps accounting of the thread ran in a tick.
-    fn on_tick(&self, registers_state: RegistersState, tick: u64);
+    fn on_tick(&mut self, registers_state: RegistersState, tick: u64);
 
+    /// Tells the scheduler that the running thread gives up the rest of its time slice.
+    fn yield_running_thread(&mut self) {}
+
+    /// Returns the thread running on the processor.
     fn get_running_thread(&self) -> Option<Arc<Mutex<Thread>>>;
+
+    /// Forgets that the running thread is running, as it exited.
     fn clear_running_thread(&mut self);
 
-    fn get_processes_and_threads(&self) -> SchedulerTable;
+    /// Returns the number of threads in the queue, including the running one.
+    fn queued_threads(&self) -> usize;
+}
+
+/// The threads queued on a processor, in the order they were queued.
+#[derive(Default)]
+pub(super) struct RunQueue(VecDeque<Arc<Mutex<Thread>>>);
+
+impl RunQueue {
+    pub fn push(&mut self, thread: Arc<Mutex<Thread>>) {
+        if !self.0.iter().any(|queued| Arc::ptr_eq(queued, &thread)) {
+            self.0.push_back(thread);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Mutex<Thread>>> {
+        self.0.iter()
+    }
+
+    /// Drops the threads which aren't ready anymore - they're queued again when they are.
+    ///
+    /// The running thread stays running, even if it's not queued.
+    pub fn remove_stopped(&mut self) {
+        self.0.retain(|thread| thread.lock().is_runnable());
+    }
+
+    /// Moves the thread to the back of the queue.
+    pub fn move_to_back(&mut self, thread: &Arc<Mutex<Thread>>) {
+        if let Some(index) = self.0.iter().position(|queued| Arc::ptr_eq(queued, thread)) {
+            let thread = self.0.remove(index).unwrap();
+            self.0.push_back(thread);
+        }
+    }
+
+    /// Removes the thread which has been queued the longest, of the ones that can run on the other processor.
+    ///
+    /// The running thread and the one whose FPU state the processor holds stay,
+    /// as their state is still in the processor.
+    pub fn steal(
+        &mut self,
+        running_thread: Option<&Arc<Mutex<Thread>>>,
+        cpu_index: usize,
+        thief_index: usize,
+    ) -> Option<Arc<Mutex<Thread>>> {
+        let index = self.0.iter().position(|thread| {
+            if running_thread.is_some_and(|running| Arc::ptr_eq(running, thread)) {
+                return false;
+            }
+            let can_run = can_run_on(&thread.lock(), thief_index);
+            // The thread is unlocked first, as the FPU owner is locked before its thread
+            can_run && !owns_fpu(cpu_index, thread)
+        })?;
+        let thread = self.0.remove(index)?;
+        thread.lock().cpu_index = Some(thief_index);
+        Some(thread)
+    }
+
+    /// Removes the threads whose affinity doesn't allow the processor, except for the running one.
+    pub fn take_disallowed(
+        &mut self,
+        running_thread: Option<&Arc<Mutex<Thread>>>,
+        cpu_index: usize,
+    ) -> Vec<Arc<Mutex<Thread>>> {
+        self.0
+            .extract_if(.., |thread| {
+                running_thread.is_none_or(|running| !Arc::ptr_eq(running, thread))
+                    && !thread.lock().affinity.contains(cpu_index)
+            })
+            .collect()
+    }
 }
 
-/// Runs the scheduler, giving it control of the CPU.
+/// Checks if the thread can run on the processor: it's ready (or not started), its affinity allows
+/// the processor, and no other processor might still use its stacks.
+pub(super) fn can_run_on(thread: &Thread, cpu_index: usize) -> bool {
+    thread.is_runnable()
+        && thread.affinity.contains(cpu_index)
+        && thread.stacks_released_for(cpu_index)
+}
+
+/// Runs the scheduler of the running processor, giving it control of the CPU.
 ///
-/// Will return only if there are no threads at all to run.
+/// If there is no thread to run, the processor waits for the next timer interrupt.
 pub fn run_processes() -> ! {
-    let thread = { SCHEDULER.lock().unwrap().schedule() };
-    dispatch_thread(thread);
+    // The tagged scopes switched away from never end, like the one of a system call handler
+    // blocking its thread, so the processor leaves them here
+    set_allocation_tag(AllocationTag::Untagged);
+    match next_thread() {
+        Some(thread) => dispatch_thread(thread),
+        None => idle(),
+    }
 }
 
-pub fn add_process(process: Process) -> Arc<Mutex<Process>> {
-    SCHEDULER.lock().unwrap().add_process(process)
+/// Returns the thread the running processor switches to, stealing one from the busiest processor
+/// if none of its own can run.
+fn next_thread() -> Option<Arc<Mutex<Thread>>> {
+    let cpu_index = current_cpu_index();
+    let (thread, disallowed_threads) = {
+        let mut scheduler = SCHEDULERS[cpu_index].lock()?;
+        let thread = schedule(&mut **scheduler, cpu_index);
+        (thread, scheduler.take_disallowed_threads(cpu_index))
+    };
+    for thread in disallowed_threads {
+        release_fpu(&thread);
+        enqueue_thread(thread);
+    }
+    if thread.is_some() {
+        return thread;
+    }
+
+    let stolen_thread = steal_thread(cpu_index)?;
+    let mut scheduler = SCHEDULERS[cpu_index].lock()?;
+    scheduler.add_thread(stolen_thread);
+    schedule(&mut **scheduler, cpu_index)
+}
+
+/// Runs the scheduler, and notes when the processor switched away from the previous thread.
+fn schedule(scheduler: &mut dyn Scheduler, cpu_index: usize) -> Option<Arc<Mutex<Thread>>> {
+    let previous_thread = scheduler.get_running_thread();
+    let thread = scheduler.schedule(cpu_index);
+    QUEUE_LENGTHS[cpu_index].store(scheduler.queued_threads(), Ordering::Relaxed);
+    if let Some(previous_thread) = previous_thread
+        && thread
+            .as_ref()
+            .is_none_or(|thread| !Arc::ptr_eq(thread, &previous_thread))
+    {
+        previous_thread.lock().left_cpu(cpu_index);
+    }
+    thread
+}
+
+/// Takes a thread from the busiest processor which has one the running processor can run.
+///
+/// The processors are only tried, so two of them stealing from each other can't deadlock.
+fn steal_thread(thief_index: usize) -> Option<Arc<Mutex<Thread>>> {
+    let mut tried = CpuMask::single(thief_index);
+    loop {
+        // A processor with a single thread is most likely running it
+        let victim_index = (0..cpu_count())
+            .filter(|&cpu_index| !tried.contains(cpu_index))
+            .filter(|&cpu_index| QUEUE_LENGTHS[cpu_index].load(Ordering::Relaxed) > 1)
+            .max_by_key(|&cpu_index| QUEUE_LENGTHS[cpu_index].load(Ordering::Relaxed))?;
+        tried.insert(victim_index);
+
+        let Some(mut victim) = SCHEDULERS[victim_index].try_lock() else {
+            continue;
+        };
+        if let Some(thread) = victim.steal_thread(victim_index, thief_index) {
+            QUEUE_LENGTHS[victim_index].store(victim.queued_threads(), Ordering::Relaxed);
+            return Some(thread);
+        }
+    }
+}
+
+/// Queues the ready thread on the processor it last ran on, or on the least busy one its affinity allows.
+pub fn enqueue_thread(thread: Arc<Mutex<Thread>>) {
+    // The thread can be stolen while it's not locked, so the processor is checked again afterwards
+    loop {
+        let (cpu_index, affinity) = {
+            let thread = thread.lock();
+            (thread.cpu_index, thread.affinity)
+        };
+        let target_index = match cpu_index {
+            Some(cpu_index) if affinity.contains(cpu_index) && cpu_index < cpu_count() => cpu_index,
+            _ => least_busy_cpu(affinity),
+        };
+        // The timer interrupt locks the scheduler too
+        let queued = without_interrupts(|| {
+            let Some(mut scheduler) = SCHEDULERS[target_index].lock() else {
+                return true;
+            };
+            {
+                let mut thread = thread.lock();
+                if thread.cpu_index != cpu_index {
+                    return false;
+                }
+                thread.cpu_index = Some(target_index);
+            }
+            scheduler.add_thread(thread.clone());
+            QUEUE_LENGTHS[target_index].store(scheduler.queued_threads(), Ordering::Relaxed);
+            true
+        });
+        if queued {
+            return;
+        }
+    }
+}
+
+/// Returns the processor of the affinity with the fewest queued threads, or the bootstrap processor
+/// if the affinity has no running processor.
+fn least_busy_cpu(affinity: CpuMask) -> usize {
+    affinity
+        .running_cpus()
+        .min_by_key(|&cpu_index| QUEUE_LENGTHS[cpu_index].load(Ordering::Relaxed))
+        .unwrap_or(0)
+}
+
+/// Returns the thread running on the running processor.
+pub fn running_thread() -> Option<Arc<Mutex<Thread>>> {
+    // The timer interrupt locks the scheduler too
+    without_interrupts(|| {
+        SCHEDULERS[current_cpu_index()]
+            .lock()
+            .and_then(|scheduler| scheduler.get_running_thread())
+    })
+}
+
+/// Tells the scheduler of the running processor that the running thread gives up the rest of its time slice.
+pub fn yield_running_thread() {
+    without_interrupts(|| {
+        if let Some(mut scheduler) = SCHEDULERS[current_cpu_index()].lock() {
+            scheduler.yield_running_thread();
+        }
+    });
+}
+
+/// Accounts the timer tick to the thread running on the running processor.
+///
+/// The bootstrap processor also wakes up the sleeping threads, as only its timer counts the time.
+/// Its timer is always armed: the APICs only take over from the PICs if they can route it.
+pub fn on_timer_tick(registers_state: RegistersState) {
+    let cpu_index = current_cpu_index();
+    let busy = SCHEDULERS[cpu_index].lock().is_some_and(|mut scheduler| {
+        scheduler.on_tick(registers_state, get_current_tick());
+        scheduler.get_running_thread().is_some()
+    });
+    cpu(cpu_index).count_tick(busy);
+    if cpu_index == 0 {
+        wake_sleeping_threads();
+    }
+}
+
+/// Wakes up the sleeping threads of every process whose wake-up tick has passed.
+fn wake_sleeping_threads() {
+    let woken_threads: Vec<_> = PROCESSES
+        .lock()
+        .iter()
+        .flat_map(Process::update_sleeping_threads)
+        .collect();
+    woken_threads.into_iter().for_each(enqueue_thread);
+}
+
+/// Creates the scheduler of every running processor.
+pub(super) fn init_schedulers(create_scheduler: impl Fn() -> Box<dyn Scheduler>) {
+    for scheduler in SCHEDULERS.iter().take(cpu_count()) {
+        scheduler.call_once(&create_scheduler);
+    }
+}
+
+/// Saves the state of the thread that ran in a tick, and accounts the tick to it and its process.
+pub(super) fn account_tick(
+    thread: &Arc<Mutex<Thread>>,
+    registers_state: RegistersState,
+    tick: u64,
+) {
+    let mut thread_mut = thread.lock();
+
+    thread_mut.registers_state = registers_state;
+    thread_mut.total_ticks += tick - thread_mut.last_tick;
+    thread_mut.last_tick = tick;
+    let mut process: MutexGuard<'_, Process> = thread_mut.process.lock();
+    process.total_ticks += tick - process.last_tick;
+    process.last_tick = tick;
+}
+
+/// Registers the process, so it's shown by the IKD and its parent can wait for it.
+pub fn add_process(process: &Arc<Mutex<Process>>) {
+    {
+        let process = process.lock();
+        register_process(process.id, process.parent_id, process.kernel_process);
+    }
+    // The timer interrupt locks the processes too
+    without_interrupts(|| PROCESSES.lock().push(process.clone()));
+}
+
+/// Forgets the terminated process, which has to be unlocked.
+fn remove_process(process: &Arc<Mutex<Process>>) {
+    // Dropping a process can free its address space, so we do it after releasing the lock
+    let removed: Vec<_> = without_interrupts(|| {
+        PROCESSES
+            .lock()
+            .extract_if(.., |p| Arc::ptr_eq(p, process))
+            .collect()
+    });
+    drop(removed);
+    logln!("Removed process from scheduler");
+}
+
+/// Returns the processes, their threads and the processors, as the IKD shows them.
+pub fn scheduler_table() -> SchedulerTable {
+    let cpus = (0..cpu_count())
+        .map(|cpu_index| {
+            let (queued_threads, running_thread) = without_interrupts(|| {
+                SCHEDULERS[cpu_index].lock().map_or((0, None), |scheduler| {
+                    (scheduler.queued_threads(), scheduler.get_running_thread())
+                })
+            });
+            CpuInfo::new(cpu_index, queued_threads, running_thread)
+        })
+        .collect();
+    without_interrupts(|| SchedulerTable::new(PROCESSES.lock().iter(), cpus))
 }
 
 #[derive(Default)]
 pub struct FirstComeFirstServedScheduler {
-    /// The currently running process.
     running_thread: Option<Arc<Mutex<Thread>>>,
-    /// The list of processes that are registered.
-    processes: VecDeque<Arc<Mutex<Process>>>,
+    /// The threads queued on the processor, in the order they take turns.
+    queue: RunQueue,
 }
 
 impl Scheduler for FirstComeFirstServedScheduler {
@@ -61,134 +395,171 @@ impl Scheduler for FirstComeFirstServedScheduler {
         self.running_thread = None;
     }
 
-    fn get_processes_and_threads(&self) -> SchedulerTable {
-        let current_tick = get_current_tick();
-        SchedulerTable(
-            self.processes
-                .iter()
-                .map(|p| p.lock())
-                .map(|p| ProcessInfo {
-                    id: p.id,
-                    kernel_process: p.kernel_process,
-                    load: p.tick_density(current_tick),
-                    threads: p
-                        .ready_threads
-                        .iter()
-                        .chain(p.not_started_threads.iter())
-                        .chain(p.sleeping_threads.iter())
-                        .map(|t| t.lock())
-                        .map(|t| ThreadInfo {
-                            id: t.id,
-                            state: match t.state {
-                                ThreadState::NotStarted => "not started",
-                                ThreadState::Ready => "ready",
-                                ThreadState::Running => "running",
-                                ThreadState::Sleeping(_) => "sleeping",
-                                ThreadState::Terminated => "terminated",
-                            },
-                            load: t.tick_density(current_tick),
-                        })
-                        .collect(),
-                })
-                .collect(),
-        )
+    fn add_thread(&mut self, thread: Arc<Mutex<Thread>>) {
+        self.queue.push(thread);
+    }
+
+    fn steal_thread(&mut self, cpu_index: usize, thief_index: usize) -> Option<Arc<Mutex<Thread>>> {
+        self.queue
+            .steal(self.running_thread.as_ref(), cpu_index, thief_index)
     }
 
-    fn add_process(&mut self, process: Process) -> Arc<Mutex<Process>> {
-        let rc = Arc::new(Mutex::new(process));
-        self.processes.push_back(rc.clone());
-        rc
+    fn take_disallowed_threads(&mut self, cpu_index: usize) -> Vec<Arc<Mutex<Thread>>> {
+        self.queue
+            .take_disallowed(self.running_thread.as_ref(), cpu_index)
     }
 
-    fn remove_process(&mut self, process: &Arc<Mutex<Process>>) {
-        self.processes.retain(|p| !Arc::ptr_eq(p, process));
+    fn queued_threads(&self) -> usize {
+        self.queue.len()
     }
 
     /// Manages scheduler operations on a timer tick
-    fn on_tick(&self, registers_state: RegistersState, tick: u64) {
-        if let Some(thread) = self.running_thread.clone() {
-            let mut thread_mut = thread.lock();
-
-            thread_mut.registers_state = registers_state.clone();
-            thread_mut.total_ticks += tick - thread_mut.last_tick;
-            thread_mut.last_tick = tick;
-            let mut process: MutexGuard<'_, Process> = thread_mut.process.lock();
-            process.total_ticks += tick - process.last_tick;
-            process.last_tick = tick;
+    fn on_tick(&mut self, registers_state: RegistersState, tick: u64) {
+        if let Some(thread) = &self.running_thread {
+            account_tick(thread, registers_state, tick);
         }
     }
 
-    fn schedule(&mut self) -> Arc<Mutex<Thread>> {
-        // We're taking the first process in the queue that returns a runnable thread
-        let processes = &mut self.processes;
-        let process = processes
-            .extract_if(.., |process| {
-                Process::update_sleeping_threads(process);
-                let lock = process.lock();
-                !lock.ready_threads.is_empty() || !lock.not_started_threads.is_empty()
-            })
-            .next()
-            .expect("There has to be at least one process in the scheduler");
-        let thread = self.get_thread_to_run(process.clone());
-        // Putting the process at the back of the queue
-        self.processes.push_back(process);
-
-        if let Some(previous_thread) = self.running_thread.take()
-            && !Arc::ptr_eq(&previous_thread, &thread)
-        {
-            previous_thread
-                .clone()
-                .lock()
-                .process
-                .lock()
-                .ready_threads
-                .push(previous_thread);
+    fn schedule(&mut self, cpu_index: usize) -> Option<Arc<Mutex<Thread>>> {
+        self.queue.remove_stopped();
+        // We're taking the first thread in the queue that can run, and putting it at the back of the queue
+        let thread = self
+            .queue
+            .iter()
+            .find(|thread| can_run_on(&thread.lock(), cpu_index))
+            .cloned();
+        self.running_thread = thread.clone();
+        let thread = thread?;
+        self.queue.move_to_back(&thread);
+
+        let not_started = matches!(thread.lock().state, ThreadState::NotStarted);
+        if not_started {
+            Thread::change_state(thread.clone(), ThreadState::Ready);
         }
-
-        self.running_thread = Some(thread.clone());
-        thread
+        Some(thread)
     }
 }
 
-impl FirstComeFirstServedScheduler {
-    /// Returns the thread from the process that should be ran next.
-    fn get_thread_to_run(&self, process: Arc<Mutex<Process>>) -> Arc<Mutex<Thread>> {
-        let mut process_borrowed = process.lock();
-
-        // Taking the first thread in the chosen process
-        let thread = process_borrowed.ready_threads.try_remove(0);
-
-        let thread = if let Some(thread) = thread {
-            thread
-        } else {
-            let thread = process_borrowed
-                .not_started_threads
-                .try_remove(0)
-                .expect("A process must have a ready or not started thread to run");
-            thread.lock().state = ThreadState::Ready;
-            thread
-        };
+/// Threads that have exited, but might still have their stacks in use.
+static EXITED_THREADS: Mutex<Vec<Arc<Mutex<Thread>>>> = Mutex::new(Vec::new());
 
-        // Putting the thread at the back of the thread-queue
-        process_borrowed.ready_threads.push(thread.clone());
+/// Keeps the exited thread alive until its stacks are surely not used anymore.
+///
+/// A thread exits while running on its own stacks, so it can't be dropped right away.
+pub fn release_exited_thread(thread: Arc<Mutex<Thread>>) {
+    without_interrupts(|| {
+        drop_unused_exited_threads();
+        EXITED_THREADS.lock().push(thread);
+    });
+}
 
-        thread
+/// Drops the exited threads whose stacks no processor uses anymore.
+pub fn drop_unused_exited_threads() {
+    let stack_pointer: u64;
+    unsafe {
+        asm!("mov {}, rsp", out(reg) stack_pointer, options(nomem, nostack, preserves_flags));
     }
+    let stack_pointer = VirtAddr::new(stack_pointer);
+    let cpu_index = current_cpu_index();
+    // Dropping a thread can take other locks, so we do it after releasing ours
+    let unused_threads: Vec<_> = without_interrupts(|| {
+        EXITED_THREADS
+            .lock()
+            .extract_if(.., |thread| {
+                // A scheduler still holding the thread might be running it
+                Arc::strong_count(thread) == 1 && {
+                    let thread = thread.lock();
+                    !thread.is_stack_address(stack_pointer) && thread.stacks_released_for(cpu_index)
+                }
+            })
+            .collect()
+    });
+    drop(unused_threads);
 }
 
-/// Removes the thread from its process. If this thread is the last one, the process is cleaned up.
-pub fn exit_thread(thread: Arc<Mutex<Thread>>) -> Result<(), AddressNotAligned> {
+/// Removes the thread from its process. If this thread is the last one, the process terminates
+/// with the thread's exit code.
+pub fn exit_thread(thread: Arc<Mutex<Thread>>, exit_code: u64) {
     logln!("Exiting thread");
-    let mut borrowed_thread = thread.lock();
-    let process = borrowed_thread.process.clone();
-    let mut borrowed_process = process.lock();
-
-    remove_thread_from_process_queues(&mut borrowed_process, &thread, borrowed_thread.state);
-
-    logln!("Removed thread from process");
+    // The scheduler is locked before the thread
+    without_interrupts(|| {
+        let cpu_index = current_cpu_index();
+        if let Some(mut scheduler) = SCHEDULERS[cpu_index].lock()
+            && scheduler
+                .get_running_thread()
+                .is_some_and(|running| Arc::ptr_eq(&running, &thread))
+        {
+            scheduler.clear_running_thread();
+            thread.lock().left_cpu(cpu_index);
+        }
+    });
+
+    let process = thread.lock().process.clone();
+    let finished = {
+        let mut borrowed_thread = thread.lock();
+        let mut borrowed_process = process.lock();
+
+        remove_thread_from_process_queues(&mut borrowed_process, &thread, borrowed_thread.state);
+        borrowed_thread.state = ThreadState::Terminated;
+
+        logln!("Removed thread from process");
+
+        let thread_vectors = [
+            &borrowed_process.not_started_threads,
+            &borrowed_process.ready_threads,
+            &borrowed_process.sleeping_threads,
+            &borrowed_process.blocked_threads,
+        ];
+        if thread_vectors.into_iter().all(|v| v.is_empty()) {
+            finish_process(&mut borrowed_process, exit_code);
+            true
+        } else {
+            false
+        }
+    };
+    if finished {
+        remove_process(&process);
+    }
+}
 
-    check_should_remove_process(&mut borrowed_process, &mut borrowed_thread)?;
-    Ok(())
+/// Terminates the process with the exit code, stopping all of its threads.
+///
+/// If the running thread belongs to the process, the caller has to switch to another thread afterwards.
+/// The threads running on the other processors keep running until their next timer tick,
+/// so the address space is only freed once the last of them is dropped.
+pub fn terminate_process(process: &Arc<Mutex<Process>>, exit_code: u64) {
+    without_interrupts(|| {
+        let threads: Vec<_> = {
+            let mut borrowed_process = process.lock();
+            if borrowed_process.state == ProcessState::Zombie {
+                return;
+            }
+            [
+                core::mem::take(&mut borrowed_process.not_started_threads),
+                core::mem::take(&mut borrowed_process.ready_threads),
+                core::mem::take(&mut borrowed_process.sleeping_threads),
+                core::mem::take(&mut borrowed_process.blocked_threads),
+            ]
+            .concat()
+        };
+        let cpu_index = current_cpu_index();
+        if let Some(mut scheduler) = SCHEDULERS[cpu_index].lock()
+            && let Some(running_thread) = scheduler.get_running_thread()
+            && threads
+                .iter()
+                .any(|thread| Arc::ptr_eq(thread, &running_thread))
+        {
+            scheduler.clear_running_thread();
+            running_thread.lock().left_cpu(cpu_index);
+        }
+        for thread in threads {
+            thread.lock().state = ThreadState::Terminated;
+            // We could be running on the stacks of one of the threads
+            release_exited_thread(thread);
+        }
+        finish_process(&mut process.lock(), exit_code);
+        remove_process(process);
+    })
 }
 
 /// Removes the thread from the respective process queue, depending on the thread state.
@@ -198,16 +569,6 @@ pub fn remove_thread_from_process_queues(
     state: ThreadState,
 ) {
     match state {
-        ThreadState::Running => {
-            let mut scheduler = SCHEDULER.lock().unwrap();
-            if scheduler
-                .get_running_thread()
-                .as_ref()
-                .is_some_and(|t| Arc::ptr_eq(t, changed_thread))
-            {
-                scheduler.clear_running_thread();
-            }
-        }
         ThreadState::NotStarted => {
             borrowed_process
                 .not_started_threads
@@ -226,32 +587,30 @@ pub fn remove_thread_from_process_queues(
                 .extract_if(.., |t| Arc::ptr_eq(t, changed_thread))
                 .next();
         }
+        ThreadState::Blocked(_) => {
+            borrowed_process
+                .blocked_threads
+                .extract_if(.., |t| Arc::ptr_eq(t, changed_thread))
+                .next();
+        }
         _ => {}
     }
 }
 
-/// Checks if the process has no threads and can be safely removed.
-fn check_should_remove_process(
-    borrowed_process: &mut Process,
-    borrowed_thread: &mut Thread,
-) -> Result<(), AddressNotAligned> {
-    let thread_vectors = [
-        &borrowed_process.not_started_threads,
-        &borrowed_process.ready_threads,
-        &borrowed_process.sleeping_threads,
-    ];
-    if thread_vectors.into_iter().all(|v| v.is_empty()) {
-        //Clean up the process
-        SCHEDULER
-            .lock()
-            .unwrap()
-            .remove_process(&borrowed_thread.process);
-        logln!("Removed process from scheduler");
-        unsafe {
-            clear_user_mode_mapping(borrowed_process.cr3.0)?;
-        }
-    }
-    Ok(())
+/// Marks the process without threads as a zombie, for its parent to reap.
+///
+/// Its address space is freed when the process is dropped, as processors can still be running on it
+/// until they switch away from its threads.
+/// The caller removes it from the scheduler once it's unlocked.
+fn finish_process(borrowed_process: &mut Process, exit_code: u64) {
+    borrowed_process.state = ProcessState::Zombie;
+    borrowed_process.exit_code = Some(exit_code);
+    logln!(
+        "Process {} terminated with exit code {}",
+        borrowed_process.id,
+        exit_code
+    );
+    record_process_exit(borrowed_process.id, exit_code);
 }
 
 pub fn add_thread_to_process_queues(
@@ -266,6 +625,7 @@ pub fn add_thread_to_process_queues(
             "Trying to change a thread to running state - use dispatcher::switch_to_thread() instead"
         ),
         ThreadState::Sleeping(_) => borrowed_process.sleeping_threads.push(thread.clone()),
+        ThreadState::Blocked(_) => borrowed_process.blocked_threads.push(thread.clone()),
         ThreadState::Terminated => {}
     }
 }
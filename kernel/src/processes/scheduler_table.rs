@@ -1,7 +1,18 @@
-use alloc::vec::Vec;
-use internal_utils::logln;
+use alloc::{sync::Arc, vec::Vec};
+use internal_utils::{clocks::get_current_tick, logln};
+use spin::Mutex;
 
-pub struct SchedulerTable(pub Vec<ProcessInfo>);
+use crate::smp::cpu;
+
+use super::{
+    process::Process,
+    thread::{Thread, ThreadState},
+};
+
+pub struct SchedulerTable {
+    pub processes: Vec<ProcessInfo>,
+    pub cpus: Vec<CpuInfo>,
+}
 
 pub struct ProcessInfo {
     pub id: u64,
@@ -14,25 +25,140 @@ pub struct ThreadInfo {
     pub id: u64,
     pub state: &'static str,
     pub load: u64,
+    pub priority: u8,
+    pub remaining_quantum: u64,
+    /// The processor whose run queue the thread is on.
+    pub cpu_index: Option<usize>,
+    pub affinity: u64,
+}
+
+pub struct CpuInfo {
+    pub index: usize,
+    pub apic_id: u8,
+    /// The percentage of timer ticks the processor spent running a thread.
+    pub load: u64,
+    pub queued_threads: usize,
+    /// The process and thread IDs of the running thread.
+    pub running_thread: Option<(u64, u64)>,
+}
+
+impl CpuInfo {
+    /// Describes the processor, with the threads its scheduler holds.
+    pub fn new(
+        index: usize,
+        queued_threads: usize,
+        running_thread: Option<Arc<Mutex<Thread>>>,
+    ) -> Self {
+        let cpu = cpu(index);
+        CpuInfo {
+            index,
+            apic_id: cpu.apic_id(),
+            load: cpu.load(),
+            queued_threads,
+            running_thread: running_thread.map(|thread| {
+                let thread = thread.lock();
+                (thread.process.lock().id, thread.id)
+            }),
+        }
+    }
 }
 
 impl SchedulerTable {
+    /// Creates the table out of the processors, and the processes with all their threads.
+    pub fn new<'a>(
+        processes: impl Iterator<Item = &'a Arc<Mutex<Process>>>,
+        cpus: Vec<CpuInfo>,
+    ) -> Self {
+        let current_tick = get_current_tick();
+        let processes = processes
+            .map(|p| p.lock())
+            .map(|p| ProcessInfo {
+                id: p.id,
+                kernel_process: p.kernel_process,
+                load: p.tick_density(current_tick),
+                threads: p
+                    .ready_threads
+                    .iter()
+                    .chain(p.not_started_threads.iter())
+                    .chain(p.sleeping_threads.iter())
+                    .chain(p.blocked_threads.iter())
+                    .map(|t| t.lock())
+                    .map(|t| ThreadInfo {
+                        id: t.id,
+                        state: match t.state {
+                            ThreadState::NotStarted => "not started",
+                            ThreadState::Ready => "ready",
+                            ThreadState::Running => "running",
+                            ThreadState::Sleeping(_) => "sleeping",
+                            ThreadState::Blocked(_) => "blocked",
+                            ThreadState::Terminated => "terminated",
+                        },
+                        load: t.tick_density(current_tick),
+                        priority: t.priority,
+                        remaining_quantum: t.remaining_quantum,
+                        cpu_index: t.cpu_index,
+                        affinity: t.affinity.bits(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        SchedulerTable { processes, cpus }
+    }
+
     pub fn log(&self) {
-        logln!(" Process   | Thread    | CPU Usage | State     ");
-        for p in self.0.iter() {
+        logln!(" CPU | APIC ID | CPU Usage | Queued threads | Running thread ");
+        for c in self.cpus.iter() {
+            match c.running_thread {
+                Some((process_id, thread_id)) => logln!(
+                    "{: >4} | {: >7} | {: >8}% | {: >14} | {}:{}",
+                    c.index,
+                    c.apic_id,
+                    c.load,
+                    c.queued_threads,
+                    process_id,
+                    thread_id
+                ),
+                None => logln!(
+                    "{: >4} | {: >7} | {: >8}% | {: >14} | idle",
+                    c.index,
+                    c.apic_id,
+                    c.load,
+                    c.queued_threads
+                ),
+            }
+        }
+        logln!(
+            " Process   | Thread    | CPU Usage | State       | Priority | Quantum | CPU | Affinity "
+        );
+        for p in self.processes.iter() {
             logln!(
-                "{: >10} |           | {: >8}% | {: >9} ",
+                "{: >10} |           | {: >8}% | {: >11} |          |         |     |",
                 p.id,
                 p.load,
                 if p.kernel_process { "ring 0" } else { "ring 3" }
             );
             for t in p.threads.iter() {
-                logln!(
-                    "           | {: >9} | {: >8}% |{: >11}",
-                    t.id,
-                    t.load,
-                    t.state
-                );
+                match t.cpu_index {
+                    Some(cpu_index) => logln!(
+                        "           | {: >9} | {: >8}% | {: >11} | {: >8} | {: >7} | {: >3} | {:#x}",
+                        t.id,
+                        t.load,
+                        t.state,
+                        t.priority,
+                        t.remaining_quantum,
+                        cpu_index,
+                        t.affinity
+                    ),
+                    None => logln!(
+                        "           | {: >9} | {: >8}% | {: >11} | {: >8} | {: >7} |   - | {:#x}",
+                        t.id,
+                        t.load,
+                        t.state,
+                        t.priority,
+                        t.remaining_quantum,
+                        t.affinity
+                    ),
+                }
             }
         }
     }
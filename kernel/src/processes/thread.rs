@@ -3,11 +3,14 @@ use internal_utils::clocks::get_current_tick;
 use spin::Mutex;
 use x86_64::VirtAddr;
 
+use crate::memory::guarded_stack::{GuardedStack, StackOwner};
+use crate::processes::fpu_state::FpuState;
 use crate::processes::registers_state::Flags;
 use crate::processes::scheduler::{
-    add_thread_to_process_queues, remove_thread_from_process_queues,
+    add_thread_to_process_queues, enqueue_thread, remove_thread_from_process_queues,
 };
 use crate::processes::wakers::WakeHandle;
+use crate::smp::{CpuMask, cpu};
 
 use super::process::Process;
 
@@ -18,7 +21,10 @@ pub enum ThreadState {
     NotStarted,
     Ready,
     Running,
-    Sleeping(WakeHandle),
+    /// Sleeping until the given timer tick.
+    Sleeping(u64),
+    /// Waiting until the handle is woken up.
+    Blocked(WakeHandle),
     Terminated,
 }
 
@@ -30,6 +36,8 @@ pub struct Thread {
     pub state: ThreadState,
     /// The state of the registers.
     pub registers_state: RegistersState,
+    /// The state of the FPU/SSE/AVX registers, while they're not loaded in the CPU.
+    pub fpu_state: FpuState,
     /// Total ticks the thread has been running for.
     pub total_ticks: u64,
     /// The tick the thread has been created on.
@@ -38,6 +46,20 @@ pub struct Thread {
     pub last_tick: u64,
     /// The process the thread is running for.
     pub process: Arc<Mutex<Process>>,
+    /// The scheduling priority of the thread, 0 being the highest.
+    pub priority: u8,
+    /// Timer ticks left in the thread's current time slice.
+    pub remaining_quantum: u64,
+    /// The stack used by the kernel while handling the thread's system calls and ring 3 interrupts.
+    pub kernel_stack: GuardedStack,
+    /// The stack the thread runs on, if the kernel owns it (e.g. for kernel threads).
+    pub stack: Option<GuardedStack>,
+    /// The processors the thread may run on.
+    pub affinity: CpuMask,
+    /// The processor whose run queue the thread is (or was last) on, `None` until it's queued.
+    pub cpu_index: Option<usize>,
+    /// The processor the thread last ran on, with the timer ticks it had handled when it switched away.
+    pub last_cpu: Option<(usize, u64)>,
 }
 
 impl Thread {
@@ -47,24 +69,93 @@ impl Thread {
         self.total_ticks * 100 / ticks_maximum
     }
 
+    /// Checks if any of the thread's stacks contains the address.
+    pub fn is_stack_address(&self, address: VirtAddr) -> bool {
+        self.kernel_stack.contains(address)
+            || self
+                .stack
+                .as_ref()
+                .is_some_and(|stack| stack.contains(address))
+    }
+
+    /// Checks if the thread can be picked to run - it's ready or hasn't started yet.
+    pub fn is_runnable(&self) -> bool {
+        matches!(self.state, ThreadState::Ready | ThreadState::NotStarted)
+    }
+
+    /// Notes that the processor switched away from the thread.
+    pub fn left_cpu(&mut self, cpu_index: usize) {
+        self.last_cpu = Some((cpu_index, cpu(cpu_index).ticks()));
+    }
+
+    /// Checks if no processor other than the given one can still be using the thread's stacks.
+    ///
+    /// A processor keeps running on the stacks of the thread it switched away from until it enters
+    /// the next thread, so they're only surely released once it handled another timer tick.
+    pub fn stacks_released_for(&self, cpu_index: usize) -> bool {
+        self.last_cpu
+            .is_none_or(|(last_cpu, ticks)| last_cpu == cpu_index || cpu(last_cpu).ticks() > ticks)
+    }
+
+    /// Changes the state of the thread, moving it between its process's queues.
+    ///
+    /// A thread becoming ready again is queued on a processor, so it gets to run.
     pub fn change_state(thread: Arc<Mutex<Thread>>, state: ThreadState) {
-        let mut borrowed_thread = thread.lock();
-        let process = borrowed_thread.process.clone();
-        let mut borrowed_process = process.lock();
-        remove_thread_from_process_queues(&mut borrowed_process, &thread, borrowed_thread.state);
-        borrowed_thread.state = state;
-        add_thread_to_process_queues(&mut borrowed_process, &thread, state);
+        Thread::change_state_if(thread, |_| true, state);
+    }
+
+    /// Changes the state of the thread if the condition holds for its current state,
+    /// returning whether it did.
+    pub fn change_state_if(
+        thread: Arc<Mutex<Thread>>,
+        condition: impl FnOnce(ThreadState) -> bool,
+        state: ThreadState,
+    ) -> bool {
+        let woken = {
+            let mut borrowed_thread = thread.lock();
+            if !condition(borrowed_thread.state) {
+                return false;
+            }
+            let was_runnable = borrowed_thread.is_runnable();
+            let process = borrowed_thread.process.clone();
+            let mut borrowed_process = process.lock();
+            remove_thread_from_process_queues(
+                &mut borrowed_process,
+                &thread,
+                borrowed_thread.state,
+            );
+            borrowed_thread.state = state;
+            add_thread_to_process_queues(&mut borrowed_process, &thread, state);
+            !was_runnable && borrowed_thread.is_runnable()
+        };
+        // The schedulers are locked before the threads, so the thread is queued once it's unlocked
+        if woken {
+            enqueue_thread(thread);
+        }
+        true
+    }
+
+    /// Returns the owner the stacks of the thread are reported with if they overflow.
+    pub fn stack_owner(&self) -> StackOwner {
+        StackOwner::Thread {
+            process_id: self.process.lock().id,
+            thread_id: self.id,
+        }
     }
 
     /// Creates a new thread with the given starting address and stack pointer.
     ///
+    /// The thread only runs once it's fully set up and queued with `enqueue_thread`.
+    /// Returns `None` if there is not enough memory for the thread's kernel stack.
+    ///
     /// # Safety
     /// This function is unsafe as it does not enforce pointing the instruction and stack pointers to valid addresses.
     pub unsafe fn new_native(
         address: usize,
         stack_pointer: usize,
         process: Arc<Mutex<Process>>,
-    ) -> Arc<Mutex<Self>> {
+    ) -> Option<Arc<Mutex<Self>>> {
+        let kernel_stack = GuardedStack::new()?;
         let thread = Thread {
             id: {
                 let mut process = process.lock();
@@ -76,17 +167,26 @@ impl Thread {
             start_tick: get_current_tick(),
             last_tick: 0,
             process: process.clone(),
+            priority: 0,
+            remaining_quantum: 0,
+            kernel_stack,
+            stack: None,
+            affinity: CpuMask::ALL,
+            cpu_index: None,
+            last_cpu: None,
+            fpu_state: FpuState::new(),
             registers_state: RegistersState::new(
                 VirtAddr::new(address as u64),
                 Flags::IF.union(Flags::R1).union(Flags::RF),
                 VirtAddr::new(stack_pointer as u64),
             ),
         };
+        thread.kernel_stack.set_owner(thread.stack_owner());
         let thread_reference = Arc::new(Mutex::new(thread));
         process
             .lock()
             .not_started_threads
             .push(thread_reference.clone());
-        thread_reference
+        Some(thread_reference)
     }
 }
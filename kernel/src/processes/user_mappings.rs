@@ -0,0 +1,177 @@
+use core::fmt::Display;
+
+use rost_user::{MemoryProtection, SysCallError};
+use x86_64::{
+    VirtAddr,
+    structures::paging::{PageSize, PageTableFlags, Size4KiB},
+};
+
+use crate::addressing::{USER_MAPPINGS_START, USER_SPACE_END, USER_SPACE_START};
+use crate::smp::tlb::{AddressSpace, TlbFlush};
+
+use super::{
+    memory_areas::{MemoryArea, MemoryAreaError},
+    memory_mapper::{protect_user_pages, unmap_user_pages},
+    process::Process,
+};
+
+/// The errors of changing the memory mappings of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingError {
+    /// The process is a kernel process, which has no user memory
+    NotUserProcess,
+    /// The range is empty, not page-aligned or reaches outside of the user address space
+    InvalidRange,
+    /// The protection has unknown flags, or doesn't allow reading
+    InvalidProtection,
+    /// The range overlaps memory the process has already mapped
+    Overlapping,
+    /// A part of the range is not mapped
+    NotMapped,
+    /// There is no free range large enough for the mapping
+    NoSpace,
+}
+
+impl Display for MappingError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MappingError::NotUserProcess => write!(f, "Kernel processes have no user memory"),
+            MappingError::InvalidRange => write!(f, "Invalid memory range"),
+            MappingError::InvalidProtection => write!(f, "Invalid memory protection"),
+            MappingError::Overlapping => write!(f, "The range is already mapped"),
+            MappingError::NotMapped => write!(f, "The range is not mapped"),
+            MappingError::NoSpace => write!(f, "No free range large enough"),
+        }
+    }
+}
+
+impl From<MappingError> for SysCallError {
+    fn from(error: MappingError) -> Self {
+        match error {
+            MappingError::NotMapped => SysCallError::BadAddress,
+            MappingError::NoSpace => SysCallError::OutOfMemory,
+            _ => SysCallError::InvalidArgument,
+        }
+    }
+}
+
+impl From<MemoryAreaError> for MappingError {
+    fn from(error: MemoryAreaError) -> Self {
+        match error {
+            MemoryAreaError::Overlapping => MappingError::Overlapping,
+            MemoryAreaError::NotMapped => MappingError::NotMapped,
+        }
+    }
+}
+
+/// Returns the flags of the pages with the given protection.
+fn get_page_flags(protection: MemoryProtection) -> Result<PageTableFlags, MappingError> {
+    if !protection.contains(MemoryProtection::READ) {
+        return Err(MappingError::InvalidProtection);
+    }
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if protection.contains(MemoryProtection::WRITE) {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if !protection.contains(MemoryProtection::EXECUTE) {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    Ok(flags)
+}
+
+/// Returns the end of the range, rounded up to whole pages.
+fn get_range_end(address: VirtAddr, length: u64) -> Result<VirtAddr, MappingError> {
+    let end = address
+        .as_u64()
+        .checked_add(length)
+        .and_then(|end| end.checked_next_multiple_of(Size4KiB::SIZE))
+        .ok_or(MappingError::InvalidRange)?;
+    if length == 0
+        || !address.is_aligned(Size4KiB::SIZE)
+        || address.as_u64() < USER_SPACE_START
+        || end > USER_SPACE_END
+    {
+        return Err(MappingError::InvalidRange);
+    }
+    Ok(VirtAddr::new(end))
+}
+
+impl Process {
+    /// Maps zeroed memory into the process, returning its address.
+    ///
+    /// Without an address, the memory is placed in the lowest free range above `USER_MAPPINGS_START`.
+    /// The pages are only backed by frames once the process touches them.
+    pub fn map_anonymous(
+        &mut self,
+        address: Option<VirtAddr>,
+        length: u64,
+        protection: MemoryProtection,
+    ) -> Result<VirtAddr, MappingError> {
+        if self.kernel_process {
+            return Err(MappingError::NotUserProcess);
+        }
+        let flags = get_page_flags(protection)?;
+        let address = match address {
+            Some(address) => address,
+            None => {
+                let length = length
+                    .checked_next_multiple_of(Size4KiB::SIZE)
+                    .ok_or(MappingError::InvalidRange)?;
+                self.memory_areas
+                    .find_free_range(
+                        length,
+                        VirtAddr::new(USER_MAPPINGS_START),
+                        VirtAddr::new(USER_SPACE_END),
+                    )
+                    .ok_or(MappingError::NoSpace)?
+            }
+        };
+        let end = get_range_end(address, length)?;
+        self.memory_areas.add(MemoryArea {
+            start: address,
+            end,
+            flags,
+        })?;
+        Ok(address)
+    }
+
+    /// Unmaps the memory in the range, freeing the frames backing it once the returned flush is shot down.
+    ///
+    /// Parts of the range that are not mapped are skipped.
+    pub fn unmap(&mut self, address: VirtAddr, length: u64) -> Result<TlbFlush, MappingError> {
+        if self.kernel_process {
+            return Err(MappingError::NotUserProcess);
+        }
+        let end = get_range_end(address, length)?;
+        let mut flush = TlbFlush::new(AddressSpace::User(self.cr3.0));
+        for area in self.memory_areas.remove_range(address, end) {
+            unsafe { unmap_user_pages(self.cr3.0, area.pages(), &mut flush) };
+        }
+        Ok(flush)
+    }
+
+    /// Changes the protection of the memory in the range, which has to be fully mapped.
+    ///
+    /// The other threads of the process can keep the old protection until the returned flush is shot down.
+    pub fn protect(
+        &mut self,
+        address: VirtAddr,
+        length: u64,
+        protection: MemoryProtection,
+    ) -> Result<TlbFlush, MappingError> {
+        if self.kernel_process {
+            return Err(MappingError::NotUserProcess);
+        }
+        let flags = get_page_flags(protection)?;
+        let end = get_range_end(address, length)?;
+        self.memory_areas.protect_range(address, end, flags)?;
+        let area = MemoryArea {
+            start: address,
+            end,
+            flags,
+        };
+        let mut flush = TlbFlush::new(AddressSpace::User(self.cr3.0));
+        unsafe { protect_user_pages(self.cr3.0, area.pages(), flags, &mut flush) };
+        Ok(flush)
+    }
+}
@@ -0,0 +1,167 @@
+use alloc::{vec, vec::Vec};
+use internal_utils::kernel_information::KERNEL_INFORMATION;
+use rost_user::SysCallError;
+use spin::Mutex;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{PageSize, Size4KiB},
+};
+
+use crate::{
+    addressing::USER_SPACE_END,
+    smp::tlb::{AddressSpace, TlbFlush},
+};
+
+use super::{memory_areas::MemoryAccess, memory_mapper::translate_user_address, process::Process};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserMemoryError {
+    /// The range overflows or reaches outside of the user address space
+    OutOfRange,
+    /// A part of the range is not mapped, or not accessible by the process
+    Inaccessible,
+    /// The process is a kernel process, which has no user memory
+    NotUserProcess,
+}
+
+impl From<UserMemoryError> for SysCallError {
+    fn from(_: UserMemoryError) -> Self {
+        SysCallError::BadAddress
+    }
+}
+
+/// Returns the physical chunks backing a user buffer, one for every page it touches.
+///
+/// Every page is checked before anything is copied, so a copy either fully succeeds or does nothing.
+fn get_user_chunks(
+    process: &Process,
+    address: u64,
+    length: usize,
+    write: bool,
+    flush: &mut TlbFlush,
+) -> Result<Vec<(PhysAddr, usize)>, UserMemoryError> {
+    if process.kernel_process {
+        return Err(UserMemoryError::NotUserProcess);
+    }
+    let end = address
+        .checked_add(length as u64)
+        .ok_or(UserMemoryError::OutOfRange)?;
+    if end > USER_SPACE_END {
+        return Err(UserMemoryError::OutOfRange);
+    }
+
+    let mut chunks = Vec::new();
+    let mut current = address;
+    while current < end {
+        let physical = translate_or_resolve(process, VirtAddr::new(current), write, flush)
+            .ok_or(UserMemoryError::Inaccessible)?;
+        // A chunk can only span up to the end of the current page
+        let page_end = (current & !(Size4KiB::SIZE - 1)) + Size4KiB::SIZE;
+        let chunk_length = (page_end.min(end) - current) as usize;
+        chunks.push((physical, chunk_length));
+        current += chunk_length as u64;
+    }
+    Ok(chunks)
+}
+
+/// Translates the address, mapping its page first if the process hasn't touched it yet.
+fn translate_or_resolve(
+    process: &Process,
+    address: VirtAddr,
+    write: bool,
+    flush: &mut TlbFlush,
+) -> Option<PhysAddr> {
+    let translate = || unsafe { translate_user_address(process.cr3.0, address, write) };
+    translate().or_else(|| {
+        let access = if write {
+            MemoryAccess::Write
+        } else {
+            MemoryAccess::Read
+        };
+        process
+            .resolve_page_fault(address, access, flush)
+            .then(translate)
+            .flatten()
+    })
+}
+
+/// Runs the copy on the physical chunks backing a user buffer, while the process is locked,
+/// so they can't be unmapped in the meantime.
+fn copy_user_chunks(
+    process: &Mutex<Process>,
+    address: u64,
+    length: usize,
+    write: bool,
+    copy: impl FnOnce(Vec<(PhysAddr, usize)>),
+) -> Result<(), UserMemoryError> {
+    let (result, flush) = {
+        let process = process.lock();
+        let mut flush = TlbFlush::new(AddressSpace::User(process.cr3.0));
+        let result = get_user_chunks(&process, address, length, write, &mut flush).map(copy);
+        (result, flush)
+    };
+    // Writing can copy copy-on-write pages, which the other threads of the process might still read
+    flush.shoot_down();
+    result
+}
+
+/// Copies the process's memory at the address into the buffer.
+///
+/// The memory is accessed through the physical memory mapping, so this works with any page table active
+/// and can never page fault.
+pub fn copy_from_user(
+    process: &Mutex<Process>,
+    address: u64,
+    buffer: &mut [u8],
+) -> Result<(), UserMemoryError> {
+    let pmo = KERNEL_INFORMATION.get().unwrap().physical_memory_offset;
+    copy_user_chunks(process, address, buffer.len(), false, |chunks| {
+        let mut copied = 0;
+        for (physical, length) in chunks {
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    (physical.as_u64() + pmo) as *const u8,
+                    buffer[copied..].as_mut_ptr(),
+                    length,
+                );
+            }
+            copied += length;
+        }
+    })
+}
+
+/// Copies the data into the process's memory at the address, which has to be writable by the process.
+///
+/// The memory is accessed through the physical memory mapping, so this works with any page table active
+/// and can never page fault.
+pub fn copy_to_user(
+    process: &Mutex<Process>,
+    address: u64,
+    data: &[u8],
+) -> Result<(), UserMemoryError> {
+    let pmo = KERNEL_INFORMATION.get().unwrap().physical_memory_offset;
+    copy_user_chunks(process, address, data.len(), true, |chunks| {
+        let mut copied = 0;
+        for (physical, length) in chunks {
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data[copied..].as_ptr(),
+                    (physical.as_u64() + pmo) as *mut u8,
+                    length,
+                );
+            }
+            copied += length;
+        }
+    })
+}
+
+/// Reads `length` bytes of the process's memory at the address.
+pub fn read_user_bytes(
+    process: &Mutex<Process>,
+    address: u64,
+    length: usize,
+) -> Result<Vec<u8>, UserMemoryError> {
+    let mut buffer = vec![0; length];
+    copy_from_user(process, address, &mut buffer)?;
+    Ok(buffer)
+}
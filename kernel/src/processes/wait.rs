@@ -0,0 +1,148 @@
+use core::fmt::Display;
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use lazy_static::lazy_static;
+use rost_user::SysCallError;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::{thread::Thread, wakers::WakeHandle};
+
+/// The errors of waiting for a child process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The process has no child it could wait for (or the given process is not its child)
+    NoChildren,
+}
+
+impl Display for WaitError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WaitError::NoChildren => write!(f, "No child process to wait for"),
+        }
+    }
+}
+
+impl From<WaitError> for SysCallError {
+    fn from(error: WaitError) -> Self {
+        match error {
+            WaitError::NoChildren => SysCallError::NoChildren,
+        }
+    }
+}
+
+/// What's known about a process that hasn't been reaped yet.
+struct ProcessRecord {
+    /// The process that can reap it, if it's still alive.
+    parent_id: Option<u64>,
+    /// Kernel processes never reap their children, so the children are orphans from the start.
+    kernel_process: bool,
+    /// The exit code, if the process is a zombie.
+    exit_code: Option<u64>,
+}
+
+/// Every process that hasn't been reaped yet, by ID.
+///
+/// Zombies only keep their record here - the rest of the process is freed when it terminates.
+static PROCESS_RECORDS: Mutex<BTreeMap<u64, ProcessRecord>> = Mutex::new(BTreeMap::new());
+
+lazy_static! {
+    /// Woken up every time a process terminates.
+    static ref PROCESS_EXITED: WakeHandle = WakeHandle::new();
+}
+
+/// Starts keeping track of a process, so its parent can wait for it.
+///
+/// The children of kernel processes are dropped as soon as they terminate.
+pub(super) fn register_process(id: u64, parent_id: Option<u64>, kernel_process: bool) {
+    without_interrupts(|| {
+        let mut records = PROCESS_RECORDS.lock();
+        let parent_id = parent_id.filter(|parent_id| {
+            records
+                .get(parent_id)
+                .is_some_and(|parent| !parent.kernel_process)
+        });
+        records.insert(
+            id,
+            ProcessRecord {
+                parent_id,
+                kernel_process,
+                exit_code: None,
+            },
+        );
+    });
+}
+
+/// Turns the process into a zombie, until its parent reaps it.
+///
+/// Its own zombie children can't be reaped anymore, so they're dropped,
+/// and its living children are orphaned - they will be dropped as soon as they terminate.
+pub(super) fn record_process_exit(id: u64, exit_code: u64) {
+    without_interrupts(|| {
+        {
+            let mut records = PROCESS_RECORDS.lock();
+            records.retain(|_, record| {
+                if record.parent_id != Some(id) {
+                    return true;
+                }
+                record.parent_id = None;
+                record.exit_code.is_none()
+            });
+
+            let Some(record) = records.get_mut(&id) else {
+                return;
+            };
+            record.exit_code = Some(exit_code);
+            let parent_is_alive = record
+                .parent_id
+                .and_then(|parent_id| records.get(&parent_id))
+                .is_some_and(|parent| parent.exit_code.is_none());
+            if !parent_is_alive {
+                records.remove(&id);
+            }
+        }
+        PROCESS_EXITED.wake_all();
+    });
+}
+
+/// Reaps a terminated child of the process, returning its ID and exit code.
+///
+/// Waits for any child if `child_id` is `None`.
+/// Returns `Ok(None)` if the children are all still running.
+pub fn try_reap_child(
+    parent_id: u64,
+    child_id: Option<u64>,
+) -> Result<Option<(u64, u64)>, WaitError> {
+    without_interrupts(|| {
+        let mut records = PROCESS_RECORDS.lock();
+        let mut has_children = false;
+        let mut exited_child = None;
+        for (&id, record) in records.iter() {
+            if record.parent_id != Some(parent_id) || child_id.is_some_and(|child| child != id) {
+                continue;
+            }
+            has_children = true;
+            if let Some(exit_code) = record.exit_code {
+                exited_child = Some((id, exit_code));
+                break;
+            }
+        }
+        if !has_children {
+            return Err(WaitError::NoChildren);
+        }
+        if let Some((id, _)) = exited_child {
+            records.remove(&id);
+        }
+        Ok(exited_child)
+    })
+}
+
+/// Blocks the thread until any process terminates.
+pub fn block_until_process_exit(thread: Arc<Mutex<Thread>>) {
+    PROCESS_EXITED.block(thread);
+}
+
+/// Makes the thread blocked by `block_until_process_exit` ready again, if no process terminated yet.
+pub fn stop_waiting_for_process_exit(thread: &Arc<Mutex<Thread>>) {
+    PROCESS_EXITED.unblock(thread);
+}
@@ -1 +1,144 @@
-pub type WakeHandle = u64;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+use spin::Mutex;
+use x86_64::instructions::{hlt, interrupts::without_interrupts};
+
+use super::{
+    running_thread,
+    thread::{Thread, ThreadState},
+};
+
+/// Identifies an event threads can block on, until something wakes them up.
+///
+/// Every handle has its own wait queue, which wakes the threads in the order they started waiting.
+/// The threads can be woken up from anywhere - an interrupt handler, a channel or another thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WakeHandle(u64);
+
+static NEXT_WAKE_HANDLE: AtomicU64 = AtomicU64::new(0);
+
+/// The threads blocked on each handle.
+static WAIT_QUEUES: Mutex<BTreeMap<WakeHandle, VecDeque<Arc<Mutex<Thread>>>>> =
+    Mutex::new(BTreeMap::new());
+
+impl WakeHandle {
+    /// Creates a new, unique handle.
+    pub fn new() -> Self {
+        WakeHandle(NEXT_WAKE_HANDLE.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Blocks the thread on this handle, so it won't be scheduled until it's woken up.
+    ///
+    /// If the thread is the running one, it keeps running until the next `schedule()`.
+    pub fn block(self, thread: Arc<Mutex<Thread>>) {
+        // The state changes can't be interrupted, as interrupt handlers can wake threads up
+        without_interrupts(|| {
+            Thread::change_state(thread.clone(), ThreadState::Blocked(self));
+            WAIT_QUEUES
+                .lock()
+                .entry(self)
+                .or_default()
+                .push_back(thread);
+        });
+    }
+
+    /// Blocks the running kernel thread until this handle is woken up.
+    pub fn wait(self) {
+        let thread = running_thread().expect("Only a running thread can wait");
+        self.block(thread.clone());
+        // The timer interrupt switches to other threads, and dispatches us again when we're woken up
+        while without_interrupts(|| matches!(thread.lock().state, ThreadState::Blocked(_))) {
+            hlt();
+        }
+    }
+
+    /// Blocks the running kernel thread until the check returns a value, checking again every time
+    /// this handle is woken up.
+    ///
+    /// The thread is blocked before the check, so a wake-up from another processor can't slip in
+    /// between them.
+    pub fn wait_until<T>(self, mut check: impl FnMut() -> Option<T>) -> T {
+        let thread = running_thread().expect("Only a running thread can wait");
+        loop {
+            self.block(thread.clone());
+            if let Some(result) = check() {
+                self.unblock(&thread);
+                return result;
+            }
+            while without_interrupts(|| matches!(thread.lock().state, ThreadState::Blocked(_))) {
+                hlt();
+            }
+        }
+    }
+
+    /// Takes the thread out of this handle's wait queue, making it ready again,
+    /// if it hasn't been woken up yet.
+    pub fn unblock(self, thread: &Arc<Mutex<Thread>>) {
+        without_interrupts(|| {
+            {
+                let mut queues = WAIT_QUEUES.lock();
+                if let Some(queue) = queues.get_mut(&self) {
+                    queue.retain(|waiting| !Arc::ptr_eq(waiting, thread));
+                    if queue.is_empty() {
+                        queues.remove(&self);
+                    }
+                }
+            }
+            self.try_wake(thread.clone());
+        });
+    }
+
+    /// Wakes up the thread which has been blocked on this handle for the longest time.
+    ///
+    /// Returns whether there was a thread to wake up.
+    pub fn wake_one(self) -> bool {
+        without_interrupts(|| {
+            while let Some(thread) = self.pop_waiting_thread() {
+                if self.try_wake(thread) {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+
+    /// Wakes up all threads blocked on this handle, returning how many there were.
+    pub fn wake_all(self) -> usize {
+        without_interrupts(|| {
+            let threads = WAIT_QUEUES.lock().remove(&self).unwrap_or_default();
+            threads
+                .into_iter()
+                .filter(|thread| self.try_wake(thread.clone()))
+                .count()
+        })
+    }
+
+    fn pop_waiting_thread(self) -> Option<Arc<Mutex<Thread>>> {
+        let mut queues = WAIT_QUEUES.lock();
+        let queue = queues.get_mut(&self)?;
+        let thread = queue.pop_front();
+        if queue.is_empty() {
+            queues.remove(&self);
+        }
+        thread
+    }
+
+    /// Makes the thread ready, if it's still blocked on this handle (it could have exited in the meantime).
+    fn try_wake(self, thread: Arc<Mutex<Thread>>) -> bool {
+        Thread::change_state_if(
+            thread,
+            |state| matches!(state, ThreadState::Blocked(handle) if handle == self),
+            ThreadState::Ready,
+        )
+    }
+}
+
+impl Default for WakeHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
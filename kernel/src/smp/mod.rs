@@ -0,0 +1,356 @@
+// The bootstrap processor starts the application processors listed in the MADT one at a time,
+// with an INIT IPI followed by two startup IPIs.
+// They start in real mode, at a trampoline copied below 1MiB which takes them straight to long mode,
+// and continue in the kernel with their own GDT, TSS and stacks.
+
+use core::{
+    arch::naked_asm,
+    mem,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering},
+};
+
+use alloc::vec::Vec;
+use internal_utils::{
+    acpi::MadtEntry,
+    clocks::busy_wait_microseconds,
+    kernel_information::{KERNEL_INFORMATION, heap_tracking::set_processor_tags},
+    logln,
+};
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{PageSize, Size4KiB},
+};
+
+use crate::{
+    interrupts::{apic, setup_application_processor},
+    memory::{
+        guarded_stack::{GuardedStack, StackOwner},
+        identity_map, identity_unmap, kernel_level_4_frame, switch_to_kernel_memory,
+    },
+    processes::{enable_fpu, run_processes},
+    syscalls::load_syscall_registers,
+};
+
+pub mod tlb;
+mod trampoline;
+use trampoline::TrampolineParameters;
+
+/// The most processors the kernel runs on - the others are left waiting for a startup IPI.
+pub const MAX_CPUS: usize = 16;
+
+/// The startup IPIs take the page number of the trampoline, so it has to be below 1MiB.
+const TRAMPOLINE_LIMIT: u64 = 0x10_0000;
+
+/// How long an application processor gets to reach the kernel, in microseconds.
+const STARTUP_TIMEOUT: u64 = 100_000;
+/// How often the bootstrap processor checks if the application processor started, in microseconds.
+const STARTUP_POLL_INTERVAL: u64 = 100;
+
+/// The data of a processor, mostly written by the processor itself.
+///
+/// The other processors read its ticks and load, and request TLB shootdowns through it, so every field is atomic.
+/// `_syscall` reaches it through the kernel GS base before it has a stack, so it has a fixed layout.
+#[repr(C)]
+pub struct PerCpu {
+    /// The top of the kernel stack `_syscall` switches to.
+    pub(crate) syscall_kernel_stack: AtomicU64,
+    /// Scratch space for the user stack pointer while `_syscall` is switching stacks.
+    pub(crate) syscall_user_stack: AtomicU64,
+    apic_id: AtomicU8,
+    /// The top of the stack the processor waits on while it has no thread to run.
+    idle_stack_top: AtomicU64,
+    /// The timer interrupts the processor handled.
+    ticks: AtomicU64,
+    /// The timer interrupts which found a thread running on the processor.
+    busy_ticks: AtomicU64,
+    /// The subsystem the processor's heap allocations are accounted to.
+    allocation_tag: AtomicU8,
+    /// The physical address of the level 4 page table the processor runs on.
+    address_space: AtomicU64,
+    /// Set while the processor has to flush the pages of the requested TLB shootdown.
+    flush_requested: AtomicBool,
+}
+
+impl PerCpu {
+    const fn new() -> PerCpu {
+        PerCpu {
+            syscall_kernel_stack: AtomicU64::new(0),
+            syscall_user_stack: AtomicU64::new(0),
+            apic_id: AtomicU8::new(0),
+            idle_stack_top: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+            busy_ticks: AtomicU64::new(0),
+            allocation_tag: AtomicU8::new(0),
+            address_space: AtomicU64::new(0),
+            flush_requested: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the ID of the processor's local APIC.
+    pub fn apic_id(&self) -> u8 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of timer interrupts the processor handled.
+    pub fn ticks(&self) -> u64 {
+        self.ticks.load(Ordering::Acquire)
+    }
+
+    /// Returns the percentage of timer interrupts which found a thread running on the processor.
+    pub fn load(&self) -> u64 {
+        self.busy_ticks.load(Ordering::Relaxed) * 100 / self.ticks().max(1)
+    }
+
+    /// Counts a timer interrupt of the processor. Should only be called by the timer interrupt handler.
+    pub(crate) fn count_tick(&self, busy: bool) {
+        if busy {
+            self.busy_ticks.fetch_add(1, Ordering::Relaxed);
+        }
+        self.ticks.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// A set of processors, by CPU index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuMask(u64);
+
+impl CpuMask {
+    /// Every processor, including the ones started later.
+    pub const ALL: CpuMask = CpuMask(u64::MAX);
+
+    /// Creates the set out of its bits, the bit `n` standing for the CPU index `n`.
+    pub const fn from_bits(bits: u64) -> CpuMask {
+        CpuMask(bits)
+    }
+
+    /// Creates the set of only the processor.
+    pub const fn single(cpu_index: usize) -> CpuMask {
+        CpuMask(1 << cpu_index)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Adds the processor to the set.
+    pub fn insert(&mut self, cpu_index: usize) {
+        self.0 |= CpuMask::single(cpu_index).0;
+    }
+
+    pub const fn contains(self, cpu_index: usize) -> bool {
+        cpu_index < u64::BITS as usize && self.0 & (1 << cpu_index) != 0
+    }
+
+    /// Returns the processors of the set running the kernel.
+    pub fn running_cpus(self) -> impl Iterator<Item = usize> {
+        (0..cpu_count()).filter(move |&cpu_index| self.contains(cpu_index))
+    }
+}
+
+/// The data of every processor, by CPU index - the bootstrap processor has the index 0.
+static CPUS: [PerCpu; MAX_CPUS] = [const { PerCpu::new() }; MAX_CPUS];
+
+/// The number of processors running the kernel, which have the CPU indices below it.
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+/// The CPU index of every APIC ID.
+static CPU_INDICES: [AtomicU8; 256] = [const { AtomicU8::new(0) }; 256];
+
+/// Set by an application processor once it runs the kernel, so the next one can be started.
+static PROCESSOR_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the index of the running processor, from 0 to `cpu_count()`.
+///
+/// Without the APICs, only the bootstrap processor runs, so it's always 0.
+pub fn current_cpu_index() -> usize {
+    apic::local_apic_id().map_or(0, |apic_id| {
+        CPU_INDICES[apic_id as usize].load(Ordering::Relaxed) as usize
+    })
+}
+
+/// Returns the data of the running processor.
+pub fn current_cpu() -> &'static PerCpu {
+    &CPUS[current_cpu_index()]
+}
+
+/// Accounts the heap allocations of every processor to its own tag.
+pub fn init_allocation_tags() {
+    set_processor_tags(|| &current_cpu().allocation_tag);
+}
+
+/// Returns the data of the processor.
+pub fn cpu(cpu_index: usize) -> &'static PerCpu {
+    &CPUS[cpu_index]
+}
+
+/// Returns the number of processors running the kernel.
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::Acquire)
+}
+
+/// Waits on the running processor's idle stack until an interrupt gives it a thread to run.
+///
+/// The stack of the thread switched away from is left behind, so the thread can run elsewhere.
+/// So is its address space, which another processor might free once its process exits.
+pub fn idle() -> ! {
+    let stack_top = current_cpu().idle_stack_top.load(Ordering::Relaxed);
+    assert_ne!(stack_top, 0, "The processor has no idle stack");
+    switch_to_kernel_memory();
+    unsafe { idle_on_stack(stack_top) }
+}
+
+#[unsafe(naked)]
+unsafe extern "sysv64" fn idle_on_stack(stack_top: u64) -> ! {
+    naked_asm!("mov rsp, rdi", "2:", "sti", "hlt", "jmp 2b")
+}
+
+/// Allocates the stack the processor starts on (for the application processors), and waits on while idle.
+///
+/// The stack is never freed, as the processor might still start after its timeout.
+fn allocate_idle_stack(cpu_index: usize) -> Option<u64> {
+    let stack = GuardedStack::new()?;
+    stack.set_owner(StackOwner::Processor(cpu_index));
+    let stack_top = stack.top().as_u64();
+    mem::forget(stack);
+    CPUS[cpu_index]
+        .idle_stack_top
+        .store(stack_top, Ordering::Relaxed);
+    Some(stack_top)
+}
+
+/// Starts the enabled processors of the MADT, which then run threads once the scheduler is initialized.
+///
+/// Has to be called on the bootstrap processor, after the interrupts and the system calls are set up.
+pub fn start_application_processors() {
+    logln!("[   ---{:^15}---   ]", "SMP");
+    if allocate_idle_stack(0).is_none() {
+        logln!("[WARN] Not enough memory for the idle stack of the bootstrap processor");
+    }
+    let Some(bootstrap_apic_id) = apic::local_apic_id() else {
+        logln!("[WARN] No APIC found, running on a single processor");
+        return;
+    };
+    CPUS[0].apic_id.store(bootstrap_apic_id, Ordering::Relaxed);
+    let kernel_info = KERNEL_INFORMATION
+        .get()
+        .expect("The kernel information has to be initialized");
+    let apic_ids: Vec<u8> = kernel_info
+        .acpi
+        .and_then(|tables| tables.madt())
+        .map(|madt| {
+            madt.entries()
+                .filter_map(|entry| match entry {
+                    MadtEntry::LocalApic {
+                        apic_id,
+                        enabled: true,
+                        ..
+                    } if apic_id != bootstrap_apic_id => Some(apic_id),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if apic_ids.is_empty() {
+        logln!("Running on a single processor");
+        return;
+    }
+
+    // The trampoline loads CR3 while still in 32 bit mode
+    let cr3 = kernel_level_4_frame().start_address();
+    if cr3.as_u64() > u32::MAX as u64 {
+        logln!("[WARN] The kernel's paging table is above 4GiB, running on a single processor");
+        return;
+    }
+    let pmo = kernel_info.physical_memory_offset;
+    let Some(frames) = kernel_info.allocator.lock().allocate_contiguous(
+        1,
+        PhysAddr::new(TRAMPOLINE_LIMIT),
+        Size4KiB::SIZE,
+    ) else {
+        logln!("[WARN] No memory below 1MiB for the trampoline, running on a single processor");
+        return;
+    };
+    if let Err(error) = identity_map(frames.start) {
+        logln!("[WARN] Couldn't map the trampoline: {:?}", error);
+        unsafe { kernel_info.allocator.lock().deallocate_contiguous(frames) };
+        return;
+    }
+    let page = frames.start.start_address();
+    unsafe { trampoline::install(page, pmo) };
+    // The application processors' timers have to tick as often as the bootstrap processor's
+    apic::calibrate_timer();
+
+    for apic_id in apic_ids {
+        let cpu_index = cpu_count();
+        if cpu_index >= MAX_CPUS {
+            logln!("[WARN] Only {} processors are supported", MAX_CPUS);
+            break;
+        }
+        // A processor starting late would run the trampoline of the next one, so we stop there
+        if !start_processor(apic_id, cpu_index, page, cr3, pmo) {
+            logln!("[WARN] The processor with APIC ID {} didn't start", apic_id);
+            break;
+        }
+    }
+
+    identity_unmap(frames.start);
+    unsafe { kernel_info.allocator.lock().deallocate_contiguous(frames) };
+    logln!("Running on {} processors", cpu_count());
+}
+
+/// Starts the processor at the trampoline in the page, returning `false` if it didn't reach the kernel in time.
+fn start_processor(
+    apic_id: u8,
+    cpu_index: usize,
+    page: PhysAddr,
+    cr3: PhysAddr,
+    physical_memory_offset: u64,
+) -> bool {
+    let Some(stack_top) = allocate_idle_stack(cpu_index) else {
+        return false;
+    };
+
+    CPUS[cpu_index].apic_id.store(apic_id, Ordering::Relaxed);
+    CPU_INDICES[apic_id as usize].store(cpu_index as u8, Ordering::Relaxed);
+    PROCESSOR_STARTED.store(false, Ordering::Release);
+    unsafe {
+        TrampolineParameters::new(
+            page,
+            cr3,
+            VirtAddr::new(stack_top),
+            application_processor_entry,
+            cpu_index,
+        )
+        .write(page, physical_memory_offset);
+    }
+
+    apic::send_init_ipi(apic_id);
+    busy_wait_microseconds(10_000);
+    // The second startup IPI is ignored if the first one started the processor
+    for _ in 0..2 {
+        apic::send_startup_ipi(apic_id, (page.as_u64() / Size4KiB::SIZE) as u8);
+        busy_wait_microseconds(200);
+    }
+
+    for _ in 0..STARTUP_TIMEOUT / STARTUP_POLL_INTERVAL {
+        if PROCESSOR_STARTED.load(Ordering::Acquire) {
+            CPU_COUNT.fetch_add(1, Ordering::AcqRel);
+            return true;
+        }
+        busy_wait_microseconds(STARTUP_POLL_INTERVAL);
+    }
+    false
+}
+
+/// Where the application processors continue from the trampoline, on their idle stack.
+extern "sysv64" fn application_processor_entry(cpu_index: usize) -> ! {
+    setup_application_processor(cpu_index);
+    enable_fpu();
+    load_syscall_registers();
+    apic::start_local_timer();
+    logln!("CPU {} started", cpu_index);
+    PROCESSOR_STARTED.store(true, Ordering::Release);
+
+    // The processor idles until the scheduler is initialized, then runs threads on every timer tick
+    run_processes();
+}
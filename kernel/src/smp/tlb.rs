@@ -0,0 +1,225 @@
+// A processor changing a mapping only flushes its own TLB, so the other processors using the address space
+// get an inter-processor interrupt and flush theirs. The changing processor waits until they all did,
+// and only then the unmapped frames are released.
+// Only one shootdown is sent at once. The processors waiting to send theirs handle the requests they get
+// meanwhile, as they do it with interrupts disabled.
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU64, Ordering, fence},
+};
+
+use alloc::vec::Vec;
+use internal_utils::kernel_information::KERNEL_INFORMATION;
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    instructions::{interrupts::without_interrupts, tlb},
+    structures::paging::{Page, PhysFrame, Size4KiB},
+};
+
+use crate::interrupts::apic;
+
+use super::{CpuMask, cpu, cpu_count, current_cpu, current_cpu_index};
+
+/// The vector of the inter-processor interrupt asking a processor to flush its TLB.
+pub const TLB_SHOOTDOWN_VECTOR: u8 = 0xFE;
+
+/// Flushing more pages than this flushes the whole TLB instead.
+const MAX_FLUSHED_PAGES: u64 = 32;
+
+/// Held by the processor sending a shootdown, as the requested pages are shared by every processor.
+static SHOOTDOWN_LOCK: Mutex<()> = Mutex::new(());
+/// The first page of the requested shootdown.
+static REQUESTED_START: AtomicU64 = AtomicU64::new(0);
+/// The number of pages of the requested shootdown, or 0 to flush the whole TLB.
+static REQUESTED_PAGES: AtomicU64 = AtomicU64::new(0);
+
+/// The mappings a TLB flush is for, which decides the processors that have to flush.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    /// The kernel's part of the address space, which every processor uses.
+    Kernel,
+    /// A user address space, by its level 4 page table, used by the processors running its threads.
+    User(PhysFrame),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlushedPages {
+    Nothing,
+    /// The pages from the start up to the end (excluded).
+    Range(Page, Page),
+    Everything,
+}
+
+/// The mappings that changed in an address space, which the processors using it might still have in their TLB.
+///
+/// It has to be shot down once the locks are released, as the other processors might be waiting for them
+/// with interrupts disabled, and so couldn't flush.
+#[derive(Debug)]
+#[must_use = "the other processors keep using the old mappings until it's shot down"]
+pub struct TlbFlush {
+    address_space: AddressSpace,
+    pages: FlushedPages,
+    /// The frames unmapped from the pages, which can only be released once no TLB maps them anymore.
+    released_frames: Vec<PhysFrame>,
+}
+
+impl TlbFlush {
+    /// Creates an empty flush of the address space.
+    pub const fn new(address_space: AddressSpace) -> TlbFlush {
+        TlbFlush {
+            address_space,
+            pages: FlushedPages::Nothing,
+            released_frames: Vec::new(),
+        }
+    }
+
+    /// Creates an empty flush with room for the given number of released frames,
+    /// so they can be added without allocating.
+    pub fn with_capacity(address_space: AddressSpace, frames: usize) -> TlbFlush {
+        TlbFlush {
+            released_frames: Vec::with_capacity(frames),
+            ..TlbFlush::new(address_space)
+        }
+    }
+
+    /// Adds a page whose mapping changed.
+    pub fn add_page(&mut self, page: Page) {
+        self.pages = match self.pages {
+            FlushedPages::Nothing => FlushedPages::Range(page, page + 1),
+            FlushedPages::Range(start, end) => {
+                let (start, end) = (start.min(page), end.max(page + 1));
+                if end - start > MAX_FLUSHED_PAGES {
+                    FlushedPages::Everything
+                } else {
+                    FlushedPages::Range(start, end)
+                }
+            }
+            FlushedPages::Everything => FlushedPages::Everything,
+        };
+    }
+
+    /// Adds an unmapped page, whose frame is released once the flush is shot down.
+    pub fn add_unmapped_page(&mut self, page: Page, frame: PhysFrame) {
+        self.add_page(page);
+        self.released_frames.push(frame);
+    }
+
+    /// Flushes every mapping of the address space.
+    pub fn add_everything(&mut self) {
+        self.pages = FlushedPages::Everything;
+    }
+
+    /// Flushes the pages from the TLB of every processor using the address space, waiting for them,
+    /// and then releases the unmapped frames.
+    ///
+    /// Must not be called while holding a lock another processor might wait for with interrupts disabled.
+    pub fn shoot_down(self) {
+        let (start, count) = match self.pages {
+            FlushedPages::Nothing => (VirtAddr::zero(), None),
+            FlushedPages::Range(start, end) => (start.start_address(), Some(end - start)),
+            FlushedPages::Everything => (VirtAddr::zero(), Some(0)),
+        };
+        if let Some(count) = count {
+            shoot_down(self.address_space, start, count);
+        }
+        if self.released_frames.is_empty() {
+            return;
+        }
+        let allocator = KERNEL_INFORMATION.get().unwrap().allocator;
+        let mut allocator = allocator.lock();
+        for frame in self.released_frames {
+            // The frame could still be shared with another address space
+            unsafe { allocator.release_frame(frame) };
+        }
+    }
+}
+
+/// Notes the address space the running processor is about to switch to, so it gets the shootdowns for it.
+///
+/// Has to be called before loading the page table into CR3, with interrupts disabled.
+pub fn enter_address_space(level_4_frame: PhysFrame) {
+    current_cpu()
+        .address_space
+        .store(level_4_frame.start_address().as_u64(), Ordering::SeqCst);
+}
+
+/// Checks if the processor might have mappings of the address space in its TLB.
+fn uses_address_space(cpu_index: usize, address_space: AddressSpace) -> bool {
+    match address_space {
+        AddressSpace::Kernel => true,
+        AddressSpace::User(level_4_frame) => {
+            cpu(cpu_index).address_space.load(Ordering::SeqCst)
+                == level_4_frame.start_address().as_u64()
+        }
+    }
+}
+
+/// Flushes the pages from the running processor's TLB, or the whole TLB if the count is 0.
+fn flush_pages(start: VirtAddr, count: u64) {
+    if count == 0 {
+        tlb::flush_all();
+        return;
+    }
+    for page in Page::<Size4KiB>::range(
+        Page::containing_address(start),
+        Page::containing_address(start) + count,
+    ) {
+        tlb::flush(page.start_address());
+    }
+}
+
+/// Flushes the pages from the TLB of every processor using the address space, waiting for them.
+fn shoot_down(address_space: AddressSpace, start: VirtAddr, count: u64) {
+    // The processors loading the address space from now on see the changed page tables
+    fence(Ordering::SeqCst);
+    without_interrupts(|| {
+        let cpu_index = current_cpu_index();
+        if uses_address_space(cpu_index, address_space) {
+            flush_pages(start, count);
+        }
+        if cpu_count() == 1 {
+            return;
+        }
+
+        let _guard = loop {
+            if let Some(guard) = SHOOTDOWN_LOCK.try_lock() {
+                break guard;
+            }
+            // The processor sending a shootdown might be waiting for us
+            handle_tlb_shootdown();
+            spin_loop();
+        };
+        REQUESTED_START.store(start.as_u64(), Ordering::Relaxed);
+        REQUESTED_PAGES.store(count, Ordering::Relaxed);
+        let mut targets = CpuMask::from_bits(0);
+        for target in (0..cpu_count()).filter(|&target| target != cpu_index) {
+            if uses_address_space(target, address_space) {
+                targets.insert(target);
+                cpu(target).flush_requested.store(true, Ordering::Release);
+                apic::send_ipi(cpu(target).apic_id(), TLB_SHOOTDOWN_VECTOR);
+            }
+        }
+        for target in targets.running_cpus() {
+            while cpu(target).flush_requested.load(Ordering::Acquire) {
+                spin_loop();
+            }
+        }
+    });
+}
+
+/// Flushes the pages of the shootdown sent to the running processor, if there is one.
+///
+/// Called by the shootdown interrupt's handler, with interrupts disabled.
+pub fn handle_tlb_shootdown() {
+    let cpu = current_cpu();
+    if !cpu.flush_requested.load(Ordering::Acquire) {
+        return;
+    }
+    flush_pages(
+        VirtAddr::new(REQUESTED_START.load(Ordering::Relaxed)),
+        REQUESTED_PAGES.load(Ordering::Relaxed),
+    );
+    cpu.flush_requested.store(false, Ordering::Release);
+}
@@ -0,0 +1,174 @@
+use core::{arch::global_asm, mem::offset_of};
+
+use x86_64::{
+    PhysAddr, VirtAddr,
+    registers::{
+        control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+        model_specific::{Efer, EferFlags},
+    },
+};
+
+/// The values the trampoline needs, patched into its copy before starting a processor.
+///
+/// The layout has to match the `.skip` reserving it at the end of the trampoline.
+#[repr(C)]
+pub(super) struct TrampolineParameters {
+    /// The far pointer `ljmpl` takes to enter the 64 bit code segment.
+    long_mode_offset: u32,
+    long_mode_selector: u16,
+    _padding: [u16; 2],
+    /// The GDT pointer `lgdtl` takes, which has to be right after the padding.
+    gdt_limit: u16,
+    gdt_base: u32,
+    cr0: u32,
+    cr4: u32,
+    cr3: u32,
+    efer: u32,
+    stack_top: u64,
+    /// The address of the `extern "sysv64" fn(usize) -> !` the processor continues at.
+    entry: u64,
+    cpu_index: u64,
+    /// A null, a 64 bit code and a data segment - just enough to get to the kernel's GDT.
+    gdt: [u64; 3],
+}
+
+const CODE_SELECTOR: u16 = 8;
+const DATA_SELECTOR: u16 = 16;
+const CODE_SEGMENT: u64 = 0x00AF_9A00_0000_FFFF;
+const DATA_SEGMENT: u64 = 0x00CF_9200_0000_FFFF;
+
+// The code an application processor starts at, in real mode, after the startup IPI.
+// It's copied to a page below 1MiB, identity mapped, and switches straight to long mode using the
+// paging of the kernel, before calling the entry with the stack and CPU index of the parameters.
+global_asm!(
+    ".pushsection .text.ap_trampoline, \"ax\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_long_mode",
+    ".global ap_trampoline_end",
+    ".code16",
+    "ap_trampoline_start:",
+    ".Lstart:",
+    "cli",
+    "cld",
+    // The startup IPI sets CS to the page, and the parameters are addressed from it
+    "movw %cs, %ax",
+    "movw %ax, %ds",
+    "lgdtl .Lparameters - .Lstart + {gdt_pointer}",
+    "movl .Lparameters - .Lstart + {cr4}, %eax",
+    "movl %eax, %cr4",
+    "movl .Lparameters - .Lstart + {cr3}, %eax",
+    "movl %eax, %cr3",
+    "movl .Lparameters - .Lstart + {efer}, %eax",
+    "xorl %edx, %edx",
+    "movl ${efer_msr}, %ecx",
+    "wrmsr",
+    // Enabling protected mode and paging at once activates long mode
+    "movl .Lparameters - .Lstart + {cr0}, %eax",
+    "movl %eax, %cr0",
+    "ljmpl *.Lparameters - .Lstart + {long_mode}",
+    ".code64",
+    "ap_trampoline_long_mode:",
+    "movw ${data_selector}, %ax",
+    "movw %ax, %ds",
+    "movw %ax, %es",
+    "movw %ax, %ss",
+    "movq .Lparameters + {stack_top}(%rip), %rsp",
+    "movq .Lparameters + {cpu_index}(%rip), %rdi",
+    "movq .Lparameters + {entry}(%rip), %rax",
+    "callq *%rax",
+    "ud2",
+    ".balign 8",
+    ".Lparameters:",
+    ".skip {parameters_size}",
+    "ap_trampoline_end:",
+    ".popsection",
+    gdt_pointer = const offset_of!(TrampolineParameters, gdt_limit),
+    cr0 = const offset_of!(TrampolineParameters, cr0),
+    cr4 = const offset_of!(TrampolineParameters, cr4),
+    cr3 = const offset_of!(TrampolineParameters, cr3),
+    efer = const offset_of!(TrampolineParameters, efer),
+    efer_msr = const 0xC000_0080u32,
+    long_mode = const offset_of!(TrampolineParameters, long_mode_offset),
+    data_selector = const DATA_SELECTOR,
+    stack_top = const offset_of!(TrampolineParameters, stack_top),
+    cpu_index = const offset_of!(TrampolineParameters, cpu_index),
+    entry = const offset_of!(TrampolineParameters, entry),
+    parameters_size = const size_of::<TrampolineParameters>(),
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_long_mode: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Returns the distance of the symbol from the start of the trampoline.
+fn trampoline_offset(symbol: *const u8) -> u64 {
+    symbol as u64 - (&raw const ap_trampoline_start) as u64
+}
+
+/// Returns the distance of the parameters from the start of the trampoline, which they end.
+fn parameters_offset() -> u64 {
+    trampoline_offset(&raw const ap_trampoline_end) - size_of::<TrampolineParameters>() as u64
+}
+
+/// Copies the trampoline to the start of the page.
+///
+/// # Safety
+/// The page has to be unused, in physical memory mapped at the offset.
+pub(super) unsafe fn install(page: PhysAddr, physical_memory_offset: u64) {
+    let length = trampoline_offset(&raw const ap_trampoline_end) as usize;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            &raw const ap_trampoline_start,
+            (physical_memory_offset + page.as_u64()) as *mut u8,
+            length,
+        );
+    }
+}
+
+impl TrampolineParameters {
+    /// Creates the parameters of a processor starting at the trampoline copied to the page.
+    ///
+    /// The processor gets the control registers of the running one, and the paging table in CR3,
+    /// which has to be below 4GiB and identity map the page.
+    pub(super) fn new(
+        page: PhysAddr,
+        cr3: PhysAddr,
+        stack_top: VirtAddr,
+        entry: extern "sysv64" fn(usize) -> !,
+        cpu_index: usize,
+    ) -> TrampolineParameters {
+        let gdt_offset = parameters_offset() + offset_of!(TrampolineParameters, gdt) as u64;
+        TrampolineParameters {
+            long_mode_offset: (page.as_u64()
+                + trampoline_offset(&raw const ap_trampoline_long_mode))
+                as u32,
+            long_mode_selector: CODE_SELECTOR,
+            _padding: [0; 2],
+            gdt_limit: (size_of::<[u64; 3]>() - 1) as u16,
+            gdt_base: (page.as_u64() + gdt_offset) as u32,
+            // The thread owning the FPU state runs on another processor
+            cr0: (Cr0::read_raw() & !Cr0Flags::TASK_SWITCHED.bits()) as u32,
+            // PCIDs can only be enabled once in long mode
+            cr4: (Cr4::read_raw() & !Cr4Flags::PCID.bits()) as u32,
+            cr3: cr3.as_u64() as u32,
+            // The processor sets the long mode active bit itself
+            efer: (Efer::read_raw() & !EferFlags::LONG_MODE_ACTIVE.bits()) as u32,
+            stack_top: stack_top.as_u64(),
+            entry: entry as usize as u64,
+            cpu_index: cpu_index as u64,
+            gdt: [0, CODE_SEGMENT, DATA_SEGMENT],
+        }
+    }
+
+    /// Writes the parameters into the trampoline copied to the page.
+    ///
+    /// # Safety
+    /// The trampoline has to be installed in the page, in physical memory mapped at the offset.
+    pub(super) unsafe fn write(self, page: PhysAddr, physical_memory_offset: u64) {
+        let address = physical_memory_offset + page.as_u64() + parameters_offset();
+        unsafe { core::ptr::write_volatile(address as *mut TrampolineParameters, self) };
+    }
+}
@@ -0,0 +1,250 @@
+use alloc::sync::Arc;
+use internal_utils::{clocks::get_timer_ticks, log, logln};
+use rost_user::{MemoryProtection, SysCallError, SysCallNumber};
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+use crate::power::{power_off, reboot};
+use crate::processes::process::Process;
+use crate::processes::thread::{Thread, ThreadState};
+use crate::processes::user_memory::{UserMemoryError, copy_to_user, read_user_bytes};
+use crate::processes::wait::{
+    block_until_process_exit, stop_waiting_for_process_exit, try_reap_child,
+};
+use crate::processes::{
+    exit_thread, fork_process, release_exited_thread, run_processes, yield_running_thread,
+};
+use crate::smp::{CpuMask, current_cpu_index};
+
+use super::system_call::{SysCallHandlerFunc, register_syscall};
+
+/// The longest text a single `Log` system call can write.
+const MAX_LOG_LENGTH: u64 = 4096;
+
+/// The length of the `syscall` instruction, for restarting a system call.
+const SYSCALL_INSTRUCTION_LENGTH: u64 = 2;
+
+/// Registers the handlers of the core system call ABI.
+pub(super) fn register_core_syscalls() {
+    let handlers: [(SysCallNumber, SysCallHandlerFunc); 14] = [
+        (SysCallNumber::Exit, exit_syscall),
+        (SysCallNumber::Yield, yield_syscall),
+        (SysCallNumber::Sleep, sleep_syscall),
+        (SysCallNumber::Log, log_syscall),
+        (SysCallNumber::GetProcessId, get_process_id_syscall),
+        (SysCallNumber::GetThreadId, get_thread_id_syscall),
+        (SysCallNumber::Wait, wait_syscall),
+        (SysCallNumber::Fork, fork_syscall),
+        (SysCallNumber::Map, map_syscall),
+        (SysCallNumber::Unmap, unmap_syscall),
+        (SysCallNumber::Protect, protect_syscall),
+        (SysCallNumber::PowerOff, power_off_syscall),
+        (SysCallNumber::Reboot, reboot_syscall),
+        (SysCallNumber::SetAffinity, set_affinity_syscall),
+    ];
+    for (number, handler) in handlers {
+        register_syscall(number as u16, handler);
+    }
+}
+
+/// Returns the third argument of the system call, which the handlers only get in the saved RDX.
+fn get_third_argument(thread: &Arc<Mutex<Thread>>) -> u64 {
+    thread.lock().registers_state.rdx
+}
+
+/// Returns the address argument, if it's a valid virtual address.
+fn get_address_argument(address: u64) -> Result<VirtAddr, SysCallError> {
+    VirtAddr::try_new(address).map_err(|_| SysCallError::BadAddress)
+}
+
+/// Sets the value the thread will see in RAX once it's dispatched again.
+fn set_resume_result(thread: &Arc<Mutex<Thread>>, result: u64) {
+    thread.lock().registers_state.rax = result;
+}
+
+fn exit_syscall(code: u64, _arg2: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    {
+        let borrowed_thread = thread.lock();
+        logln!(
+            "Thread {} of process {} exited with code {}",
+            borrowed_thread.id,
+            borrowed_thread.process.lock().id,
+            code
+        );
+    }
+    exit_thread(thread.clone(), code);
+    // We're running on the thread's kernel stack, so it has to outlive this handler
+    release_exited_thread(thread);
+    run_processes();
+}
+
+fn yield_syscall(_arg1: u64, _arg2: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    set_resume_result(&thread, 0);
+    drop(thread);
+    yield_running_thread();
+    run_processes();
+}
+
+fn sleep_syscall(ticks: u64, _arg2: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    set_resume_result(&thread, 0);
+    let wake_up_tick = get_timer_ticks().saturating_add(ticks);
+    Thread::change_state(thread, ThreadState::Sleeping(wake_up_tick));
+    run_processes();
+}
+
+fn log_syscall(address: u64, length: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    if length > MAX_LOG_LENGTH {
+        return SysCallError::InvalidArgument.into_result();
+    }
+    let process = thread.lock().process.clone();
+    let bytes = match read_user_bytes(&process, address, length as usize) {
+        Ok(bytes) => bytes,
+        Err(error) => return SysCallError::from(error).into_result(),
+    };
+    match core::str::from_utf8(&bytes) {
+        Ok(text) => {
+            log!("{}", text);
+            length
+        }
+        Err(_) => SysCallError::InvalidArgument.into_result(),
+    }
+}
+
+fn get_process_id_syscall(_arg1: u64, _arg2: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    thread.lock().process.lock().id
+}
+
+fn get_thread_id_syscall(_arg1: u64, _arg2: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    thread.lock().id
+}
+
+/// Writes the exit code of a reaped child to the address given to `Wait`, unless it's null.
+fn write_exit_code(
+    process: &Arc<Mutex<Process>>,
+    exit_code_address: u64,
+    exit_code: u64,
+) -> Result<(), UserMemoryError> {
+    if exit_code_address == 0 {
+        return Ok(());
+    }
+    copy_to_user(process, exit_code_address, &exit_code.to_ne_bytes())
+}
+
+fn wait_syscall(child_id: u64, exit_code_address: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    let process = thread.lock().process.clone();
+    let parent_id = process.lock().id;
+    // Checking the address before reaping, so the exit code can't get lost
+    if let Err(error) = write_exit_code(&process, exit_code_address, 0) {
+        return SysCallError::from(error).into_result();
+    }
+
+    let child_id = (child_id != 0).then_some(child_id);
+    // Blocking before checking, so a child terminating on another processor in between wakes us up
+    block_until_process_exit(thread.clone());
+    let result = try_reap_child(parent_id, child_id);
+    if !matches!(result, Ok(None)) {
+        stop_waiting_for_process_exit(&thread);
+    }
+    match result {
+        Ok(Some((id, exit_code))) => {
+            match write_exit_code(&process, exit_code_address, exit_code) {
+                Ok(()) => id,
+                Err(error) => SysCallError::from(error).into_result(),
+            }
+        }
+        Ok(None) => {
+            // The system call starts over once any process terminates
+            thread.lock().registers_state.rip -= SYSCALL_INSTRUCTION_LENGTH;
+            // run_processes doesn't return, so nothing may keep the thread alive past its exit
+            drop(process);
+            drop(thread);
+            run_processes();
+        }
+        Err(error) => SysCallError::from(error).into_result(),
+    }
+}
+
+fn fork_syscall(_arg1: u64, _arg2: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    match fork_process(&thread) {
+        Ok(child) => child.lock().id,
+        Err(error) => SysCallError::from(error).into_result(),
+    }
+}
+
+fn map_syscall(address: u64, length: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    let Some(protection) = MemoryProtection::from_bits(get_third_argument(&thread)) else {
+        return SysCallError::InvalidArgument.into_result();
+    };
+    let address = match address {
+        0 => None,
+        address => match get_address_argument(address) {
+            Ok(address) => Some(address),
+            Err(error) => return error.into_result(),
+        },
+    };
+    let process = thread.lock().process.clone();
+    let result = process.lock().map_anonymous(address, length, protection);
+    match result {
+        Ok(address) => address.as_u64(),
+        Err(error) => SysCallError::from(error).into_result(),
+    }
+}
+
+fn unmap_syscall(address: u64, length: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    let address = match get_address_argument(address) {
+        Ok(address) => address,
+        Err(error) => return error.into_result(),
+    };
+    let process = thread.lock().process.clone();
+    let result = process.lock().unmap(address, length);
+    match result {
+        Ok(flush) => {
+            flush.shoot_down();
+            0
+        }
+        Err(error) => SysCallError::from(error).into_result(),
+    }
+}
+
+fn protect_syscall(address: u64, length: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    let Some(protection) = MemoryProtection::from_bits(get_third_argument(&thread)) else {
+        return SysCallError::InvalidArgument.into_result();
+    };
+    let address = match get_address_argument(address) {
+        Ok(address) => address,
+        Err(error) => return error.into_result(),
+    };
+    let process = thread.lock().process.clone();
+    let result = process.lock().protect(address, length, protection);
+    match result {
+        Ok(flush) => {
+            flush.shoot_down();
+            0
+        }
+        Err(error) => SysCallError::from(error).into_result(),
+    }
+}
+
+fn power_off_syscall(_arg1: u64, _arg2: u64, _thread: Arc<Mutex<Thread>>) -> u64 {
+    SysCallError::from(power_off()).into_result()
+}
+
+fn reboot_syscall(_arg1: u64, _arg2: u64, _thread: Arc<Mutex<Thread>>) -> u64 {
+    SysCallError::from(reboot()).into_result()
+}
+
+fn set_affinity_syscall(mask: u64, _arg2: u64, thread: Arc<Mutex<Thread>>) -> u64 {
+    let affinity = CpuMask::from_bits(mask);
+    if affinity.running_cpus().next().is_none() {
+        return SysCallError::InvalidArgument.into_result();
+    }
+    thread.lock().affinity = affinity;
+    if affinity.contains(current_cpu_index()) {
+        return 0;
+    }
+    // The scheduler moves the thread to an allowed processor once it switched away from it
+    set_resume_result(&thread, 0);
+    drop(thread);
+    yield_running_thread();
+    run_processes();
+}
@@ -1,2 +1,3 @@
+mod handlers;
 mod system_call;
-pub use system_call::setup_syscalls;
+pub use system_call::{load_syscall_registers, set_syscall_stack, setup_syscalls};
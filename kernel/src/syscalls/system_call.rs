@@ -1,32 +1,70 @@
+use core::{mem::offset_of, sync::atomic::Ordering};
+
 use alloc::sync::Arc;
+use internal_utils::kernel_information::heap_tracking::{AllocationTag, with_allocation_tag};
 use internal_utils::logln;
 use lazy_static::lazy_static;
+use rost_user::SysCallError;
 use spin::Mutex;
 use x86_64::VirtAddr;
 
+use crate::processes::registers_state::Flags;
 use crate::processes::thread::Thread;
+use crate::processes::{RegistersState, running_thread};
+use crate::push_registers_state;
+use crate::smp::{PerCpu, current_cpu};
 
 use crate::interrupts::gdt::GDT;
+
+use super::handlers::register_core_syscalls;
 use core::arch::naked_asm;
 
-pub type SysCallHandlerFunc = extern "C" fn(u64, u64, Arc<Mutex<Thread>>) -> u64;
+pub type SysCallHandlerFunc = fn(u64, u64, Arc<Mutex<Thread>>) -> u64;
+
+/// The number of entries in the system call table.
+const SYSCALL_COUNT: usize = 1024;
 
-/// A system call handler that panics.
-extern "C" fn fail_syscall(_arg1: u64, _arg2: u64, calling_thread: Arc<Mutex<Thread>>) -> u64 {
-    panic!("NO SYSCALL DEFINED");
+/// The value returned to the user for a system call that has no handler.
+pub const UNDEFINED_SYSCALL_RESULT: u64 = SysCallError::UndefinedSysCall.into_result();
+
+/// A system call handler for the numbers that have nothing registered.
+fn fail_syscall(_arg1: u64, _arg2: u64, calling_thread: Arc<Mutex<Thread>>) -> u64 {
+    let thread = calling_thread.lock();
+    logln!(
+        "Undefined system call from thread {} of process {}",
+        thread.id,
+        thread.process.lock().id
+    );
+    UNDEFINED_SYSCALL_RESULT
 }
 
 lazy_static! {
-    static ref SYSCALLS: Mutex<[SysCallHandlerFunc; 1024]> = Mutex::new([fail_syscall; 1024]);
+    static ref SYSCALLS: Mutex<[SysCallHandlerFunc; SYSCALL_COUNT]> =
+        Mutex::new([fail_syscall; SYSCALL_COUNT]);
 }
 
-/// Sets up the LSTAR, FSTAR and STAR model-specific registers so it's possible to use `syscall`.
+/// Sets the stack the next `syscall` on the running processor will run the kernel on.
+pub fn set_syscall_stack(stack_top: VirtAddr) {
+    current_cpu()
+        .syscall_kernel_stack
+        .store(stack_top.as_u64(), Ordering::Relaxed);
+}
+
+/// Sets up the model-specific registers of the bootstrap processor and registers the system calls.
 pub fn setup_syscalls() {
+    logln!("[   ---{:^15}---   ]", "SYSCALLS");
+    logln!("Loading LSTAR, FSTAR and STAR");
+    load_syscall_registers();
+    register_core_syscalls();
+    logln!("Syscalls active");
+}
+
+/// Sets up the LSTAR, FSTAR, STAR and kernel GS base model-specific registers of the running processor
+/// so it's possible to use `syscall`.
+pub fn load_syscall_registers() {
     use x86_64::registers::model_specific;
     use x86_64::registers::model_specific::{Efer, EferFlags};
     use x86_64::registers::rflags::RFlags;
-    logln!("[   ---{:^15}---   ]", "SYSCALLS");
-    logln!("Loading LSTAR, FSTAR and STAR");
     // LSTAR stores the address of the `syscall` handler.
     model_specific::LStar::write(VirtAddr::from_ptr(_syscall as *const ()));
     // FSTAR stores which bits of the flag register are cleared by `syscall`.
@@ -39,6 +77,8 @@ pub fn setup_syscalls() {
         GDT.1.kernel_data_selector,
     )
     .unwrap();
+    // The kernel GS base points to the processor's data, which `_syscall` swaps in to find its stack.
+    model_specific::KernelGsBase::write(VirtAddr::from_ptr(current_cpu()));
     let new_efer_flags = {
         let mut flags = Efer::read();
         flags.set(EferFlags::SYSTEM_CALL_EXTENSIONS, true);
@@ -47,10 +87,8 @@ pub fn setup_syscalls() {
     unsafe {
         Efer::write(new_efer_flags);
     }
-    logln!("Syscalls active");
 }
 
-#[allow(dead_code)]
 /// Registers a system call with a handler.
 pub fn register_syscall(syscall_number: u16, handler: SysCallHandlerFunc) {
     SYSCALLS.lock()[syscall_number as usize] = handler;
@@ -61,85 +99,82 @@ pub fn register_syscall(syscall_number: u16, handler: SysCallHandlerFunc) {
 /// - the instruction pointer is stored in RCX
 /// - the flags are stored in R11
 /// - the stack pointer is still targeting the user mode stack
+/// - the interrupts are disabled (SFMASK clears every flag)
 ///
-/// To properly handle this, we need to:
-/// 1. save the user mode stack pointer
-/// 2. set the syscall stack pointer
-/// 3. save all the registers we need to preserve on the stack
-/// 4. do our thing with the values we got from the user
-/// 5. restore the registers from the stack
+/// To properly handle this, we:
+/// 1. swap in the kernel GS base, pointing to the processor's data, and save the user mode stack pointer there
+/// 2. switch to the kernel stack of the running thread
+/// 3. save the user registers on the stack as a `RegistersState`, and swap the GS base back
+/// 4. call the registered handler, which writes the result into RAX of that state
+/// 5. restore the registers from the (possibly modified) state
 /// 6. restore the user mode stack pointer
-/// 7. iretq
+/// 7. sysretq
+///
+/// The system call number is passed in RAX and the arguments in RDI and RSI.
+/// System calls taking a third argument read it from RDX of the saved state.
+/// Only ring 3 threads may use `syscall`, as `sysretq` always returns to ring 3.
 #[unsafe(no_mangle)]
 #[unsafe(naked)]
 unsafe extern "C" fn _syscall() -> ! {
     naked_asm!(
-        "" //    "cli",
-           //    "mov r10, rsp",
-           //    "mov rsp, 0x007F80014000", // User stack saved in R10, start of kernel stack loaded
-           //    push_all!(),
-           //    "mov r9, rsp",
-           //    "push r9",
-           //    "  call handler", // Return value is in RAX
-           //    "  push rax",
-           //    "    call get_code_selector",
-           //    "    push rax",
-           //    "      call get_data_selector",
-           //    "    pop rbx",
-           //    "    mov rcx, rax", // struct is in RBX+RCX now
-           //    "  pop rax",        // We get the syscall value back
-           //    "pop r9",           // We get the register state value back
-           //    // Preparing iretq
-           //    "push rcx",           // data selector
-           //    "push [r9 + 40]",     // process stack pointer
-           //    "mov r11, [r9 + 32]", // rflags
-           //    "or r11, 0x200",
-           //    "and r11, 0xffffffffffffbfff",
-           //    "push r11",       // rflags
-           //    "push rbx",       // code selector
-           //    "push [r9 + 96]", // instruction address to return to
-           //    "push rax",       // We want to keep the RAX
-           //    mov_all!(),
-           //    "pop rax",
-           //    "iretq"
+        "swapgs",
+        "mov gs:[{user_stack}], rsp",
+        "mov rsp, gs:[{kernel_stack}]",
+        // Building a RegistersState on the kernel stack
+        "push r11",                           // rflags
+        "push 0",                             // reserved
+        "push rcx",                           // instruction address to return to
+        push_registers_state!(),              // RAX-R15
+        "push qword ptr gs:[{user_stack}]",   // user stack pointer
+        // The handler might switch to another thread without coming back, so GS is restored right away
+        "swapgs",
+        "mov rdi, rsp",
+        // 19 values pushed - we need to realign the stack to 16 bytes for the call
+        "sub rsp, 8",
+        "call {handler}",
+        "add rsp, 8",
+        // Restoring the registers, with RCX and R11 holding what sysretq needs
+        "mov r9, rsp",
+        "mov rax, [r9 + 1*8]",
+        "mov rbx, [r9 + 2*8]",
+        "mov rdx, [r9 + 4*8]",
+        "mov rbp, [r9 + 5*8]",
+        "mov rsi, [r9 + 6*8]",
+        "mov rdi, [r9 + 7*8]",
+        "mov r8, [r9 + 8*8]",
+        "mov r10, [r9 + 10*8]",
+        "mov r12, [r9 + 12*8]",
+        "mov r13, [r9 + 13*8]",
+        "mov r14, [r9 + 14*8]",
+        "mov r15, [r9 + 15*8]",
+        "mov rcx, [r9 + 16*8]",
+        "mov r11, [r9 + 18*8]",
+        "mov rsp, [r9]",
+        "mov r9, [r9 + 9*8]",
+        "sysretq",
+        user_stack = const offset_of!(PerCpu, syscall_user_stack),
+        kernel_stack = const offset_of!(PerCpu, syscall_kernel_stack),
+        handler = sym syscall_handler,
     );
 }
 
-//#[unsafe(no_mangle)]
-//extern "C" fn handler(name: u64, arg1: u64, arg2: u64) -> u64 {
-//    // This block executes after saving the user state and before returning back
-//    with_kernel_memory(|| {
-//        let thread = SCHEDULER.lock().unwrap().running_thread.clone().unwrap();
-//        SYSCALLS.lock()[name as u16 as usize](arg1, arg2, thread)
-//    })
-//}
-//
-//// TODO Try to combine both of these functions to make it faster.
-//// We should be able to return a C-style struct with two u64s and manage it through ASM.
-//#[unsafe(no_mangle)]
-//extern "C" fn get_code_selector() -> u64 {
-//    with_kernel_memory(|| {
-//        let thread = SCHEDULER.lock().unwrap().running_thread.clone().unwrap();
-//        let thread: spin::MutexGuard<'_, Thread> = thread.as_ref().lock();
-//        let process = thread.process.as_ref().lock();
-//        if process.kernel_process {
-//            (GDT.1.kernel_code_selector.index() * 8) as u64
-//        } else {
-//            ((GDT.1.user_code_selector.index() * 8) | 3) as u64
-//        }
-//    })
-//}
-//
-//#[unsafe(no_mangle)]
-//extern "C" fn get_data_selector() -> u64 {
-//    with_kernel_memory(|| {
-//        let thread = SCHEDULER.lock().unwrap().running_thread.clone().unwrap();
-//        let thread = thread.as_ref().lock();
-//        let process = thread.process.as_ref().lock();
-//        if process.kernel_process {
-//            (GDT.1.kernel_data_selector.index() * 8) as u64
-//        } else {
-//            ((GDT.1.user_data_selector.index() * 8) | 3) as u64
-//        }
-//    })
-//}
+/// Dispatches a system call to its registered handler.
+///
+/// This executes after saving the user state and before returning back.
+/// The state is saved into the running thread first, so handlers that switch to another thread
+/// can resume this one later.
+extern "sysv64" fn syscall_handler(state: *mut RegistersState) {
+    let state = unsafe { &mut *state };
+    let thread = running_thread().expect("A system call needs a running thread");
+    thread.lock().registers_state = state.clone();
+
+    let handler = SYSCALLS
+        .lock()
+        .get(state.rax as usize)
+        .copied()
+        .unwrap_or(fail_syscall);
+    state.rax = with_allocation_tag(AllocationTag::Syscalls, || {
+        handler(state.rdi, state.rsi, thread)
+    });
+    state.rflags = (state.rflags | Flags::IF) & Flags::NT.complement();
+}
@@ -0,0 +1,121 @@
+use core::fmt::Display;
+
+use bitflags::bitflags;
+
+/// The numbers of the system calls, passed in RAX.
+///
+/// The arguments are passed in RDI, RSI and RDX, and the result is returned in RAX.
+/// These values are part of the ABI and must never change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum SysCallNumber {
+    /// Terminates the calling thread, with the exit code as the first argument.
+    Exit = 0,
+    /// Lets the scheduler run another thread.
+    Yield = 1,
+    /// Sleeps for the number of timer ticks given as the first argument.
+    Sleep = 2,
+    /// Writes the UTF-8 text given as a pointer and a length to the kernel log.
+    Log = 3,
+    /// Returns the ID of the calling process.
+    GetProcessId = 4,
+    /// Returns the ID of the calling thread.
+    GetThreadId = 5,
+    /// Waits for a child process to terminate and reaps it, returning its ID.
+    ///
+    /// The first argument is the ID of the child, or 0 for any child.
+    /// The second argument is the address the exit code is written to, or 0 to ignore it.
+    Wait = 6,
+    /// Clones the calling process, returning the ID of the child in the parent and 0 in the child.
+    Fork = 7,
+    /// Maps zeroed memory into the calling process, returning its address.
+    ///
+    /// The arguments are the address (or 0 to let the kernel choose one), the length and the `MemoryProtection`.
+    Map = 8,
+    /// Unmaps the memory in the range given as an address and a length.
+    Unmap = 9,
+    /// Changes the `MemoryProtection` of the memory in the range given as an address and a length.
+    Protect = 10,
+    /// Powers the system off, only returning if it couldn't.
+    PowerOff = 11,
+    /// Reboots the system, only returning if it couldn't.
+    Reboot = 12,
+    /// Restricts the calling thread to the processors of the mask given as the first argument,
+    /// the bit `n` standing for the CPU index `n`.
+    ///
+    /// The mask has to contain a running processor.
+    SetAffinity = 13,
+}
+
+bitflags! {
+    /// The accesses a process allows to its memory.
+    ///
+    /// Every mapping has to be readable, as x86_64 pages can't deny reads.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MemoryProtection: u64 {
+        const READ = 1;
+        const WRITE = 1 << 1;
+        const EXECUTE = 1 << 2;
+    }
+}
+
+/// The exit codes of processes the kernel terminated because of a CPU exception start here,
+/// with the vector of the exception added to it.
+pub const EXCEPTION_EXIT_CODE_BASE: u64 = 0x100;
+
+/// The errors a system call can return.
+///
+/// They are returned in RAX as their negated value, so any result in the last 4096 values of `u64` is an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum SysCallError {
+    /// There is no system call with the requested number
+    UndefinedSysCall = 1,
+    /// An argument has a value the system call does not accept
+    InvalidArgument = 2,
+    /// A pointer argument does not point to memory the process can access
+    BadAddress = 3,
+    /// The process has no child process to wait for
+    NoChildren = 4,
+    /// There is not enough memory to complete the system call
+    OutOfMemory = 5,
+    /// The system does not support the operation
+    NotSupported = 6,
+    /// Unknown error
+    Unknown = 4095,
+}
+
+impl SysCallError {
+    /// Encodes the error as a system call result.
+    pub const fn into_result(self) -> u64 {
+        (self as u64).wrapping_neg()
+    }
+
+    /// Decodes a system call result, which is either a value or an error.
+    pub const fn from_result(result: u64) -> Result<u64, SysCallError> {
+        match result.wrapping_neg() {
+            1 => Err(SysCallError::UndefinedSysCall),
+            2 => Err(SysCallError::InvalidArgument),
+            3 => Err(SysCallError::BadAddress),
+            4 => Err(SysCallError::NoChildren),
+            5 => Err(SysCallError::OutOfMemory),
+            6 => Err(SysCallError::NotSupported),
+            7..=4095 => Err(SysCallError::Unknown),
+            _ => Ok(result),
+        }
+    }
+}
+
+impl Display for SysCallError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SysCallError::UndefinedSysCall => write!(f, "Undefined system call"),
+            SysCallError::InvalidArgument => write!(f, "Invalid argument"),
+            SysCallError::BadAddress => write!(f, "Bad address"),
+            SysCallError::NoChildren => write!(f, "No child processes"),
+            SysCallError::OutOfMemory => write!(f, "Out of memory"),
+            SysCallError::NotSupported => write!(f, "Not supported"),
+            SysCallError::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+#![no_std]
+
+//! The userspace side of the rOSt system call ABI.
+//!
+//! Programs should use the wrappers in this crate instead of issuing `syscall` by hand.
+
+pub mod abi;
+mod raw;
+
+pub use abi::{MemoryProtection, SysCallError, SysCallNumber};
+use raw::{syscall0, syscall1, syscall2, syscall3};
+
+/// Terminates the calling thread. The process ends when its last thread exits.
+pub fn exit(code: u64) -> ! {
+    unsafe {
+        syscall1(SysCallNumber::Exit, code);
+    }
+    unreachable!("The exit system call never returns")
+}
+
+/// Gives the rest of the thread's time slice back to the scheduler.
+pub fn yield_now() {
+    unsafe {
+        syscall0(SysCallNumber::Yield);
+    }
+}
+
+/// Puts the calling thread to sleep for the given number of timer ticks.
+pub fn sleep(ticks: u64) {
+    unsafe {
+        syscall1(SysCallNumber::Sleep, ticks);
+    }
+}
+
+/// Writes the text to the kernel log, returning the number of bytes written.
+pub fn log(text: &str) -> Result<usize, SysCallError> {
+    let result = unsafe { syscall2(SysCallNumber::Log, text.as_ptr() as u64, text.len() as u64) };
+    SysCallError::from_result(result).map(|written| written as usize)
+}
+
+/// Returns the ID of the calling process.
+pub fn process_id() -> u64 {
+    unsafe { syscall0(SysCallNumber::GetProcessId) }
+}
+
+/// Returns the ID of the calling thread, unique inside its process.
+pub fn thread_id() -> u64 {
+    unsafe { syscall0(SysCallNumber::GetThreadId) }
+}
+
+/// Clones the calling process, with the memory of the child being a copy of the parent's.
+///
+/// Returns the ID of the child in the parent, and 0 in the child.
+pub fn fork() -> Result<u64, SysCallError> {
+    SysCallError::from_result(unsafe { syscall0(SysCallNumber::Fork) })
+}
+
+/// Waits for a child process to terminate, or any child if `child` is `None`.
+///
+/// Returns the ID and the exit code of the child, which can't be waited for again.
+pub fn wait(child: Option<u64>) -> Result<(u64, u64), SysCallError> {
+    let mut exit_code = 0u64;
+    let result = unsafe {
+        syscall2(
+            SysCallNumber::Wait,
+            child.unwrap_or(0),
+            &mut exit_code as *mut u64 as u64,
+        )
+    };
+    SysCallError::from_result(result).map(|child| (child, exit_code))
+}
+
+/// Maps `length` bytes of zeroed memory, returning its address.
+///
+/// The memory is placed at `address` if it's given, which has to be page-aligned and free.
+///
+/// # Safety
+/// Mapping over memory the program uses is not allowed, but mapping at a fixed address can still
+/// break assumptions of the program about its address space.
+pub unsafe fn map(
+    address: Option<u64>,
+    length: u64,
+    protection: MemoryProtection,
+) -> Result<*mut u8, SysCallError> {
+    let result = unsafe {
+        syscall3(
+            SysCallNumber::Map,
+            address.unwrap_or(0),
+            length,
+            protection.bits(),
+        )
+    };
+    SysCallError::from_result(result).map(|address| address as *mut u8)
+}
+
+/// Unmaps the memory in the range, freeing it.
+///
+/// # Safety
+/// Nothing can use the memory after it has been unmapped.
+pub unsafe fn unmap(address: *mut u8, length: u64) -> Result<(), SysCallError> {
+    let result = unsafe { syscall2(SysCallNumber::Unmap, address as u64, length) };
+    SysCallError::from_result(result).map(|_| ())
+}
+
+/// Changes the allowed accesses to the memory in the range.
+///
+/// # Safety
+/// Nothing can access the memory in a way the new protection doesn't allow.
+pub unsafe fn protect(
+    address: *mut u8,
+    length: u64,
+    protection: MemoryProtection,
+) -> Result<(), SysCallError> {
+    let result = unsafe {
+        syscall3(
+            SysCallNumber::Protect,
+            address as u64,
+            length,
+            protection.bits(),
+        )
+    };
+    SysCallError::from_result(result).map(|_| ())
+}
+
+/// Powers the system off, returning the reason if it couldn't.
+pub fn power_off() -> SysCallError {
+    let result = unsafe { syscall0(SysCallNumber::PowerOff) };
+    SysCallError::from_result(result)
+        .err()
+        .unwrap_or(SysCallError::Unknown)
+}
+
+/// Reboots the system, returning the reason if it couldn't.
+pub fn reboot() -> SysCallError {
+    let result = unsafe { syscall0(SysCallNumber::Reboot) };
+    SysCallError::from_result(result)
+        .err()
+        .unwrap_or(SysCallError::Unknown)
+}
+
+/// Lets the calling thread only run on the processors of the mask, the bit `n` standing for the CPU index `n`.
+///
+/// If the thread runs on another processor, it moves to one of the mask before returning.
+pub fn set_affinity(mask: u64) -> Result<(), SysCallError> {
+    let result = unsafe { syscall1(SysCallNumber::SetAffinity, mask) };
+    SysCallError::from_result(result).map(|_| ())
+}
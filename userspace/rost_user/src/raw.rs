@@ -0,0 +1,67 @@
+use core::arch::asm;
+
+use crate::abi::SysCallNumber;
+
+// `syscall` overwrites RCX and R11 with the return address and the flags.
+
+pub(crate) unsafe fn syscall0(number: SysCallNumber) -> u64 {
+    let result: u64;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") number as u64 => result,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+    result
+}
+
+pub(crate) unsafe fn syscall1(number: SysCallNumber, arg1: u64) -> u64 {
+    let result: u64;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") number as u64 => result,
+            in("rdi") arg1,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+    result
+}
+
+pub(crate) unsafe fn syscall2(number: SysCallNumber, arg1: u64, arg2: u64) -> u64 {
+    let result: u64;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") number as u64 => result,
+            in("rdi") arg1,
+            in("rsi") arg2,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+    result
+}
+
+pub(crate) unsafe fn syscall3(number: SysCallNumber, arg1: u64, arg2: u64, arg3: u64) -> u64 {
+    let result: u64;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") number as u64 => result,
+            in("rdi") arg1,
+            in("rsi") arg2,
+            in("rdx") arg3,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+    result
+}